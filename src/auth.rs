@@ -1,2 +1,18 @@
-pub mod bcrypt;
+pub mod api_key;
+pub mod bootstrap;
+pub mod deletion;
+pub mod extractor;
 pub mod jwt;
+pub mod keys;
+pub mod oidc;
+pub mod one_time;
+pub mod password;
+pub mod password_reset;
+pub mod permissions;
+pub mod policy;
+pub mod refresh;
+pub mod revoke;
+pub mod session;
+pub mod throttle;
+pub mod totp;
+pub mod verification;
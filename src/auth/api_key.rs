@@ -0,0 +1,91 @@
+use chrono::{prelude::*, Duration};
+
+use crate::{
+    db::{
+        connect::DbState,
+        models::{ApiKey, Errors, User},
+    },
+    utils::random_id::{random_id, random_token},
+};
+
+use super::{jwt::Claims, permissions::Permission, refresh::hash_token};
+
+/// Every API key starts with this, which tells them apart from JWTs.
+pub const API_KEY_PREFIX: &str = "n4k_";
+
+/// Characters of a key kept in `ApiKey::prefix`, prefix included.
+const DISPLAYED_CHARS: usize = 12;
+
+/// How stale `last_used` may get before a request writes it again.
+const TOUCH_INTERVAL_SECS: i64 = 60;
+
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(API_KEY_PREFIX)
+}
+
+/// Creates a key for `user` limited to `scopes`. Returns the key itself,
+/// which is never stored and can only be shown this once.
+pub async fn create_api_key(
+    state: &DbState,
+    user: &User,
+    name: &str,
+    scopes: Vec<Permission>,
+) -> Result<(String, ApiKey), Errors> {
+    let key = format!("{}{}", API_KEY_PREFIX, random_token());
+
+    let api_key = ApiKey {
+        key_id: random_id(),
+        user_id: user.user_id.clone(),
+        name: name.to_string(),
+        prefix: key[..DISPLAYED_CHARS].to_string(),
+        key_hash: hash_token(&key),
+        scopes,
+        created_at: Utc::now(),
+        last_used: None,
+    };
+
+    state.api_keys.insert_api_key(&api_key).await?;
+
+    Ok((key, api_key))
+}
+
+/// Claims for a request authenticated with the API key `key`, `None` when
+/// the key or its owner doesn't exist anymore or the owner is disabled.
+/// `Claims::scopes` holds the key's scopes.
+pub async fn authenticate_api_key(state: &DbState, key: &str) -> Result<Option<Claims>, Errors> {
+    let api_key = match state.api_keys.find_api_key(&hash_token(key)).await? {
+        Some(res) => res,
+        None => return Ok(None),
+    };
+
+    let user = match state.users.find_user_by_id(&api_key.user_id).await? {
+        Some(user) if !user.disabled => user,
+        _ => return Ok(None),
+    };
+
+    let now = Utc::now();
+
+    let stale = match api_key.last_used {
+        Some(last_used) => now - last_used > Duration::try_seconds(TOUCH_INTERVAL_SECS).unwrap(),
+        None => true,
+    };
+
+    if stale {
+        state.api_keys.touch_api_key(&api_key.key_id, now).await?;
+    }
+
+    // API keys have no session and don't expire, they last until revoked.
+    Ok(Some(Claims {
+        username: user.username,
+        userid: user.user_id,
+        email: user.email,
+        email_verified: user.email_verified,
+        role: user.role,
+        jti: api_key.key_id,
+        sid: String::new(),
+        scopes: Some(api_key.scopes),
+        iat: api_key.created_at.timestamp(),
+        iat_ms: api_key.created_at.timestamp_millis(),
+        exp: 0,
+    }))
+}
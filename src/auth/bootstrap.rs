@@ -0,0 +1,73 @@
+use std::env;
+
+use crate::{
+    db::{
+        connect::DbState,
+        models::{Errors, Role, User, UserOptions},
+    },
+    utils::random_id::random_id,
+};
+
+use super::{
+    password::hash_password,
+    policy::{normalize_username, username_key},
+};
+
+/// Makes sure the account named by `ADMIN_USERNAME` exists and is an enabled
+/// admin. An existing account is promoted, otherwise one is created with
+/// `ADMIN_PASSWORD`. Does nothing when `ADMIN_USERNAME` is not set.
+pub async fn bootstrap_admin(state: &DbState) {
+    let username = match env::var("ADMIN_USERNAME") {
+        Ok(res) => res,
+        Err(_) => return,
+    };
+
+    match promote_or_create(state, &username).await {
+        Ok(msg) => println!("{}", msg),
+        Err(e) => panic!("Error: admin bootstrap failed: {:?}", e),
+    }
+}
+
+async fn promote_or_create(state: &DbState, username: &str) -> Result<String, Errors> {
+    if let Some(mut user) = state.users.find_user_by_username(username).await? {
+        if user.role == Role::Admin && !user.disabled {
+            return Ok(format!("Admin {:?} ready", username));
+        }
+
+        user.role = Role::Admin;
+        user.disabled = false;
+        state.users.update_user(&user).await?;
+
+        return Ok(format!("User {:?} promoted to admin", username));
+    }
+
+    let password = match env::var("ADMIN_PASSWORD") {
+        Ok(res) => res,
+        Err(_) => panic!(
+            "Error: the admin {:?} does not exist, set ADMIN_PASSWORD to create it.",
+            username
+        ),
+    };
+
+    let encoded_pass = match hash_password(&password).await {
+        Ok(pass) => pass,
+        Err(e) => panic!("Error: {:?}", e),
+    };
+
+    let user = User {
+        user_id: random_id(),
+        username: normalize_username(username),
+        username_key: username_key(username),
+        password: encoded_pass,
+        email: None,
+        ip: None,
+        role: Role::Admin,
+        disabled: false,
+        ..Default::default()
+    };
+
+    state.users.insert_user(&user).await?;
+    UserOptions::create(state, user.user_id).await?;
+
+    Ok(format!("Admin {:?} created", username))
+}
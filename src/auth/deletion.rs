@@ -0,0 +1,91 @@
+use std::env;
+
+use chrono::{prelude::*, Duration};
+use once_cell::sync::Lazy;
+
+use crate::db::{
+    connect::DbState,
+    models::{Errors, TokenPurpose, User},
+};
+
+use super::{revoke::revoke_all_sessions, throttle::clear_login_failures};
+
+/// Seconds between a user deleting their account and its data being purged,
+/// `ACCOUNT_DELETION_GRACE_SECS` (default 0, purged right away). Until then
+/// the deletion can be cancelled.
+pub static DELETION_GRACE_SECS: Lazy<i64> =
+    Lazy::new(|| match env::var("ACCOUNT_DELETION_GRACE_SECS") {
+        Ok(res) => match res.parse() {
+            Ok(secs) if secs >= 0 => secs,
+            _ => panic!("Error: ACCOUNT_DELETION_GRACE_SECS must be a number of seconds"),
+        },
+        Err(_) => 0,
+    });
+
+/// Removes `user` and everything stored for them. The user goes last, so a
+/// purge that fails halfway can simply be run again.
+pub async fn purge_user(state: &DbState, user: &User) -> Result<(), Errors> {
+    revoke_all_sessions(state, &user.user_id).await?;
+
+    state
+        .tokens
+        .delete_user_refresh_tokens(&user.user_id)
+        .await?;
+
+    state.notes.delete_all_notes(&user.user_id).await?;
+
+    state.notebooks.delete_user_notebooks(&user.user_id).await?;
+
+    state.options.delete_options(&user.user_id).await?;
+
+    state.api_keys.delete_user_api_keys(&user.user_id).await?;
+
+    for purpose in [
+        TokenPurpose::PasswordReset,
+        TokenPurpose::EmailVerification,
+        TokenPurpose::LoginChallenge,
+    ] {
+        state
+            .tokens
+            .delete_user_one_time_tokens(&user.user_id, purpose)
+            .await?;
+    }
+
+    clear_login_failures(state, &user.username).await?;
+
+    state.users.delete_user(&user.user_id).await?;
+
+    Ok(())
+}
+
+/// Deletes `user` once the grace period is over. Returns when that will be,
+/// `None` when the account was purged right away.
+pub async fn schedule_deletion(
+    state: &DbState,
+    user: &mut User,
+) -> Result<Option<DateTime<Utc>>, Errors> {
+    if *DELETION_GRACE_SECS == 0 {
+        purge_user(state, user).await?;
+
+        return Ok(None);
+    }
+
+    let due = Utc::now() + Duration::try_seconds(*DELETION_GRACE_SECS).unwrap();
+
+    user.deletion_scheduled_at = Some(due);
+    state.users.update_user(user).await?;
+
+    Ok(Some(due))
+}
+
+/// Purges every account whose grace period is over and returns how many.
+pub async fn purge_due_accounts(state: &DbState) -> Result<u64, Errors> {
+    let mut purged = 0;
+
+    for user in state.users.list_users_due_for_deletion(Utc::now()).await? {
+        purge_user(state, &user).await?;
+        purged += 1;
+    }
+
+    Ok(purged)
+}
@@ -0,0 +1,213 @@
+use std::{convert::Infallible, env, net::SocketAddr, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::StatusCode;
+use once_cell::sync::Lazy;
+use serde_json::json;
+
+use crate::db::connect::DbState;
+
+use super::{
+    api_key::{authenticate_api_key, is_api_key},
+    jwt::{compare_jwt, Claims, JwtError},
+};
+
+const REALM: &str = "note4keep";
+
+/// Set `TRUST_PROXY=true` when running behind a reverse proxy, so the client
+/// address is read from `X-Forwarded-For` instead of the socket.
+static TRUST_PROXY: Lazy<bool> = Lazy::new(|| match env::var("TRUST_PROXY") {
+    Ok(res) => matches!(res.to_lowercase().as_str(), "1" | "true" | "yes"),
+    Err(_) => false,
+});
+
+/// Validated claims of the bearer token sent with the request.
+///
+/// Rejects with 401 and a `WWW-Authenticate` challenge when the token is
+/// missing, malformed, expired, revoked or badly signed, and with 403 for
+/// API keys outside of `require_permission` routes.
+#[derive(Debug, Clone)]
+pub struct AuthUser(pub Claims);
+
+/// Where a request comes from, recorded on the sessions it starts.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    MissingToken,
+    InvalidToken,
+    Forbidden,
+    Internal,
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let (status, challenge, msg) = match self {
+            AuthError::Internal => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            AuthError::MissingToken => (
+                StatusCode::UNAUTHORIZED,
+                format!("Bearer realm=\"{}\"", REALM),
+                "Missing bearer token",
+            ),
+            AuthError::InvalidToken => (
+                StatusCode::UNAUTHORIZED,
+                format!("Bearer realm=\"{}\", error=\"invalid_token\"", REALM),
+                "Invalid or expired token",
+            ),
+            AuthError::Forbidden => (
+                StatusCode::FORBIDDEN,
+                format!("Bearer realm=\"{}\", error=\"insufficient_scope\"", REALM),
+                "Not allowed",
+            ),
+        };
+
+        let mut res = (status, Json(json!({ "response": msg }))).into_response();
+
+        if let Ok(value) = HeaderValue::from_str(&challenge) {
+            res.headers_mut().insert(header::WWW_AUTHENTICATE, value);
+        }
+
+        res
+    }
+}
+
+/// Pulls the token out of an `Authorization: Bearer <token>` header.
+pub fn bearer_token(headers: &HeaderMap) -> Result<String, AuthError> {
+    let authorization = match headers.get(header::AUTHORIZATION) {
+        Some(res) => res,
+        None => return Err(AuthError::MissingToken),
+    };
+
+    let authorization = match authorization.to_str() {
+        Ok(res) => res,
+        Err(_) => return Err(AuthError::InvalidToken),
+    };
+
+    match authorization.trim().split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => {
+            let token = token.trim();
+
+            if token.is_empty() {
+                return Err(AuthError::MissingToken);
+            }
+
+            Ok(token.to_string())
+        }
+        _ => Err(AuthError::MissingToken),
+    }
+}
+
+/// Validates the bearer token of a request, a JWT or an API key. The
+/// `DbState` comes from the request extensions, where the `Extension` layer
+/// in `main` puts it.
+pub async fn authenticate(
+    headers: &HeaderMap,
+    state: Option<&Arc<DbState>>,
+) -> Result<Claims, AuthError> {
+    let token = bearer_token(headers)?;
+
+    let state = match state {
+        Some(state) => state,
+        None => {
+            println!("Error: DbState missing from request extensions");
+            return Err(AuthError::Internal);
+        }
+    };
+
+    if is_api_key(&token) {
+        return match authenticate_api_key(state, &token).await {
+            Ok(Some(claims)) => Ok(claims),
+            Ok(None) => Err(AuthError::InvalidToken),
+            Err(e) => {
+                println!("Error: {:?}", e);
+                Err(AuthError::Internal)
+            }
+        };
+    }
+
+    match compare_jwt(state, &token).await {
+        Ok(res) => Ok(res.claims),
+        Err(JwtError::Store(e)) => {
+            println!("Error: {:?}", e);
+            Err(AuthError::Internal)
+        }
+        Err(e) => {
+            println!("Error: {:?}", e);
+            Err(AuthError::InvalidToken)
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // Routes behind `require_permission` already validated the token.
+        if let Some(claims) = parts.extensions.get::<Claims>() {
+            return Ok(AuthUser(claims.clone()));
+        }
+
+        let claims = authenticate(&parts.headers, parts.extensions.get::<Arc<DbState>>()).await?;
+
+        // API keys are only good for the routes their scopes open, not for
+        // account management.
+        if claims.scopes.is_some() {
+            return Err(AuthError::Forbidden);
+        }
+
+        parts.extensions.insert(claims.clone());
+
+        Ok(AuthUser(claims))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(256).collect());
+
+        let forwarded = match *TRUST_PROXY {
+            true => parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty()),
+            false => None,
+        };
+
+        let ip = match forwarded {
+            Some(res) => Some(res),
+            None => parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string()),
+        };
+
+        Ok(ClientInfo { ip, user_agent })
+    }
+}
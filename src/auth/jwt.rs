@@ -1,89 +1,142 @@
 use std::env;
 
-use chrono::{prelude::*, Duration, LocalResult};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use chrono::{prelude::*, Duration};
+use jsonwebtoken::{decode, decode_header, encode, Header, Validation};
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::{
+    db::{
+        connect::DbState,
+        models::{Errors, Role, User},
+    },
+    utils::random_id::random_id,
+};
+
+use super::{keys::KEY_RING, permissions::Permission, session::check_session};
+
+/// Lifetime of an access token in seconds, `ACCESS_TOKEN_TTL` (default 15
+/// minutes). Clients renew them through `/api/users/refresh`.
+pub static ACCESS_TOKEN_TTL: Lazy<i64> = Lazy::new(|| match env::var("ACCESS_TOKEN_TTL") {
+    Ok(res) => match res.parse() {
+        Ok(ttl) => ttl,
+        Err(_) => panic!("Error: ACCESS_TOKEN_TTL must be a number of seconds"),
+    },
+    Err(_) => 15 * 60,
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub username: String,
     pub userid: String,
     pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub role: Role,
+    /// Unique id of this token, used to revoke it before it expires.
+    #[serde(default)]
+    pub jti: String,
+    /// Login the token belongs to, shared with its refresh token family.
+    #[serde(default)]
+    pub sid: String,
+    /// Only set for requests made with an API key, which are limited to
+    /// these permissions on top of those of the role.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<Permission>>,
     pub iat: i64,
+    /// `iat` in milliseconds, revocations are that precise. Missing from
+    /// tokens issued before it was added, see `issued_at_ms`.
+    #[serde(default)]
+    pub iat_ms: i64,
     pub exp: i64,
 }
 
-fn add_to_date(y: i32, m: u32, _d: u32) -> LocalResult<DateTime<Utc>> {
-    let current = Utc::now();
-    let mut year = current.year();
-    let mut month = current.month();
-    let mut _day = current.day();
-
-    match y {
-        0 => {}
-        _ => year += y,
-    }
-
-    match m {
-        0 => {}
-        _ => {
-            let mut years_added = 0;
-            let mut munits = month + m;
-
-            loop {
-                if munits > 12 {
-                    munits = munits - 12;
-                    years_added += 1;
-                } else {
-                    break;
-                }
-            }
-
-            year += years_added;
-            month = munits;
+impl Claims {
+    /// When the token was issued, in unix milliseconds.
+    pub fn issued_at_ms(&self) -> i64 {
+        match self.iat_ms {
+            0 => self.iat * 1000,
+            iat_ms => iat_ms,
         }
     }
+}
 
-    Utc.with_ymd_and_hms(year, month, 5, 0, 0, 0)
+#[derive(Debug)]
+pub enum JwtError {
+    Invalid(jsonwebtoken::errors::Error),
+    /// Signed with a key that is not, or no longer, in the key ring.
+    UnknownKey,
+    Revoked,
+    Store(Errors),
 }
 
 pub async fn create_jwt(
-    user: String,
-    id: String,
-    email: Option<String>,
+    user: &User,
+    session_id: &str,
 ) -> Result<String, jsonwebtoken::errors::Error> {
-    let secret = env::var("SECRET").unwrap();
+    let now = Utc::now();
 
     let token_structure = Claims {
-        username: user,
-        userid: id,
-        email,
-        iat: Utc::now()
-            .checked_add_signed(Duration::try_seconds(60).unwrap())
-            .expect("valid timestamp")
-            .timestamp(),
-        exp: add_to_date(0, 3, 0).unwrap().timestamp(),
+        username: user.username.clone(),
+        userid: user.user_id.clone(),
+        email: user.email.clone(),
+        email_verified: user.email_verified,
+        role: user.role,
+        jti: random_id(),
+        sid: session_id.to_string(),
+        scopes: None,
+        iat: now.timestamp(),
+        iat_ms: now.timestamp_millis(),
+        exp: (now + Duration::try_seconds(*ACCESS_TOKEN_TTL).unwrap()).timestamp(),
     };
 
-    let token = encode(
-        &Header::default(),
-        &token_structure,
-        &EncodingKey::from_secret(&secret.as_ref()),
-    );
+    let key = KEY_RING.signing_key();
+
+    let mut header = Header::new(key.algorithm);
+    header.kid = key.kid.clone();
 
-    token
+    encode(&header, &token_structure, &key.encoding)
 }
 
+/// Checks the signature and expiry of `token`, that it was not revoked
+/// through a logout and that its session is still active.
 pub async fn compare_jwt(
-    token: &String,
-) -> Result<jsonwebtoken::TokenData<Claims>, jsonwebtoken::errors::Error> {
-    let secret = env::var("SECRET").unwrap();
+    state: &DbState,
+    token: &str,
+) -> Result<jsonwebtoken::TokenData<Claims>, JwtError> {
+    let header = match decode_header(token) {
+        Ok(res) => res,
+        Err(e) => return Err(JwtError::Invalid(e)),
+    };
+
+    // The key is picked by kid and brings its own algorithm, so a token
+    // can't choose how it gets verified.
+    let key = match KEY_RING.find(header.kid.as_deref()) {
+        Some(res) => res,
+        None => return Err(JwtError::UnknownKey),
+    };
+
+    let decoded = match decode::<Claims>(token, &key.decoding, &Validation::new(key.algorithm)) {
+        Ok(res) => res,
+        Err(e) => return Err(JwtError::Invalid(e)),
+    };
+
+    let claims = &decoded.claims;
 
-    let decoded_token = decode::<Claims>(
-        &token,
-        &DecodingKey::from_secret(&secret.as_ref()),
-        &Validation::default(),
-    );
+    match state
+        .tokens
+        .is_token_revoked(&claims.jti, &claims.userid, claims.issued_at_ms())
+        .await
+    {
+        Ok(false) => {}
+        Ok(true) => return Err(JwtError::Revoked),
+        Err(e) => return Err(JwtError::Store(e)),
+    }
 
-    decoded_token
+    match check_session(state, claims).await {
+        Ok(true) => Ok(decoded),
+        Ok(false) => Err(JwtError::Revoked),
+        Err(e) => Err(JwtError::Store(e)),
+    }
 }
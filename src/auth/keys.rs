@@ -0,0 +1,217 @@
+use std::{env, fs, path::Path};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey,
+};
+use once_cell::sync::Lazy;
+use ring::{
+    rsa::{KeyPair as RsaKeyPair, PublicKeyComponents},
+    signature::{Ed25519KeyPair, KeyPair},
+};
+
+/// Secret for HS256 tokens and the values derived from it, `SECRET`.
+pub static SECRET: Lazy<String> = Lazy::new(|| match env::var("SECRET") {
+    Ok(res) => res,
+    Err(_) => panic!("Error: SECRET is not set"),
+});
+
+/// A key access tokens are signed or verified with.
+pub struct SigningKey {
+    /// Sent as the `kid` header. HS256 tokens have none, like before keys
+    /// could be rotated.
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    pub encoding: EncodingKey,
+    pub decoding: DecodingKey,
+    /// Public half to publish, `None` for the HS256 secret.
+    pub jwk: Option<Jwk>,
+}
+
+/// Every key tokens may be signed with, loaded once at startup.
+///
+/// Without `JWT_KEYS_DIR` tokens are HS256 signed with `SECRET`. With it,
+/// every `<kid>.pem` in the directory is a private RSA (RS256) or Ed25519
+/// (EdDSA) key in PEM. New tokens are signed with `JWT_ACTIVE_KID`, by
+/// default the last kid in alphabetical order (so date-named keys work), and
+/// tokens signed with any other key of the directory still validate. To
+/// rotate, add the new key and make it active, and delete the old one once
+/// the tokens it signed have expired.
+pub struct KeyRing {
+    active: usize,
+    keys: Vec<SigningKey>,
+}
+
+pub static KEY_RING: Lazy<KeyRing> = Lazy::new(KeyRing::from_env);
+
+/// Loads the keys up front, so a broken key stops the server at startup.
+pub fn init_key_ring() {
+    let active = KEY_RING.signing_key();
+
+    println!(
+        "Signing access tokens with {:?} ({:?}), {} key(s) loaded",
+        active.kid.as_deref().unwrap_or("SECRET"),
+        active.algorithm,
+        KEY_RING.keys.len()
+    );
+}
+
+impl KeyRing {
+    fn from_env() -> KeyRing {
+        let dir = match env::var("JWT_KEYS_DIR") {
+            Ok(res) => res,
+            Err(_) => {
+                return KeyRing {
+                    active: 0,
+                    keys: vec![SigningKey {
+                        kid: None,
+                        algorithm: Algorithm::HS256,
+                        encoding: EncodingKey::from_secret(SECRET.as_bytes()),
+                        decoding: DecodingKey::from_secret(SECRET.as_bytes()),
+                        jwk: None,
+                    }],
+                }
+            }
+        };
+
+        let entries = match fs::read_dir(&dir) {
+            Ok(res) => res,
+            Err(e) => panic!("Error: can't read JWT_KEYS_DIR {:?}: {}", dir, e),
+        };
+
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "pem"))
+            .collect();
+
+        paths.sort();
+
+        let keys: Vec<SigningKey> = paths.iter().map(|path| load_key(path)).collect();
+
+        if keys.is_empty() {
+            panic!("Error: no .pem keys in JWT_KEYS_DIR {:?}", dir);
+        }
+
+        let active = match env::var("JWT_ACTIVE_KID") {
+            Ok(kid) => match keys.iter().position(|key| key.kid.as_ref() == Some(&kid)) {
+                Some(res) => res,
+                None => panic!("Error: JWT_ACTIVE_KID {:?} is not in JWT_KEYS_DIR", kid),
+            },
+            Err(_) => keys.len() - 1,
+        };
+
+        KeyRing { active, keys }
+    }
+
+    /// The key new tokens are signed with.
+    pub fn signing_key(&self) -> &SigningKey {
+        &self.keys[self.active]
+    }
+
+    /// The key a token with the `kid` header `kid` was signed with.
+    pub fn find(&self, kid: Option<&str>) -> Option<&SigningKey> {
+        self.keys.iter().find(|key| key.kid.as_deref() == kid)
+    }
+
+    /// Public keys for `/.well-known/jwks.json`, empty with HS256.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().filter_map(|key| key.jwk.clone()).collect(),
+        }
+    }
+}
+
+fn load_key(path: &Path) -> SigningKey {
+    let kid = match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some(res) => res.to_string(),
+        None => panic!("Error: bad key file name {:?}", path),
+    };
+
+    let text = match fs::read(path) {
+        Ok(res) => res,
+        Err(e) => panic!("Error: can't read key {:?}: {}", path, e),
+    };
+
+    let parsed = match pem::parse(&text) {
+        Ok(res) => res,
+        Err(e) => panic!("Error: key {:?} is not PEM: {}", path, e),
+    };
+
+    let der = parsed.contents();
+
+    // "RSA PRIVATE KEY" is PKCS#1, "PRIVATE KEY" PKCS#8 holding either kind.
+    let rsa = match parsed.tag() {
+        "RSA PRIVATE KEY" => RsaKeyPair::from_der(der).ok(),
+        "PRIVATE KEY" => RsaKeyPair::from_pkcs8(der).ok(),
+        other => panic!("Error: key {:?} is a {:?}, not a private key", path, other),
+    };
+
+    if let Some(key_pair) = rsa {
+        let public = PublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+
+        let encoding = match EncodingKey::from_rsa_pem(&text) {
+            Ok(res) => res,
+            Err(e) => panic!("Error: key {:?}: {}", path, e),
+        };
+
+        let jwk = public_jwk(
+            &kid,
+            KeyAlgorithm::RS256,
+            AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(public.n),
+                e: URL_SAFE_NO_PAD.encode(public.e),
+            }),
+        );
+
+        return signing_key(kid, Algorithm::RS256, encoding, jwk);
+    }
+
+    let key_pair = match Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+        Ok(res) => res,
+        Err(e) => panic!("Error: key {:?} is neither RSA nor Ed25519: {}", path, e),
+    };
+
+    let jwk = public_jwk(
+        &kid,
+        KeyAlgorithm::EdDSA,
+        AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+            key_type: OctetKeyPairType::OctetKeyPair,
+            curve: EllipticCurve::Ed25519,
+            x: URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref()),
+        }),
+    );
+
+    signing_key(kid, Algorithm::EdDSA, EncodingKey::from_ed_der(der), jwk)
+}
+
+fn public_jwk(kid: &str, algorithm: KeyAlgorithm, parameters: AlgorithmParameters) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    }
+}
+
+fn signing_key(kid: String, algorithm: Algorithm, encoding: EncodingKey, jwk: Jwk) -> SigningKey {
+    let decoding = match DecodingKey::from_jwk(&jwk) {
+        Ok(res) => res,
+        Err(e) => panic!("Error: key {:?}: {}", kid, e),
+    };
+
+    SigningKey {
+        kid: Some(kid),
+        algorithm,
+        encoding,
+        decoding,
+        jwk: Some(jwk),
+    }
+}
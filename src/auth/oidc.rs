@@ -0,0 +1,526 @@
+use std::env;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{prelude::*, Duration};
+use hyper::{header, HeaderMap, StatusCode};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use once_cell::sync::Lazy;
+use rand::Rng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use url::Url;
+
+use crate::{
+    db::{
+        connect::DbState,
+        models::{Errors, Role, TokenPurpose, User},
+    },
+    utils::{
+        http_client::{get_json, post_form, HttpError},
+        random_id::{random_id, random_token},
+    },
+};
+
+use super::{
+    keys::SECRET,
+    one_time::{consume_one_time_token, issue_one_time_token},
+    password::hash_password,
+    policy::{check_username, normalize_username, username_key, USERNAME_MAX_LENGTH},
+};
+
+/// Time the user has to get through the provider's login, in seconds.
+pub const OIDC_LOGIN_TTL: i64 = 10 * 60;
+
+/// Cookie holding the `state` of the login the browser started, so a
+/// callback carrying someone else's `state` (login CSRF) is refused.
+pub const OIDC_STATE_COOKIE: &str = "oidc_state";
+
+/// How long discovery documents and signing keys are cached. Keys are
+/// fetched again sooner when a token names one we don't know.
+const PROVIDER_CACHE_SECS: i64 = 60 * 60;
+
+/// ID token algorithms accepted. Only asymmetric ones, the client secret
+/// must never verify a token.
+const ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// Login through an OpenID Connect provider, enabled by setting
+/// `OIDC_ISSUER`. Also reads `OIDC_CLIENT_ID`, `OIDC_CLIENT_SECRET` (leave it
+/// out for public clients, PKCE is always used), `OIDC_REDIRECT_URI` (the
+/// URL of `/api/users/oidc/callback` as the provider sees it), `OIDC_SCOPES`
+/// (default `openid email profile`) and `OIDC_PROVISION` (default true,
+/// `false` to only let in provider accounts linked to an existing user).
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: String,
+    pub provision: bool,
+}
+
+pub static OIDC: Lazy<Option<OidcConfig>> = Lazy::new(|| {
+    let issuer = match env::var("OIDC_ISSUER") {
+        Ok(res) => res.trim_end_matches('/').to_string(),
+        Err(_) => return None,
+    };
+
+    Some(OidcConfig {
+        issuer,
+        client_id: match env::var("OIDC_CLIENT_ID") {
+            Ok(res) => res,
+            Err(_) => panic!("Error: OIDC_ISSUER is set but OIDC_CLIENT_ID is not"),
+        },
+        client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+        redirect_uri: match env::var("OIDC_REDIRECT_URI") {
+            Ok(res) => res,
+            Err(_) => panic!("Error: OIDC_ISSUER is set but OIDC_REDIRECT_URI is not"),
+        },
+        scopes: env::var("OIDC_SCOPES").unwrap_or(String::from("openid email profile")),
+        provision: match env::var("OIDC_PROVISION") {
+            Ok(res) => !matches!(res.to_lowercase().as_str(), "0" | "false" | "no"),
+            Err(_) => true,
+        },
+    })
+});
+
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Clone)]
+struct Provider {
+    metadata: ProviderMetadata,
+    keys: JwkSet,
+    fetched_at: DateTime<Utc>,
+}
+
+static PROVIDER: Lazy<RwLock<Option<Provider>>> = Lazy::new(|| RwLock::new(None));
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+/// What Note4Keep reads out of a validated ID token.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub azp: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: Option<bool>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum OidcError {
+    Disabled,
+    Http(HttpError),
+    Jwt(jsonwebtoken::errors::Error),
+    /// Unknown or already used `state`, a `state` the browser didn't start,
+    /// or an ID token that doesn't belong to this login.
+    InvalidLogin(&'static str),
+    /// The provider account isn't linked and provisioning is off.
+    NotLinked,
+    Store(Errors),
+}
+
+impl From<HttpError> for OidcError {
+    fn from(e: HttpError) -> Self {
+        OidcError::Http(e)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for OidcError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        OidcError::Jwt(e)
+    }
+}
+
+impl From<Errors> for OidcError {
+    fn from(e: Errors) -> Self {
+        OidcError::Store(e)
+    }
+}
+
+impl From<OidcError> for StatusCode {
+    fn from(e: OidcError) -> Self {
+        match e {
+            OidcError::Disabled => StatusCode::NOT_FOUND,
+            OidcError::Store(e) => e.into(),
+            OidcError::NotLinked => StatusCode::FORBIDDEN,
+            OidcError::Http(e) => {
+                println!("Error: {:?}", e);
+                StatusCode::BAD_GATEWAY
+            }
+            e => {
+                println!("Error: {:?}", e);
+                StatusCode::UNAUTHORIZED
+            }
+        }
+    }
+}
+
+fn config() -> Result<&'static OidcConfig, OidcError> {
+    match OIDC.as_ref() {
+        Some(res) => Ok(res),
+        None => Err(OidcError::Disabled),
+    }
+}
+
+/// Discovery document and signing keys of the provider, from the cache
+/// unless it is stale or `refresh` is set.
+async fn load_provider(config: &OidcConfig, refresh: bool) -> Result<Provider, OidcError> {
+    let now = Utc::now();
+
+    if !refresh {
+        if let Some(provider) = PROVIDER.read().await.as_ref() {
+            if now - provider.fetched_at < Duration::try_seconds(PROVIDER_CACHE_SECS).unwrap() {
+                return Ok(provider.clone());
+            }
+        }
+    }
+
+    let metadata: ProviderMetadata = get_json(&format!(
+        "{}/.well-known/openid-configuration",
+        config.issuer
+    ))
+    .await?;
+
+    if metadata.issuer.trim_end_matches('/') != config.issuer {
+        return Err(OidcError::InvalidLogin("discovery issuer mismatch"));
+    }
+
+    let keys: JwkSet = get_json(&metadata.jwks_uri).await?;
+
+    let provider = Provider {
+        metadata,
+        keys,
+        fetched_at: now,
+    };
+
+    *PROVIDER.write().await = Some(provider.clone());
+
+    Ok(provider)
+}
+
+/// Secrets of a login that never leave the server, derived from its `state`
+/// so they don't have to be stored.
+fn derive_secret(label: &str, state: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(format!("{}:{}:{}", label, *SECRET, state)))
+}
+
+fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// `Set-Cookie` value binding the login `login_state` to the browser, or
+/// clearing the cookie with `None`. Scoped to the path of the callback and
+/// `SameSite=Lax`, so it comes back with the provider's redirect.
+pub fn state_cookie(login_state: Option<&str>) -> Result<String, OidcError> {
+    let config = config()?;
+
+    let redirect_uri = match Url::parse(&config.redirect_uri) {
+        Ok(res) => res,
+        Err(_) => return Err(OidcError::InvalidLogin("bad redirect uri")),
+    };
+
+    let mut cookie = format!(
+        "{}={}; Path={}; Max-Age={}; HttpOnly; SameSite=Lax",
+        OIDC_STATE_COOKIE,
+        login_state.unwrap_or_default(),
+        redirect_uri.path(),
+        login_state.map_or(0, |_| OIDC_LOGIN_TTL),
+    );
+
+    if redirect_uri.scheme() == "https" {
+        cookie.push_str("; Secure");
+    }
+
+    Ok(cookie)
+}
+
+/// The login `state` the browser sending `headers` started, from its cookie.
+pub fn browser_state(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == OIDC_STATE_COOKIE)
+        .map(|(_, value)| value.to_string())
+}
+
+/// Starts a login and returns the provider URL to send the user to, with
+/// the `state` to keep in the browser's cookie.
+pub async fn authorization_url(state: &DbState) -> Result<(String, String), OidcError> {
+    let config = config()?;
+    let provider = load_provider(config, false).await?;
+
+    let login_state =
+        issue_one_time_token(state, "", TokenPurpose::OidcLogin, OIDC_LOGIN_TTL).await?;
+
+    let verifier = derive_secret("pkce", &login_state);
+    let nonce = derive_secret("nonce", &login_state);
+
+    let mut url = match Url::parse(&provider.metadata.authorization_endpoint) {
+        Ok(res) => res,
+        Err(_) => return Err(OidcError::InvalidLogin("bad authorization endpoint")),
+    };
+
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_uri)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", &login_state)
+        .append_pair("nonce", &nonce)
+        .append_pair("code_challenge", &code_challenge(&verifier))
+        .append_pair("code_challenge_method", "S256");
+
+    Ok((url.to_string(), login_state))
+}
+
+/// Finishes the login `login_state` started: trades `code` for an ID token
+/// and validates it. `browser_state` is the `state` of the browser's cookie,
+/// it must be the same login.
+pub async fn complete_login(
+    state: &DbState,
+    code: &str,
+    login_state: &str,
+    browser_state: Option<&str>,
+) -> Result<IdTokenClaims, OidcError> {
+    let config = config()?;
+
+    if browser_state != Some(login_state) {
+        return Err(OidcError::InvalidLogin("state not started by this browser"));
+    }
+
+    if consume_one_time_token(state, login_state, TokenPurpose::OidcLogin)
+        .await?
+        .is_none()
+    {
+        return Err(OidcError::InvalidLogin("unknown state"));
+    }
+
+    let provider = load_provider(config, false).await?;
+    let verifier = derive_secret("pkce", login_state);
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", config.redirect_uri.as_str()),
+        ("client_id", config.client_id.as_str()),
+        ("code_verifier", verifier.as_str()),
+    ];
+
+    if let Some(secret) = &config.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+
+    let tokens: TokenResponse = post_form(&provider.metadata.token_endpoint, &form).await?;
+
+    let id_token = match tokens.id_token {
+        Some(res) => res,
+        None => return Err(OidcError::InvalidLogin("no id_token")),
+    };
+
+    let claims = validate_id_token(config, provider, &id_token).await?;
+
+    if claims.nonce.as_deref() != Some(derive_secret("nonce", login_state).as_str()) {
+        return Err(OidcError::InvalidLogin("nonce mismatch"));
+    }
+
+    Ok(claims)
+}
+
+async fn validate_id_token(
+    config: &OidcConfig,
+    mut provider: Provider,
+    id_token: &str,
+) -> Result<IdTokenClaims, OidcError> {
+    let header = decode_header(id_token)?;
+
+    if !ALGORITHMS.contains(&header.alg) {
+        return Err(OidcError::InvalidLogin("id_token algorithm not allowed"));
+    }
+
+    let find_key = |keys: &JwkSet| match &header.kid {
+        Some(kid) => keys.find(kid).cloned(),
+        None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+        None => None,
+    };
+
+    // An unknown key usually means the provider rotated its keys.
+    let jwk = match find_key(&provider.keys) {
+        Some(res) => res,
+        None => {
+            provider = load_provider(config, true).await?;
+
+            match find_key(&provider.keys) {
+                Some(res) => res,
+                None => return Err(OidcError::InvalidLogin("unknown id_token key")),
+            }
+        }
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&provider.metadata.issuer]);
+    validation.set_audience(&[&config.client_id]);
+
+    let claims =
+        decode::<IdTokenClaims>(id_token, &DecodingKey::from_jwk(&jwk)?, &validation)?.claims;
+
+    if claims
+        .azp
+        .as_ref()
+        .is_some_and(|azp| *azp != config.client_id)
+    {
+        return Err(OidcError::InvalidLogin("id_token for another client"));
+    }
+
+    Ok(claims)
+}
+
+/// The user `claims` signs in as. In order: the user already linked to the
+/// provider account, the only user with the same email when both sides
+/// verified it (then linked), or a new user when provisioning is on.
+pub async fn find_or_provision_user(
+    state: &DbState,
+    claims: &IdTokenClaims,
+) -> Result<User, OidcError> {
+    let config = config()?;
+
+    if let Some(user) = state.users.find_user_by_oidc_subject(&claims.sub).await? {
+        return Ok(user);
+    }
+
+    let verified_email = match (&claims.email, claims.email_verified) {
+        (Some(email), Some(true)) => Some(email.clone()),
+        _ => None,
+    };
+
+    if let Some(email) = &verified_email {
+        let mut users: Vec<User> = state
+            .users
+            .list_users_by_email(email)
+            .await?
+            .into_iter()
+            .filter(|user| user.email_verified)
+            .collect();
+
+        // Emails aren't unique, with several accounts there is no telling
+        // which one the provider account is.
+        if users.len() == 1 && users[0].oidc_subject.is_none() {
+            let mut user = users.remove(0);
+
+            user.oidc_subject = Some(claims.sub.clone());
+            state.users.update_user(&user).await?;
+
+            return Ok(user);
+        }
+    }
+
+    if !config.provision {
+        return Err(OidcError::NotLinked);
+    }
+
+    let username = free_username(state, claims).await?;
+
+    // Provisioned users sign in through the provider, the password is only
+    // there to be replaced through a password reset if they ever want one.
+    let password = match hash_password(&random_token()).await {
+        Ok(res) => res,
+        Err(e) => {
+            println!("Error: {:?}", e);
+            return Err(OidcError::Store(Errors::Status(
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )));
+        }
+    };
+
+    let user = User {
+        user_id: random_id(),
+        username_key: username_key(&username),
+        username,
+        password,
+        email: claims.email.clone(),
+        email_verified: verified_email.is_some(),
+        role: Role::User,
+        oidc_subject: Some(claims.sub.clone()),
+        ..Default::default()
+    };
+
+    state.users.insert_user(&user).await?;
+
+    Ok(user)
+}
+
+/// A username for a provisioned user following the username policy, based
+/// on what the provider calls them and made unique with a number if needed.
+async fn free_username(state: &DbState, claims: &IdTokenClaims) -> Result<String, OidcError> {
+    let wanted = match (&claims.preferred_username, &claims.email) {
+        (Some(name), _) => name.clone(),
+        (None, Some(email)) => email.split('@').next().unwrap_or_default().to_string(),
+        (None, None) => String::new(),
+    };
+
+    let mut base: String = normalize_username(&wanted)
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        .take(*USERNAME_MAX_LENGTH - 4)
+        .collect();
+
+    base = base
+        .trim_matches(|c| matches!(c, '_' | '-' | '.'))
+        .to_string();
+
+    if check_username(&base).is_empty() && state.users.find_user_by_username(&base).await?.is_none()
+    {
+        return Ok(base);
+    }
+
+    if base.is_empty() {
+        base = String::from("user");
+    }
+
+    for _ in 0..10 {
+        let candidate = format!("{}{}", base, rand::thread_rng().gen_range(1000..10000));
+
+        if check_username(&candidate).is_empty()
+            && state
+                .users
+                .find_user_by_username(&candidate)
+                .await?
+                .is_none()
+        {
+            return Ok(candidate);
+        }
+    }
+
+    Err(OidcError::InvalidLogin("no free username"))
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,298 @@
+//! Logins against a mock provider serving discovery, keys and the token
+//! endpoint on a local port, the way a real provider would.
+
+use std::{collections::HashMap, env, sync::Arc};
+
+use axum::{extract::Form, routing::get, routing::post, Extension, Json, Router};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use hyper::{header, HeaderMap, StatusCode};
+use jsonwebtoken::{
+    encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    },
+    Algorithm, EncodingKey, Header,
+};
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::{net::TcpListener, sync::Mutex};
+use url::Url;
+
+use crate::{
+    db::{connect::DbState, memory::MemoryStore, models::User},
+    utils::random_id::random_id,
+};
+
+use super::{
+    authorization_url, browser_state, complete_login, find_or_provision_user, state_cookie,
+    IdTokenClaims, OidcError,
+};
+
+const CLIENT_ID: &str = "note4keep";
+const REDIRECT_URI: &str = "http://localhost:3000/api/users/oidc/callback";
+const SUBJECT: &str = "mock-subject";
+
+/// What the provider remembers of a login between the authorization and
+/// the token request.
+struct Grant {
+    nonce: String,
+    code_challenge: String,
+    redirect_uri: String,
+}
+
+struct MockProvider {
+    issuer: String,
+    key: EncodingKey,
+    jwk: Jwk,
+    grants: Mutex<HashMap<String, Grant>>,
+}
+
+impl MockProvider {
+    /// Starts the provider on a free port of 127.0.0.1.
+    async fn start() -> Arc<MockProvider> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(KeyAlgorithm::EdDSA),
+                key_id: Some(String::from("mock")),
+                ..Default::default()
+            },
+            algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref()),
+            }),
+        };
+
+        let provider = Arc::new(MockProvider {
+            issuer,
+            key: EncodingKey::from_ed_der(pkcs8.as_ref()),
+            jwk,
+            grants: Mutex::new(HashMap::new()),
+        });
+
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .layer(Extension(provider.clone()));
+
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        provider
+    }
+
+    /// What the browser goes through at the authorization endpoint: the
+    /// user logs in and is sent back with a code and the `state`.
+    async fn authorize(&self, url: &str) -> (String, String) {
+        let url = Url::parse(url).unwrap();
+        let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+
+        assert!(url.as_str().starts_with(&self.issuer));
+        assert_eq!(query["response_type"], "code");
+        assert_eq!(query["client_id"], CLIENT_ID);
+        assert_eq!(query["code_challenge_method"], "S256");
+
+        let code = crate::utils::random_id::random_token();
+
+        self.grants.lock().await.insert(
+            code.clone(),
+            Grant {
+                nonce: query["nonce"].clone(),
+                code_challenge: query["code_challenge"].clone(),
+                redirect_uri: query["redirect_uri"].clone(),
+            },
+        );
+
+        (code, query["state"].clone())
+    }
+}
+
+async fn discovery(Extension(provider): Extension<Arc<MockProvider>>) -> Json<Value> {
+    Json(json!({
+        "issuer": provider.issuer,
+        "authorization_endpoint": format!("{}/authorize", provider.issuer),
+        "token_endpoint": format!("{}/token", provider.issuer),
+        "jwks_uri": format!("{}/jwks", provider.issuer),
+    }))
+}
+
+async fn jwks(Extension(provider): Extension<Arc<MockProvider>>) -> Json<JwkSet> {
+    Json(JwkSet {
+        keys: vec![provider.jwk.clone()],
+    })
+}
+
+async fn token(
+    Extension(provider): Extension<Arc<MockProvider>>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let grant = match provider.grants.lock().await.remove(&form["code"]) {
+        Some(res) => res,
+        None => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(form["code_verifier"].as_bytes()));
+
+    if form["grant_type"] != "authorization_code"
+        || form["client_id"] != CLIENT_ID
+        || form["redirect_uri"] != grant.redirect_uri
+        || challenge != grant.code_challenge
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let now = Utc::now().timestamp();
+
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(String::from("mock"));
+
+    let id_token = encode(
+        &header,
+        &json!({
+            "iss": provider.issuer,
+            "aud": CLIENT_ID,
+            "sub": SUBJECT,
+            "iat": now,
+            "exp": now + 60,
+            "nonce": grant.nonce,
+            "email": "mock@example.com",
+            "email_verified": true,
+            "preferred_username": "Mock User",
+        }),
+        &provider.key,
+    )
+    .unwrap();
+
+    Ok(Json(json!({
+        "access_token": "mock",
+        "token_type": "Bearer",
+        "id_token": id_token,
+    })))
+}
+
+// The config is read once per process, this is the only test setting it.
+#[tokio::test]
+async fn login_through_mock_provider() {
+    let provider = MockProvider::start().await;
+
+    env::set_var("SECRET", "oidc-test-secret");
+    env::set_var("OIDC_ISSUER", &provider.issuer);
+    env::set_var("OIDC_CLIENT_ID", CLIENT_ID);
+    env::set_var("OIDC_REDIRECT_URI", REDIRECT_URI);
+
+    let state = DbState::new(MemoryStore::default());
+
+    let (url, login_state) = authorization_url(&state).await.unwrap();
+    let (code, returned_state) = provider.authorize(&url).await;
+    assert_eq!(returned_state, login_state);
+
+    let cookie = state_cookie(Some(&login_state)).unwrap();
+    assert!(cookie.starts_with(&format!("oidc_state={};", login_state)));
+    assert!(cookie.contains("Path=/api/users/oidc/callback;"));
+    assert!(cookie.contains("HttpOnly"));
+    assert!(!cookie.contains("Secure"));
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::COOKIE,
+        format!("theme=dark; oidc_state={}", login_state)
+            .parse()
+            .unwrap(),
+    );
+    assert_eq!(
+        browser_state(&headers).as_deref(),
+        Some(login_state.as_str())
+    );
+
+    // A callback from a browser that didn't start the login is refused,
+    // without using it up.
+    let res = complete_login(&state, &code, &login_state, None).await;
+    assert!(matches!(res, Err(OidcError::InvalidLogin(_))));
+
+    let res = complete_login(&state, &code, &login_state, Some("another")).await;
+    assert!(matches!(res, Err(OidcError::InvalidLogin(_))));
+
+    let claims = complete_login(&state, &code, &login_state, Some(&login_state))
+        .await
+        .unwrap();
+    assert_eq!(claims.sub, SUBJECT);
+    assert_eq!(claims.email.as_deref(), Some("mock@example.com"));
+
+    // The state is single use.
+    let res = complete_login(&state, &code, &login_state, Some(&login_state)).await;
+    assert!(matches!(res, Err(OidcError::InvalidLogin(_))));
+
+    let user = find_or_provision_user(&state, &claims).await.unwrap();
+    assert_eq!(user.oidc_subject.as_deref(), Some(SUBJECT));
+    assert!(user.email_verified);
+
+    let again = find_or_provision_user(&state, &claims).await.unwrap();
+    assert_eq!(again.user_id, user.user_id);
+
+    // A code is only traded once, a second login gets its own.
+    let (url, login_state) = authorization_url(&state).await.unwrap();
+    let (code, _) = provider.authorize(&url).await;
+
+    let claims = complete_login(&state, &code, &login_state, Some(&login_state))
+        .await
+        .unwrap();
+    assert_eq!(claims.sub, SUBJECT);
+
+    // A verified email links the only local user verified with it.
+    let local = local_user(&state, "only@example.com").await;
+
+    let claims = IdTokenClaims {
+        sub: String::from("other-subject"),
+        email: Some(String::from("only@example.com")),
+        ..claims
+    };
+
+    let user = find_or_provision_user(&state, &claims).await.unwrap();
+    assert_eq!(user.user_id, local.user_id);
+
+    // With two of them neither is linked, a new user is provisioned.
+    let first = local_user(&state, "shared@example.com").await;
+    let second = local_user(&state, "shared@example.com").await;
+
+    let claims = IdTokenClaims {
+        sub: String::from("third-subject"),
+        email: Some(String::from("shared@example.com")),
+        ..claims
+    };
+
+    let user = find_or_provision_user(&state, &claims).await.unwrap();
+    assert_ne!(user.user_id, first.user_id);
+    assert_ne!(user.user_id, second.user_id);
+
+    for local in [first, second] {
+        let stored = state.users.find_user_by_id(&local.user_id).await.unwrap();
+        assert_eq!(stored.unwrap().oidc_subject, None);
+    }
+}
+
+async fn local_user(state: &DbState, email: &str) -> User {
+    let user = User {
+        user_id: random_id(),
+        username: random_id(),
+        email: Some(email.to_string()),
+        email_verified: true,
+        ..Default::default()
+    };
+
+    state.users.insert_user(&user).await.unwrap();
+
+    user
+}
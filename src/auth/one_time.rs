@@ -0,0 +1,52 @@
+use chrono::{prelude::*, Duration};
+
+use crate::{
+    db::{
+        connect::DbState,
+        models::{Errors, OneTimeToken, TokenPurpose},
+    },
+    utils::random_id::random_token,
+};
+
+use super::refresh::hash_token;
+
+/// Stores a new single-use token of `purpose` for `user_id`, valid for
+/// `ttl` seconds, and returns the plain token to mail to the user.
+pub async fn issue_one_time_token(
+    state: &DbState,
+    user_id: &str,
+    purpose: TokenPurpose,
+    ttl: i64,
+) -> Result<String, Errors> {
+    let token = random_token();
+    let now = Utc::now();
+
+    state
+        .tokens
+        .insert_one_time_token(&OneTimeToken {
+            token_hash: hash_token(&token),
+            user_id: user_id.to_string(),
+            purpose,
+            used: false,
+            created_at: now,
+            expires_at: now + Duration::try_seconds(ttl).unwrap(),
+        })
+        .await?;
+
+    Ok(token)
+}
+
+/// Uses up `token` and returns the id of the user it was issued to, or
+/// `None` when it is unknown, expired or was already used.
+pub async fn consume_one_time_token(
+    state: &DbState,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<Option<String>, Errors> {
+    let consumed = state
+        .tokens
+        .consume_one_time_token(&hash_token(token), purpose, Utc::now())
+        .await?;
+
+    Ok(consumed.map(|token| token.user_id))
+}
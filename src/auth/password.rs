@@ -0,0 +1,146 @@
+use std::env;
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use once_cell::sync::Lazy;
+
+use crate::utils::random_id::random_token;
+
+fn env_param(name: &str, default: u32) -> u32 {
+    match env::var(name) {
+        Ok(res) => match res.parse() {
+            Ok(value) => value,
+            Err(_) => panic!("Error: {} must be a positive number", name),
+        },
+        Err(_) => default,
+    }
+}
+
+/// Argon2id parameters for new hashes, from `ARGON2_MEMORY_KIB` (default
+/// 19456), `ARGON2_ITERATIONS` (default 2) and `ARGON2_PARALLELISM` (default
+/// 1). Stored hashes made with other parameters are upgraded on login.
+static PARAMS: Lazy<Params> = Lazy::new(|| {
+    match Params::new(
+        env_param("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST),
+        env_param("ARGON2_ITERATIONS", Params::DEFAULT_T_COST),
+        env_param("ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+        None,
+    ) {
+        Ok(res) => res,
+        Err(e) => panic!("Error: invalid Argon2 parameters: {}", e),
+    }
+});
+
+/// Hash of a password nobody knows. Logins for unknown accounts are checked
+/// against it, so they take as long as logins with a wrong password.
+static DUMMY_HASH: Lazy<String> = Lazy::new(|| hash_blocking(&random_token()).unwrap());
+
+#[derive(Debug)]
+pub enum PasswordError {
+    Hash(argon2::password_hash::Error),
+    Bcrypt(bcrypt::BcryptError),
+    Join(tokio::task::JoinError),
+}
+
+/// Outcome of checking a password against a stored hash.
+#[derive(Debug, Clone, Copy)]
+pub struct Verification {
+    pub valid: bool,
+    /// The hash is bcrypt or uses outdated Argon2 parameters, and should be
+    /// replaced by a fresh `hash_password` of the same password.
+    pub needs_rehash: bool,
+}
+
+impl From<argon2::password_hash::Error> for PasswordError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        PasswordError::Hash(e)
+    }
+}
+
+impl From<bcrypt::BcryptError> for PasswordError {
+    fn from(e: bcrypt::BcryptError) -> Self {
+        PasswordError::Bcrypt(e)
+    }
+}
+
+impl From<tokio::task::JoinError> for PasswordError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        PasswordError::Join(e)
+    }
+}
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, PARAMS.clone())
+}
+
+fn hash_blocking(pass: &str) -> Result<String, PasswordError> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Ok(argon2().hash_password(pass.as_bytes(), &salt)?.to_string())
+}
+
+fn verify_blocking(pass: &str, stored: &str) -> Result<Verification, PasswordError> {
+    // Accounts created before Argon2 still have bcrypt hashes.
+    if stored.starts_with("$2") {
+        return Ok(Verification {
+            valid: bcrypt::verify(pass, stored)?,
+            needs_rehash: true,
+        });
+    }
+
+    let parsed = PasswordHash::new(stored)?;
+
+    let valid = match argon2().verify_password(pass.as_bytes(), &parsed) {
+        Ok(()) => true,
+        Err(argon2::password_hash::Error::Password) => false,
+        Err(e) => return Err(e.into()),
+    };
+
+    let needs_rehash = match Params::try_from(&parsed) {
+        Ok(params) => {
+            parsed.algorithm.as_str() != Algorithm::Argon2id.as_str()
+                || params.m_cost() != PARAMS.m_cost()
+                || params.t_cost() != PARAMS.t_cost()
+                || params.p_cost() != PARAMS.p_cost()
+        }
+        Err(_) => true,
+    };
+
+    Ok(Verification {
+        valid,
+        needs_rehash,
+    })
+}
+
+/// Hashes `pass` with Argon2id on the blocking thread pool.
+pub async fn hash_password(pass: &str) -> Result<String, PasswordError> {
+    let pass = pass.to_string();
+
+    tokio::task::spawn_blocking(move || hash_blocking(&pass)).await?
+}
+
+/// Checks `pass` against a stored Argon2id or bcrypt hash on the blocking
+/// thread pool.
+pub async fn verify_password(pass: &str, stored: &str) -> Result<Verification, PasswordError> {
+    let pass = pass.to_string();
+    let stored = stored.to_string();
+
+    tokio::task::spawn_blocking(move || verify_blocking(&pass, &stored)).await?
+}
+
+/// Computes the dummy hash up front, so the first unknown login isn't slower.
+pub fn init_dummy_hash() {
+    Lazy::force(&DUMMY_HASH);
+}
+
+/// Spends the same time as `verify_password` and always fails.
+pub async fn dummy_verify(pass: &str) -> Result<Verification, PasswordError> {
+    verify_password(pass, &DUMMY_HASH).await?;
+
+    Ok(Verification {
+        valid: false,
+        needs_rehash: false,
+    })
+}
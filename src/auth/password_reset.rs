@@ -0,0 +1,133 @@
+use std::{env, sync::Arc};
+
+use hyper::StatusCode;
+use once_cell::sync::Lazy;
+
+use crate::{
+    db::{
+        connect::DbState,
+        models::{Errors, TokenPurpose, User},
+    },
+    mail::mailer::{send_in_background, Email, Mailer, APP_URL},
+};
+
+use super::{
+    one_time::{consume_one_time_token, issue_one_time_token},
+    password::hash_password,
+    revoke::revoke_all_sessions,
+    verification::{allows, Feature},
+};
+
+/// Lifetime of a password reset token in seconds, `PASSWORD_RESET_TTL`
+/// (default 1 hour).
+pub static PASSWORD_RESET_TTL: Lazy<i64> = Lazy::new(|| match env::var("PASSWORD_RESET_TTL") {
+    Ok(res) => match res.parse() {
+        Ok(ttl) => ttl,
+        Err(_) => panic!("Error: PASSWORD_RESET_TTL must be a number of seconds"),
+    },
+    Err(_) => 60 * 60,
+});
+
+/// Mails `user` a reset token, replacing any earlier one. Does nothing for
+/// users without an email, whose account is disabled or who still have to
+/// verify their email.
+pub async fn send_password_reset(
+    state: &DbState,
+    mailer: &Arc<dyn Mailer>,
+    user: &User,
+) -> Result<(), Errors> {
+    let to = match &user.email {
+        Some(email) if !user.disabled && allows(user, Feature::PasswordReset) => email.clone(),
+        _ => return Ok(()),
+    };
+
+    state
+        .tokens
+        .delete_user_one_time_tokens(&user.user_id, TokenPurpose::PasswordReset)
+        .await?;
+
+    let token = issue_one_time_token(
+        state,
+        &user.user_id,
+        TokenPurpose::PasswordReset,
+        *PASSWORD_RESET_TTL,
+    )
+    .await?;
+
+    let link = match APP_URL.as_ref() {
+        Some(url) => format!("{}/reset-password?token={}", url, token),
+        None => format!("Reset code: {}", token),
+    };
+
+    send_in_background(
+        mailer,
+        Email {
+            to,
+            subject: String::from("Reset your Note4Keep password"),
+            body: format!(
+                "Hi {},\n\nSomeone asked to reset the password of your Note4Keep account. \
+                 If it was you, use this within {} minutes:\n\n{}\n\n\
+                 If it wasn't, you can ignore this email.\n",
+                user.username,
+                *PASSWORD_RESET_TTL / 60,
+                link
+            ),
+        },
+    );
+
+    Ok(())
+}
+
+/// Sets `password` on the account `token` was issued for and ends all of its
+/// sessions. Each token works once.
+pub async fn reset_password(
+    state: &DbState,
+    token: &str,
+    password: &str,
+) -> Result<(), StatusCode> {
+    let user_id = match consume_one_time_token(state, token, TokenPurpose::PasswordReset).await {
+        Ok(res) => match res {
+            Some(user_id) => user_id,
+            None => return Err(StatusCode::UNAUTHORIZED),
+        },
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut user = match state.users.find_user_by_id(&user_id).await {
+        Ok(res) => match res {
+            Some(user) => user,
+            None => return Err(StatusCode::UNAUTHORIZED),
+        },
+        Err(e) => return Err(e.into()),
+    };
+
+    if user.disabled {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    user.password = match hash_password(password).await {
+        Ok(res) => res,
+        Err(e) => {
+            println!("Error: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    if let Err(e) = state.users.update_user(&user).await {
+        return Err(e.into());
+    }
+
+    if let Err(e) = state
+        .tokens
+        .delete_user_one_time_tokens(&user_id, TokenPurpose::PasswordReset)
+        .await
+    {
+        return Err(e.into());
+    }
+
+    if let Err(e) = revoke_all_sessions(state, &user_id).await {
+        return Err(e.into());
+    }
+
+    Ok(())
+}
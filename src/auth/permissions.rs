@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use serde::{Deserialize, Serialize};
+
+use crate::db::{connect::DbState, models::Role};
+
+use super::{
+    extractor::{authenticate, AuthError},
+    jwt::Claims,
+};
+
+/// What a role or an API key may do. Serialized as the scope names API keys
+/// are created with.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Permission {
+    #[serde(rename = "notes:read")]
+    NotesRead,
+    #[serde(rename = "notes:write")]
+    NotesWrite,
+    #[serde(rename = "users:read")]
+    UsersRead,
+    #[serde(rename = "users:manage")]
+    UsersManage,
+}
+
+const USER_PERMISSIONS: &[Permission] = &[Permission::NotesRead, Permission::NotesWrite];
+
+const ADMIN_PERMISSIONS: &[Permission] = &[
+    Permission::NotesRead,
+    Permission::NotesWrite,
+    Permission::UsersRead,
+    Permission::UsersManage,
+];
+
+impl Role {
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::User => USER_PERMISSIONS,
+            Role::Admin => ADMIN_PERMISSIONS,
+        }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+impl Claims {
+    /// Whether the role, and for API keys also the key's scopes, grant
+    /// `permission`.
+    pub fn allows(&self, permission: Permission) -> bool {
+        let scoped = match &self.scopes {
+            Some(scopes) => scopes.contains(&permission),
+            None => true,
+        };
+
+        scoped && self.role.can(permission)
+    }
+}
+
+/// Route middleware: rejects requests whose token role lacks `permission`
+/// and hands the validated `Claims` on to the `AuthUser` extractor.
+///
+/// Use it through `middleware::from_fn` with a closure fixing the permission.
+pub async fn require_permission(
+    permission: Permission,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, AuthError> {
+    let claims = authenticate(req.headers(), req.extensions().get::<Arc<DbState>>()).await?;
+
+    if !claims.allows(permission) {
+        return Err(AuthError::Forbidden);
+    }
+
+    req.extensions_mut().insert(claims);
+
+    Ok(next.run(req).await)
+}
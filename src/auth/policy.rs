@@ -0,0 +1,240 @@
+use std::{collections::HashSet, env};
+
+use axum::Json;
+use hyper::StatusCode;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::{json, Value};
+use unicode_normalization::UnicodeNormalization;
+
+fn env_number(key: &str, default: usize) -> usize {
+    match env::var(key) {
+        Ok(res) => match res.parse() {
+            Ok(number) => number,
+            Err(_) => panic!("Error: {} must be a positive number", key),
+        },
+        Err(_) => default,
+    }
+}
+
+/// Shortest accepted password in characters, `PASSWORD_MIN_LENGTH` (default 8).
+static PASSWORD_MIN_LENGTH: Lazy<usize> = Lazy::new(|| env_number("PASSWORD_MIN_LENGTH", 8));
+
+/// Lowest accepted `strength` score from 0 to 4, `PASSWORD_MIN_STRENGTH`
+/// (default 2).
+static PASSWORD_MIN_STRENGTH: Lazy<usize> = Lazy::new(|| env_number("PASSWORD_MIN_STRENGTH", 2));
+
+/// Hashing cost grows with the input, so very long passwords are refused.
+const PASSWORD_MAX_LENGTH: usize = 128;
+
+/// Username length bounds in characters, `USERNAME_MIN_LENGTH` (default 3)
+/// and `USERNAME_MAX_LENGTH` (default 32).
+static USERNAME_MIN_LENGTH: Lazy<usize> = Lazy::new(|| env_number("USERNAME_MIN_LENGTH", 3));
+pub static USERNAME_MAX_LENGTH: Lazy<usize> = Lazy::new(|| env_number("USERNAME_MAX_LENGTH", 32));
+
+/// Punctuation allowed inside usernames besides letters and digits.
+const USERNAME_SYMBOLS: [char; 3] = ['_', '-', '.'];
+
+static COMMON_PASSWORDS: Lazy<HashSet<String>> = Lazy::new(|| {
+    include_str!("common-passwords.txt")
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_lowercase())
+        .collect()
+});
+
+/// A policy rule a username or password broke.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleFailure {
+    pub field: &'static str,
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl RuleFailure {
+    fn new(field: &'static str, rule: &'static str, message: String) -> Self {
+        RuleFailure {
+            field,
+            rule,
+            message,
+        }
+    }
+}
+
+/// The form usernames are stored in. Compatibility characters (full width
+/// letters, ligatures...) are folded into their plain equivalents.
+pub fn normalize_username(username: &str) -> String {
+    username.nfkc().collect()
+}
+
+/// What usernames are compared by, so names differing only in case or
+/// Unicode representation can't both be registered.
+pub fn username_key(username: &str) -> String {
+    normalize_username(username).to_lowercase().nfkc().collect()
+}
+
+/// Rough guess from 0 to 4 of how hard `password` is to guess, from its
+/// length and the kinds of characters it uses. Characters repeating or
+/// continuing the previous one (`aaa`, `abc`, `321`) count for little.
+pub fn strength(password: &str) -> usize {
+    let chars: Vec<char> = password.chars().collect();
+
+    let mut pool = 0;
+
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        pool += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        pool += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        pool += 10;
+    }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') {
+        pool += 33;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        pool += 100;
+    }
+
+    if pool == 0 {
+        return 0;
+    }
+
+    let effective_length: f64 = chars
+        .iter()
+        .enumerate()
+        .map(|(idx, c)| {
+            let predictable = idx > 0 && (*c as i64 - chars[idx - 1] as i64).abs() <= 1;
+
+            if predictable {
+                0.25
+            } else {
+                1.0
+            }
+        })
+        .sum();
+
+    let bits = effective_length * (pool as f64).log2();
+
+    match bits {
+        bits if bits < 28.0 => 0,
+        bits if bits < 36.0 => 1,
+        bits if bits < 60.0 => 2,
+        bits if bits < 80.0 => 3,
+        _ => 4,
+    }
+}
+
+/// Every rule `username` breaks, checked on its normalized form.
+pub fn check_username(username: &str) -> Vec<RuleFailure> {
+    let username = normalize_username(username);
+    let length = username.chars().count();
+
+    let mut failures = Vec::new();
+
+    if length < *USERNAME_MIN_LENGTH || length > *USERNAME_MAX_LENGTH {
+        failures.push(RuleFailure::new(
+            "username",
+            "length",
+            format!(
+                "Must be between {} and {} characters long",
+                *USERNAME_MIN_LENGTH, *USERNAME_MAX_LENGTH
+            ),
+        ));
+    }
+
+    if username
+        .chars()
+        .any(|c| !c.is_alphanumeric() && !USERNAME_SYMBOLS.contains(&c))
+    {
+        failures.push(RuleFailure::new(
+            "username",
+            "characters",
+            "May only contain letters, digits, '_', '-' and '.'".to_string(),
+        ));
+    }
+
+    let boundaries = [username.chars().next(), username.chars().last()];
+
+    if boundaries
+        .iter()
+        .flatten()
+        .any(|c| USERNAME_SYMBOLS.contains(c))
+    {
+        failures.push(RuleFailure::new(
+            "username",
+            "boundary",
+            "Must start and end with a letter or digit".to_string(),
+        ));
+    }
+
+    failures
+}
+
+/// Every rule `password` breaks. With `username`, passwords containing it
+/// are refused as well.
+pub fn check_password(password: &str, username: Option<&str>) -> Vec<RuleFailure> {
+    let length = password.chars().count();
+    let lowercase = password.to_lowercase();
+
+    let mut failures = Vec::new();
+
+    if length < *PASSWORD_MIN_LENGTH {
+        failures.push(RuleFailure::new(
+            "password",
+            "min_length",
+            format!("Must be at least {} characters long", *PASSWORD_MIN_LENGTH),
+        ));
+    }
+
+    if length > PASSWORD_MAX_LENGTH {
+        failures.push(RuleFailure::new(
+            "password",
+            "max_length",
+            format!("Must be at most {} characters long", PASSWORD_MAX_LENGTH),
+        ));
+    }
+
+    if COMMON_PASSWORDS.contains(&lowercase) {
+        failures.push(RuleFailure::new(
+            "password",
+            "common",
+            "Is too common".to_string(),
+        ));
+    }
+
+    if let Some(username) = username {
+        let key = username_key(username);
+
+        if key.chars().count() >= 3 && lowercase.contains(&key) {
+            failures.push(RuleFailure::new(
+                "password",
+                "contains_username",
+                "Must not contain the username".to_string(),
+            ));
+        }
+    }
+
+    if strength(password) < *PASSWORD_MIN_STRENGTH {
+        failures.push(RuleFailure::new(
+            "password",
+            "strength",
+            "Is too easy to guess, make it longer or mix in other kinds of characters".to_string(),
+        ));
+    }
+
+    failures
+}
+
+/// The 400 answer listing which rules were broken.
+pub fn policy_response(failures: &[RuleFailure]) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "response": "Rejected by the account policy",
+            "errors": failures,
+        })),
+    )
+}
@@ -0,0 +1,159 @@
+use std::env;
+
+use chrono::{prelude::*, Duration};
+use hyper::StatusCode;
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    db::{
+        connect::DbState,
+        models::{RefreshToken, User},
+    },
+    utils::random_id::random_token,
+};
+
+use super::{
+    extractor::ClientInfo,
+    jwt::{create_jwt, ACCESS_TOKEN_TTL},
+    revoke::end_session,
+};
+
+/// Lifetime of a refresh token in seconds, `REFRESH_TOKEN_TTL` (default 30
+/// days). Each rotation issues a new token with a fresh lifetime.
+pub static REFRESH_TOKEN_TTL: Lazy<i64> = Lazy::new(|| match env::var("REFRESH_TOKEN_TTL") {
+    Ok(res) => match res.parse() {
+        Ok(ttl) => ttl,
+        Err(_) => panic!("Error: REFRESH_TOKEN_TTL must be a number of seconds"),
+    },
+    Err(_) => 30 * 24 * 60 * 60,
+});
+
+/// What a successful login or refresh hands back to the client.
+pub struct IssuedTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub family_id: String,
+    pub expires_in: i64,
+}
+
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Issues an access token and a refresh token for `user` in the session
+/// `family_id`. New logins go through `session::start_session` instead.
+pub async fn issue_tokens(
+    state: &DbState,
+    user: &User,
+    family_id: &str,
+) -> Result<IssuedTokens, StatusCode> {
+    let access_token = match create_jwt(user, family_id).await {
+        Ok(token) => token,
+        Err(e) => {
+            println!("Error: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let refresh_token = random_token();
+    let now = Utc::now();
+
+    let stored = RefreshToken {
+        token_hash: hash_token(&refresh_token),
+        family_id: family_id.to_string(),
+        user_id: user.user_id.clone(),
+        used: false,
+        revoked: false,
+        created_at: now,
+        expires_at: now + Duration::try_seconds(*REFRESH_TOKEN_TTL).unwrap(),
+    };
+
+    if let Err(e) = state.tokens.insert_refresh_token(&stored).await {
+        return Err(e.into());
+    }
+
+    Ok(IssuedTokens {
+        access_token,
+        refresh_token,
+        family_id: family_id.to_string(),
+        expires_in: *ACCESS_TOKEN_TTL,
+    })
+}
+
+/// Trades a refresh token for a new pair. A token that was already rotated
+/// means it leaked, so its whole session is ended and the caller rejected.
+pub async fn rotate_refresh_token(
+    state: &DbState,
+    refresh_token: &str,
+    client: &ClientInfo,
+) -> Result<IssuedTokens, StatusCode> {
+    let token_hash = hash_token(refresh_token);
+
+    let stored = match state.tokens.find_refresh_token(&token_hash).await {
+        Ok(res) => match res {
+            Some(stored) => stored,
+            None => return Err(StatusCode::UNAUTHORIZED),
+        },
+        Err(e) => return Err(e.into()),
+    };
+
+    if stored.revoked || stored.expires_at < Utc::now() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let first_use = match state.tokens.mark_refresh_token_used(&token_hash).await {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    if !first_use {
+        println!(
+            "Refresh token reuse detected, revoking family {:?}",
+            stored.family_id
+        );
+
+        if let Err(e) = end_session(state, &stored.user_id, &stored.family_id).await {
+            return Err(e.into());
+        }
+
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user = match state.users.find_user_by_id(&stored.user_id).await {
+        Ok(res) => match res {
+            Some(user) => user,
+            None => return Err(StatusCode::UNAUTHORIZED),
+        },
+        Err(e) => return Err(e.into()),
+    };
+
+    if user.disabled {
+        if let Err(e) = end_session(state, &user.user_id, &stored.family_id).await {
+            return Err(e.into());
+        }
+
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let mut session = match state.sessions.find_session(&stored.family_id).await {
+        Ok(res) => match res {
+            Some(session) => session,
+            None => return Err(StatusCode::UNAUTHORIZED),
+        },
+        Err(e) => return Err(e.into()),
+    };
+
+    let now = Utc::now();
+
+    session.last_seen = now;
+    session.expires_at = now + Duration::try_seconds(*REFRESH_TOKEN_TTL).unwrap();
+    session.ip = client.ip.clone().or(session.ip);
+    session.user_agent = client.user_agent.clone().or(session.user_agent);
+
+    if let Err(e) = state.sessions.update_session(&session).await {
+        return Err(e.into());
+    }
+
+    issue_tokens(state, &user, &stored.family_id).await
+}
@@ -0,0 +1,82 @@
+use chrono::{prelude::*, Duration};
+
+use crate::db::{
+    connect::DbState,
+    models::{Errors, RevokedToken},
+};
+
+use super::jwt::{Claims, ACCESS_TOKEN_TTL};
+
+/// Ends the session `session_id` of `user_id`: the access tokens issued from
+/// it stop validating and its refresh token family can no longer be rotated.
+/// Returns false when the user has no such session.
+pub async fn end_session(state: &DbState, user_id: &str, session_id: &str) -> Result<bool, Errors> {
+    let deleted = state.sessions.delete_session(user_id, session_id).await?;
+
+    state.tokens.revoke_token_family(session_id).await?;
+
+    Ok(deleted)
+}
+
+/// Ends the login `claims` belongs to, putting the access token itself on the
+/// denylist as well.
+pub async fn revoke_session(state: &DbState, claims: &Claims) -> Result<(), Errors> {
+    let expires_at = match DateTime::<Utc>::from_timestamp(claims.exp, 0) {
+        Some(res) => res,
+        None => Utc::now() + Duration::try_seconds(*ACCESS_TOKEN_TTL).unwrap(),
+    };
+
+    state
+        .tokens
+        .insert_revocation(&RevokedToken {
+            jti: Some(claims.jti.clone()),
+            user_id: claims.userid.clone(),
+            issued_before_ms: None,
+            expires_at,
+        })
+        .await?;
+
+    end_session(state, &claims.userid, &claims.sid).await?;
+
+    Ok(())
+}
+
+/// Ends every login of `user_id`: all refresh tokens are revoked and every
+/// access token issued until now is rejected.
+pub async fn revoke_all_sessions(state: &DbState, user_id: &str) -> Result<(), Errors> {
+    let now = Utc::now();
+
+    state
+        .tokens
+        .insert_revocation(&RevokedToken {
+            jti: None,
+            user_id: user_id.to_string(),
+            issued_before_ms: Some(now.timestamp_millis()),
+            expires_at: now + Duration::try_seconds(*ACCESS_TOKEN_TTL).unwrap(),
+        })
+        .await?;
+
+    state.tokens.revoke_user_tokens(user_id).await?;
+
+    state.sessions.delete_user_sessions(user_id).await?;
+
+    Ok(())
+}
+
+/// Rejects every access token of `user_id` issued until now but keeps its
+/// logins, so clients pick up fresh claims with their next refresh.
+pub async fn expire_access_tokens(state: &DbState, user_id: &str) -> Result<(), Errors> {
+    let now = Utc::now();
+
+    state
+        .tokens
+        .insert_revocation(&RevokedToken {
+            jti: None,
+            user_id: user_id.to_string(),
+            issued_before_ms: Some(now.timestamp_millis()),
+            expires_at: now + Duration::try_seconds(*ACCESS_TOKEN_TTL).unwrap(),
+        })
+        .await?;
+
+    Ok(())
+}
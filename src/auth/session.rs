@@ -0,0 +1,67 @@
+use chrono::{prelude::*, Duration};
+use hyper::StatusCode;
+
+use crate::{
+    db::{
+        connect::DbState,
+        models::{Errors, Session, User},
+    },
+    utils::random_id::random_id,
+};
+
+use super::{
+    extractor::ClientInfo,
+    jwt::Claims,
+    refresh::{issue_tokens, IssuedTokens, REFRESH_TOKEN_TTL},
+};
+
+/// How stale `last_seen` may get before a request writes it again, so
+/// authenticated requests don't all turn into a database write.
+const TOUCH_INTERVAL_SECS: i64 = 60;
+
+/// Records a new login of `user` from `client` and issues its first tokens.
+pub async fn start_session(
+    state: &DbState,
+    user: &User,
+    client: &ClientInfo,
+) -> Result<IssuedTokens, StatusCode> {
+    let now = Utc::now();
+
+    let session = Session {
+        session_id: random_id(),
+        user_id: user.user_id.clone(),
+        user_agent: client.user_agent.clone(),
+        ip: client.ip.clone(),
+        created_at: now,
+        last_seen: now,
+        expires_at: now + Duration::try_seconds(*REFRESH_TOKEN_TTL).unwrap(),
+    };
+
+    if let Err(e) = state.sessions.insert_session(&session).await {
+        return Err(e.into());
+    }
+
+    issue_tokens(state, user, &session.session_id).await
+}
+
+/// True when the session the access token was issued from is still active.
+/// Also keeps its `last_seen` roughly up to date.
+pub async fn check_session(state: &DbState, claims: &Claims) -> Result<bool, Errors> {
+    let mut session = match state.sessions.find_session(&claims.sid).await? {
+        Some(res) => res,
+        None => return Ok(false),
+    };
+
+    let now = Utc::now();
+
+    if session.user_id != claims.userid || session.expires_at < now {
+        return Ok(false);
+    }
+
+    if now - session.last_seen > Duration::try_seconds(TOUCH_INTERVAL_SECS).unwrap() {
+        session.last_seen = now;
+        state.sessions.update_session(&session).await?;
+    }
+
+    Ok(true)
+}
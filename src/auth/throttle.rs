@@ -0,0 +1,113 @@
+use std::env;
+
+use chrono::{prelude::*, Duration};
+use hyper::StatusCode;
+use once_cell::sync::Lazy;
+
+use crate::db::{connect::DbState, models::Errors};
+
+use super::{extractor::ClientInfo, policy::username_key};
+
+fn env_number(key: &str, default: i64) -> i64 {
+    match env::var(key) {
+        Ok(res) => match res.parse() {
+            Ok(number) => number,
+            Err(_) => panic!("Error: {} must be a number", key),
+        },
+        Err(_) => default,
+    }
+}
+
+/// Failed logins an account takes before it gets locked,
+/// `LOGIN_MAX_FAILURES` (default 5).
+static MAX_FAILURES: Lazy<i64> = Lazy::new(|| env_number("LOGIN_MAX_FAILURES", 5));
+
+/// Failed logins from one IP before it gets locked, `LOGIN_MAX_FAILURES_PER_IP`
+/// (default 20). Higher than the account limit, IPs are often shared.
+static MAX_FAILURES_PER_IP: Lazy<i64> = Lazy::new(|| env_number("LOGIN_MAX_FAILURES_PER_IP", 20));
+
+/// Length of the first lockout in seconds, `LOGIN_LOCKOUT_SECS` (default 30).
+/// It doubles with every further failure, up to `MAX_LOCKOUT_SECS`.
+static LOCKOUT_SECS: Lazy<i64> = Lazy::new(|| env_number("LOGIN_LOCKOUT_SECS", 30));
+
+const MAX_LOCKOUT_SECS: i64 = 60 * 60;
+
+/// Failures older than this are forgotten.
+pub const FAILURE_WINDOW_SECS: i64 = 24 * 60 * 60;
+
+/// The keys failed logins of `username` from `client` count against, with
+/// how many failures each of them tolerates.
+fn login_keys(username: &str, client: &ClientInfo) -> Vec<(String, i64)> {
+    let mut keys = vec![(user_key(username), *MAX_FAILURES)];
+
+    if let Some(ip) = &client.ip {
+        keys.push((format!("ip:{}", ip), *MAX_FAILURES_PER_IP));
+    }
+
+    keys
+}
+
+fn user_key(username: &str) -> String {
+    format!("user:{}", username_key(username))
+}
+
+/// Rejects with 429 while the account or the client IP is locked out.
+pub async fn check_login_allowed(
+    state: &DbState,
+    username: &str,
+    client: &ClientInfo,
+) -> Result<(), StatusCode> {
+    let now = Utc::now();
+
+    for (key, _) in login_keys(username, client) {
+        match state.attempts.find_login_attempts(&key).await {
+            Ok(Some(attempts)) if attempts.locked_until > now => {
+                return Err(StatusCode::TOO_MANY_REQUESTS)
+            }
+            Ok(_) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts a failed login, locking the account or IP out once they pass
+/// their limit, for twice as long with every further failure.
+pub async fn record_login_failure(
+    state: &DbState,
+    username: &str,
+    client: &ClientInfo,
+) -> Result<(), Errors> {
+    let now = Utc::now();
+
+    for (key, max_failures) in login_keys(username, client) {
+        if let Some(previous) = state.attempts.find_login_attempts(&key).await? {
+            if now - previous.last_failure > Duration::try_seconds(FAILURE_WINDOW_SECS).unwrap() {
+                state.attempts.clear_login_attempts(&key).await?;
+            }
+        }
+
+        let attempts = state.attempts.record_login_failure(&key, now).await?;
+        let over = attempts.failures as i64 - max_failures;
+
+        if over >= 0 {
+            let secs = (*LOCKOUT_SECS).saturating_mul(1 << over.min(32));
+            let until = now + Duration::try_seconds(secs.min(MAX_LOCKOUT_SECS)).unwrap();
+
+            println!("Locking logins for {:?} until {}", key, until.to_rfc3339());
+            state.attempts.lock_login(&key, until).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Forgets the failures of an account after it logged in. The IP keeps its
+/// count, one valid account must not reset guesses against the others.
+pub async fn clear_login_failures(state: &DbState, username: &str) -> Result<(), Errors> {
+    state
+        .attempts
+        .clear_login_attempts(&user_key(username))
+        .await
+}
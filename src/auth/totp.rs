@@ -0,0 +1,113 @@
+use chrono::prelude::*;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::{db::models::User, utils::random_id::random_token};
+
+use super::refresh::hash_token;
+
+const ISSUER: &str = "Note4Keep";
+const DIGITS: usize = 6;
+const STEP_SECS: u64 = 30;
+const RECOVERY_CODES: usize = 10;
+
+/// Lifetime in seconds of the challenge handed out by `log_in` to accounts
+/// with two-factor authentication.
+pub const LOGIN_CHALLENGE_TTL: i64 = 5 * 60;
+
+fn totp(secret: &str, username: &str) -> Option<TOTP> {
+    let secret = match Secret::Encoded(secret.to_string()).to_bytes() {
+        Ok(res) => res,
+        Err(e) => {
+            println!("Error: {:?}", e);
+            return None;
+        }
+    };
+
+    // The account name can't contain ':' in an otpauth URI.
+    match TOTP::new(
+        Algorithm::SHA1,
+        DIGITS,
+        1,
+        STEP_SECS,
+        secret,
+        Some(ISSUER.to_string()),
+        username.replace(':', "_"),
+    ) {
+        Ok(res) => Some(res),
+        Err(e) => {
+            println!("Error: {:?}", e);
+            None
+        }
+    }
+}
+
+/// A fresh base32 encoded secret.
+pub fn new_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+/// `otpauth://` URI of `secret`, to show as a QR code to authenticator apps.
+pub fn otpauth_url(secret: &str, username: &str) -> Option<String> {
+    totp(secret, username).map(|totp| totp.get_url())
+}
+
+/// The time step `code` is valid for, allowing one step of clock drift
+/// either way.
+pub fn matching_step(secret: &str, username: &str, code: &str) -> Option<i64> {
+    let totp = totp(secret, username)?;
+    let code = code.trim();
+    let now = Utc::now().timestamp() as u64;
+
+    [now - STEP_SECS, now, now + STEP_SECS]
+        .into_iter()
+        .find(|time| totp.generate(*time) == code)
+        .map(|time| (time / STEP_SECS) as i64)
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// New recovery codes, as (codes to show the user once, hashes to store).
+pub fn new_recovery_codes() -> (Vec<String>, Vec<String>) {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let raw = &random_token()[..10];
+            let code = format!("{}-{}", &raw[..5], &raw[5..]);
+
+            (code, hash_token(raw))
+        })
+        .unzip()
+}
+
+/// Checks `code` as a TOTP code of `user` and else as one of their recovery
+/// codes. Records what was used on `user`, which the caller has to save.
+pub fn verify_second_factor(user: &mut User, code: &str) -> bool {
+    if let Some(secret) = &user.totp_secret {
+        if let Some(step) = matching_step(secret, &user.username, code) {
+            if user.totp_last_step.is_some_and(|last| step <= last) {
+                return false;
+            }
+
+            user.totp_last_step = Some(step);
+            return true;
+        }
+    }
+
+    let hash = hash_token(&normalize_recovery_code(code));
+
+    match user
+        .recovery_codes
+        .iter()
+        .position(|stored| *stored == hash)
+    {
+        Some(idx) => {
+            user.recovery_codes.remove(idx);
+            true
+        }
+        None => false,
+    }
+}
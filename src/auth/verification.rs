@@ -0,0 +1,179 @@
+use std::{env, sync::Arc};
+
+use chrono::{prelude::*, Duration};
+use hyper::StatusCode;
+use once_cell::sync::Lazy;
+
+use crate::{
+    db::{
+        connect::DbState,
+        models::{Errors, TokenPurpose, User},
+    },
+    mail::mailer::{send_in_background, Email, Mailer, APP_URL},
+};
+
+use super::one_time::{consume_one_time_token, issue_one_time_token};
+
+/// Lifetime of an email verification token in seconds,
+/// `EMAIL_VERIFICATION_TTL` (default 24 hours).
+pub static EMAIL_VERIFICATION_TTL: Lazy<i64> =
+    Lazy::new(|| match env::var("EMAIL_VERIFICATION_TTL") {
+        Ok(res) => match res.parse() {
+            Ok(ttl) => ttl,
+            Err(_) => panic!("Error: EMAIL_VERIFICATION_TTL must be a number of seconds"),
+        },
+        Err(_) => 24 * 60 * 60,
+    });
+
+/// Minimum time between two verification emails to the same account.
+const RESEND_COOLDOWN_SECS: i64 = 60;
+
+/// Features that can be limited to accounts with a verified email.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Feature {
+    PasswordReset,
+}
+
+/// Features listed in `REQUIRE_VERIFIED_EMAIL`, comma separated, e.g.
+/// `password_reset`. None by default, users registered before emails were
+/// verified would otherwise lose them.
+static VERIFIED_EMAIL_FEATURES: Lazy<Vec<Feature>> =
+    Lazy::new(|| match env::var("REQUIRE_VERIFIED_EMAIL") {
+        Ok(res) => res
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty() && name != "none")
+            .map(|name| match name.as_str() {
+                "password_reset" => Feature::PasswordReset,
+                other => panic!("Error: unknown REQUIRE_VERIFIED_EMAIL feature {:?}", other),
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    });
+
+/// Whether `user` may use `feature` given the state of their email.
+pub fn allows(user: &User, feature: Feature) -> bool {
+    user.email_verified || !VERIFIED_EMAIL_FEATURES.contains(&feature)
+}
+
+/// Mails `user` a link to confirm their email, replacing any earlier one.
+/// Does nothing when there is no email or it is already verified.
+pub async fn send_verification_email(
+    state: &DbState,
+    mailer: &Arc<dyn Mailer>,
+    user: &User,
+) -> Result<(), Errors> {
+    let to = match &user.email {
+        Some(email) if !user.email_verified => email.clone(),
+        _ => return Ok(()),
+    };
+
+    state
+        .tokens
+        .delete_user_one_time_tokens(&user.user_id, TokenPurpose::EmailVerification)
+        .await?;
+
+    let token = issue_one_time_token(
+        state,
+        &user.user_id,
+        TokenPurpose::EmailVerification,
+        *EMAIL_VERIFICATION_TTL,
+    )
+    .await?;
+
+    let link = match APP_URL.as_ref() {
+        Some(url) => format!("{}/verify-email?token={}", url, token),
+        None => format!("Verification code: {}", token),
+    };
+
+    send_in_background(
+        mailer,
+        Email {
+            to,
+            subject: String::from("Confirm your Note4Keep email"),
+            body: format!(
+                "Hi {},\n\nPlease confirm this is the email of your Note4Keep account:\n\n{}\n\n\
+                 If you didn't sign up, you can ignore this email.\n",
+                user.username, link
+            ),
+        },
+    );
+
+    Ok(())
+}
+
+/// Like `send_verification_email`, but rejects users without anything to
+/// verify and asks for a pause between two emails.
+pub async fn resend_verification_email(
+    state: &DbState,
+    mailer: &Arc<dyn Mailer>,
+    user: &User,
+) -> Result<(), StatusCode> {
+    if user.email.is_none() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    if user.email_verified {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let latest = match state
+        .tokens
+        .find_latest_one_time_token(&user.user_id, TokenPurpose::EmailVerification)
+        .await
+    {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(latest) = latest {
+        if Utc::now() - latest.created_at < Duration::try_seconds(RESEND_COOLDOWN_SECS).unwrap() {
+            return Err(StatusCode::TOO_MANY_REQUESTS);
+        }
+    }
+
+    match send_verification_email(state, mailer, user).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Marks the email of the account `token` was issued for as verified.
+pub async fn verify_email(state: &DbState, token: &str) -> Result<(), StatusCode> {
+    let user_id = match consume_one_time_token(state, token, TokenPurpose::EmailVerification).await
+    {
+        Ok(res) => match res {
+            Some(user_id) => user_id,
+            None => return Err(StatusCode::UNAUTHORIZED),
+        },
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut user = match state.users.find_user_by_id(&user_id).await {
+        Ok(res) => match res {
+            Some(user) => user,
+            None => return Err(StatusCode::UNAUTHORIZED),
+        },
+        Err(e) => return Err(e.into()),
+    };
+
+    if user.email.is_none() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    user.email_verified = true;
+
+    if let Err(e) = state.users.update_user(&user).await {
+        return Err(e.into());
+    }
+
+    if let Err(e) = state
+        .tokens
+        .delete_user_one_time_tokens(&user_id, TokenPurpose::EmailVerification)
+        .await
+    {
+        return Err(e.into());
+    }
+
+    Ok(())
+}
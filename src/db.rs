@@ -1,2 +1,6 @@
 pub mod connect;
+pub mod memory;
 pub mod models;
+pub mod mongo;
+pub mod sqlite;
+pub mod store;
@@ -1,18 +1,119 @@
 use dotenv::dotenv;
 use mongodb::{options::ClientOptions, Client, Collection};
-use std::env;
+use std::{env, sync::Arc};
+
+use super::{
+    memory::MemoryStore,
+    mongo::MongoStore,
+    sqlite::SqliteStore,
+    store::{
+        ApiKeyStore, AttemptStore, NoteStore, NotebookStore, OptionsStore, RevisionStore,
+        SessionStore, TokenStore, UserStore,
+    },
+};
 
 pub const USERS: &str = "users";
 pub const NOTES: &str = "notes";
+pub const NOTEBOOKS: &str = "notebooks";
+pub const NOTE_REVISIONS: &str = "noteRevisions";
 pub const USERS_OPTIONS: &str = "usersOptions";
+pub const REFRESH_TOKENS: &str = "refreshTokens";
+pub const REVOKED_TOKENS: &str = "revokedTokens";
+pub const SESSIONS: &str = "sessions";
+pub const ONE_TIME_TOKENS: &str = "oneTimeTokens";
+pub const LOGIN_ATTEMPTS: &str = "loginAttempts";
+pub const API_KEYS: &str = "apiKeys";
 
 pub struct DbState {
-    pub db: mongodb::Client,
+    pub users: Arc<dyn UserStore>,
+    pub notes: Arc<dyn NoteStore>,
+    pub notebooks: Arc<dyn NotebookStore>,
+    pub revisions: Arc<dyn RevisionStore>,
+    pub options: Arc<dyn OptionsStore>,
+    pub tokens: Arc<dyn TokenStore>,
+    pub sessions: Arc<dyn SessionStore>,
+    pub attempts: Arc<dyn AttemptStore>,
+    pub api_keys: Arc<dyn ApiKeyStore>,
+}
+
+impl DbState {
+    pub fn new<S>(store: S) -> DbState
+    where
+        S: UserStore
+            + NoteStore
+            + NotebookStore
+            + RevisionStore
+            + OptionsStore
+            + TokenStore
+            + SessionStore
+            + AttemptStore
+            + ApiKeyStore
+            + 'static,
+    {
+        let store = Arc::new(store);
+
+        DbState {
+            users: store.clone(),
+            notes: store.clone(),
+            notebooks: store.clone(),
+            revisions: store.clone(),
+            options: store.clone(),
+            tokens: store.clone(),
+            sessions: store.clone(),
+            attempts: store.clone(),
+            api_keys: store,
+        }
+    }
+}
+
+/// Storage backend picked with the `DB_BACKEND` env var, defaults to MongoDB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Mongo,
+    Sqlite,
+    Memory,
+}
+
+impl Backend {
+    pub fn from_env() -> Backend {
+        match env::var("DB_BACKEND") {
+            Ok(res) => match res.to_lowercase().as_str() {
+                "mongo" | "mongodb" => Backend::Mongo,
+                "sqlite" => Backend::Sqlite,
+                "memory" => Backend::Memory,
+                other => panic!("Error: unknown DB_BACKEND {:?}", other),
+            },
+            Err(_) => Backend::Mongo,
+        }
+    }
 }
 
-pub async fn connect_db() -> mongodb::Client {
+pub async fn connect_db() -> DbState {
     dotenv().ok();
 
+    match Backend::from_env() {
+        Backend::Mongo => DbState::new(MongoStore {
+            client: connect_mongo().await,
+        }),
+        Backend::Sqlite => {
+            let path = env::var("SQLITE_PATH").unwrap_or(String::from("note4keep.db"));
+
+            match SqliteStore::open(&path) {
+                Ok(store) => {
+                    println!("Database connected ({})", path);
+                    DbState::new(store)
+                }
+                Err(e) => panic!("Error: {:?}", e),
+            }
+        }
+        Backend::Memory => {
+            println!("Using in-memory storage, data will be lost on restart");
+            DbState::new(MemoryStore::default())
+        }
+    }
+}
+
+pub async fn connect_mongo() -> mongodb::Client {
     let uri = match env::var("MONGODB_URI") {
         Ok(key) => key,
         Err(_) => panic!("Error: There is no mongodb uri"),
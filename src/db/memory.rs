@@ -0,0 +1,1029 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use chrono::prelude::*;
+use hyper::StatusCode;
+use regex::RegexBuilder;
+use tokio::sync::RwLock;
+
+use crate::auth::policy::username_key;
+
+use super::{
+    models::{
+        ApiKey, Errors, LoginAttempts, NoteRevision, Notebook, Notes, OneTimeToken, RefreshToken,
+        RevokedToken, Session, TagCount, TokenPurpose, User, UserOptions,
+    },
+    store::{
+        ApiKeyStore, AttemptStore, NoteStore, NotebookStore, OptionsStore, RevisionStore,
+        SessionStore, TokenStore, UserStore,
+    },
+};
+
+/// Keeps every collection in process memory. Nothing survives a restart, it
+/// is meant for local development and for running the API without MongoDB.
+#[derive(Default)]
+pub struct MemoryStore {
+    users: RwLock<Vec<User>>,
+    notes: RwLock<Vec<Notes>>,
+    notebooks: RwLock<Vec<Notebook>>,
+    revisions: RwLock<Vec<NoteRevision>>,
+    options: RwLock<Vec<UserOptions>>,
+    refresh_tokens: RwLock<Vec<RefreshToken>>,
+    revoked_tokens: RwLock<Vec<RevokedToken>>,
+    sessions: RwLock<Vec<Session>>,
+    one_time_tokens: RwLock<Vec<OneTimeToken>>,
+    login_attempts: RwLock<Vec<LoginAttempts>>,
+    api_keys: RwLock<Vec<ApiKey>>,
+}
+
+fn newest_first(mut notes: Vec<Notes>) -> Vec<Notes> {
+    notes.sort_by_key(|n| std::cmp::Reverse(n.date));
+    notes
+}
+
+impl MemoryStore {
+    /// Deletes the notes matching `filter` and their revisions.
+    async fn delete_notes_where<F>(&self, filter: F) -> u64
+    where
+        F: Fn(&Notes) -> bool,
+    {
+        let mut notes = self.notes.write().await;
+        let mut revisions = self.revisions.write().await;
+
+        let deleted: Vec<(String, String)> = notes
+            .iter()
+            .filter(|n| filter(n))
+            .map(|n| (n.user.clone(), n.note_id.clone()))
+            .collect();
+
+        notes.retain(|n| !filter(n));
+        revisions.retain(|r| {
+            !deleted
+                .iter()
+                .any(|(user, note_id)| r.user == *user && r.note_id == *note_id)
+        });
+
+        deleted.len() as u64
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryStore {
+    async fn list_users(&self, skip: u64, limit: u64) -> Result<Vec<User>, Errors> {
+        let mut users = self.users.read().await.clone();
+
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+
+        Ok(users
+            .into_iter()
+            .skip(skip as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn count_users(&self) -> Result<u64, Errors> {
+        Ok(self.users.read().await.len() as u64)
+    }
+
+    async fn find_user_by_id(&self, user_id: &str) -> Result<Option<User>, Errors> {
+        let users = self.users.read().await;
+
+        Ok(users.iter().find(|u| u.user_id == user_id).cloned())
+    }
+
+    async fn find_user_by_username(&self, username: &str) -> Result<Option<User>, Errors> {
+        let key = username_key(username);
+        let users = self.users.read().await;
+
+        Ok(users.iter().find(|u| u.username_key == key).cloned())
+    }
+
+    async fn list_users_by_email(&self, email: &str) -> Result<Vec<User>, Errors> {
+        let users = self.users.read().await;
+
+        Ok(users
+            .iter()
+            .filter(|u| u.email.as_deref() == Some(email))
+            .cloned()
+            .collect())
+    }
+
+    async fn find_user_by_oidc_subject(&self, subject: &str) -> Result<Option<User>, Errors> {
+        let users = self.users.read().await;
+
+        Ok(users
+            .iter()
+            .find(|u| u.oidc_subject.as_deref() == Some(subject))
+            .cloned())
+    }
+
+    async fn list_users_due_for_deletion(&self, now: DateTime<Utc>) -> Result<Vec<User>, Errors> {
+        let users = self.users.read().await;
+
+        Ok(users
+            .iter()
+            .filter(|u| u.deletion_scheduled_at.is_some_and(|date| date <= now))
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_user(&self, user: &User) -> Result<(), Errors> {
+        self.users.write().await.push(user.clone());
+
+        Ok(())
+    }
+
+    async fn update_user(&self, user: &User) -> Result<bool, Errors> {
+        let mut users = self.users.write().await;
+
+        match users.iter_mut().find(|u| u.user_id == user.user_id) {
+            Some(res) => {
+                *res = user.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_user(&self, user_id: &str) -> Result<bool, Errors> {
+        let mut users = self.users.write().await;
+        let before = users.len();
+
+        users.retain(|u| u.user_id != user_id);
+
+        Ok(users.len() < before)
+    }
+}
+
+#[async_trait]
+impl NoteStore for MemoryStore {
+    async fn list_notes(&self, user: &str) -> Result<Vec<Notes>, Errors> {
+        let notes = self.notes.read().await;
+
+        Ok(newest_first(
+            notes
+                .iter()
+                .filter(|n| n.user == user && n.deleted_at.is_none())
+                .cloned()
+                .collect(),
+        ))
+    }
+
+    async fn list_notes_after(
+        &self,
+        user: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Result<Vec<Notes>, Errors> {
+        let notes = self.notes.read().await;
+
+        let mut page: Vec<Notes> = notes
+            .iter()
+            .filter(|n| n.user == user && after.is_none_or(|after| n.note_id.as_str() > after))
+            .cloned()
+            .collect();
+
+        page.sort_by(|a, b| a.note_id.cmp(&b.note_id));
+        page.truncate(limit as usize);
+
+        Ok(page)
+    }
+
+    async fn search_notes(&self, user: &str, pattern: &str) -> Result<Vec<Notes>, Errors> {
+        let regex = match RegexBuilder::new(pattern).case_insensitive(true).build() {
+            Ok(res) => res,
+            Err(_) => return Err(Errors::Status(StatusCode::BAD_REQUEST)),
+        };
+
+        let notes = self.notes.read().await;
+
+        Ok(newest_first(
+            notes
+                .iter()
+                .filter(|n| n.user == user && n.deleted_at.is_none() && regex.is_match(&n.title))
+                .cloned()
+                .collect(),
+        ))
+    }
+
+    async fn list_notes_with_tags(
+        &self,
+        user: &str,
+        tags: &[String],
+        all: bool,
+    ) -> Result<Vec<Notes>, Errors> {
+        let notes = self.notes.read().await;
+
+        Ok(newest_first(
+            notes
+                .iter()
+                .filter(|n| {
+                    n.user == user
+                        && n.deleted_at.is_none()
+                        && if all {
+                            tags.iter().all(|tag| n.tags.contains(tag))
+                        } else {
+                            tags.iter().any(|tag| n.tags.contains(tag))
+                        }
+                })
+                .cloned()
+                .collect(),
+        ))
+    }
+
+    async fn list_notes_in_notebook(
+        &self,
+        user: &str,
+        notebook_id: Option<&str>,
+    ) -> Result<Vec<Notes>, Errors> {
+        let notes = self.notes.read().await;
+
+        Ok(newest_first(
+            notes
+                .iter()
+                .filter(|n| {
+                    n.user == user
+                        && n.deleted_at.is_none()
+                        && n.notebook_id.as_deref() == notebook_id
+                })
+                .cloned()
+                .collect(),
+        ))
+    }
+
+    async fn find_note(&self, user: &str, note_id: &str) -> Result<Option<Notes>, Errors> {
+        let notes = self.notes.read().await;
+
+        Ok(notes
+            .iter()
+            .find(|n| n.user == user && n.note_id == note_id)
+            .cloned())
+    }
+
+    async fn insert_note(&self, note: &Notes) -> Result<(), Errors> {
+        self.notes.write().await.push(note.clone());
+
+        Ok(())
+    }
+
+    async fn update_note(&self, note: &Notes) -> Result<Option<Notes>, Errors> {
+        let mut notes = self.notes.write().await;
+
+        match notes.iter_mut().find(|n| {
+            n.user == note.user
+                && n.note_id == note.note_id
+                && n.revision == note.revision
+                && n.deleted_at.is_none()
+        }) {
+            Some(found) => {
+                found.title = note.title.clone();
+                found.priority = note.priority;
+                found.text = note.text.clone();
+                found.tags = note.tags.clone();
+                found.revision += 1;
+
+                Ok(Some(found.clone()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn trash_note(
+        &self,
+        user: &str,
+        note_id: &str,
+        revision: Option<u64>,
+        now: DateTime<Utc>,
+    ) -> Result<bool, Errors> {
+        let mut notes = self.notes.write().await;
+
+        match notes.iter_mut().find(|n| {
+            n.user == user
+                && n.note_id == note_id
+                && revision.is_none_or(|revision| n.revision == revision)
+                && n.deleted_at.is_none()
+        }) {
+            Some(note) => {
+                note.deleted_at = Some(now);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn trash_all_notes(&self, user: &str, now: DateTime<Utc>) -> Result<u64, Errors> {
+        let mut notes = self.notes.write().await;
+        let mut trashed = 0;
+
+        for note in notes
+            .iter_mut()
+            .filter(|n| n.user == user && n.deleted_at.is_none())
+        {
+            note.deleted_at = Some(now);
+            trashed += 1;
+        }
+
+        Ok(trashed)
+    }
+
+    async fn list_trash(&self, user: &str) -> Result<Vec<Notes>, Errors> {
+        let mut trash: Vec<Notes> = self
+            .notes
+            .read()
+            .await
+            .iter()
+            .filter(|n| n.user == user && n.deleted_at.is_some())
+            .cloned()
+            .collect();
+
+        trash.sort_by_key(|n| std::cmp::Reverse(n.deleted_at));
+
+        Ok(trash)
+    }
+
+    async fn restore_notes(&self, user: &str, note_ids: &[String]) -> Result<u64, Errors> {
+        let mut notes = self.notes.write().await;
+        let mut restored = 0;
+
+        for note in notes
+            .iter_mut()
+            .filter(|n| n.user == user && n.deleted_at.is_some() && note_ids.contains(&n.note_id))
+        {
+            note.deleted_at = None;
+            restored += 1;
+        }
+
+        Ok(restored)
+    }
+
+    async fn empty_trash(&self, user: &str) -> Result<u64, Errors> {
+        Ok(self
+            .delete_notes_where(|n| n.user == user && n.deleted_at.is_some())
+            .await)
+    }
+
+    async fn purge_trash(&self, before: DateTime<Utc>) -> Result<u64, Errors> {
+        Ok(self
+            .delete_notes_where(|n| n.deleted_at.is_some_and(|date| date < before))
+            .await)
+    }
+
+    async fn delete_all_notes(&self, user: &str) -> Result<u64, Errors> {
+        Ok(self.delete_notes_where(|n| n.user == user).await)
+    }
+
+    async fn move_notes(
+        &self,
+        user: &str,
+        note_ids: &[String],
+        notebook_id: Option<&str>,
+    ) -> Result<u64, Errors> {
+        let mut notes = self.notes.write().await;
+        let mut matched = 0;
+
+        for note in notes
+            .iter_mut()
+            .filter(|n| n.user == user && n.deleted_at.is_none() && note_ids.contains(&n.note_id))
+        {
+            note.notebook_id = notebook_id.map(String::from);
+            matched += 1;
+        }
+
+        Ok(matched)
+    }
+
+    async fn move_notebook_notes(
+        &self,
+        user: &str,
+        notebook_ids: &[String],
+        notebook_id: Option<&str>,
+    ) -> Result<u64, Errors> {
+        let mut notes = self.notes.write().await;
+        let mut matched = 0;
+
+        for note in notes.iter_mut().filter(|n| {
+            n.user == user
+                && n.notebook_id
+                    .as_ref()
+                    .is_some_and(|id| notebook_ids.contains(id))
+        }) {
+            note.notebook_id = notebook_id.map(String::from);
+            matched += 1;
+        }
+
+        Ok(matched)
+    }
+
+    async fn trash_notebook_notes(
+        &self,
+        user: &str,
+        notebook_ids: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<u64, Errors> {
+        let mut notes = self.notes.write().await;
+        let mut matched = 0;
+
+        for note in notes.iter_mut().filter(|n| {
+            n.user == user
+                && n.notebook_id
+                    .as_ref()
+                    .is_some_and(|id| notebook_ids.contains(id))
+        }) {
+            note.notebook_id = None;
+            note.deleted_at.get_or_insert(now);
+            matched += 1;
+        }
+
+        Ok(matched)
+    }
+
+    async fn list_tags(&self, user: &str) -> Result<Vec<TagCount>, Errors> {
+        let notes = self.notes.read().await;
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+
+        for tag in notes
+            .iter()
+            .filter(|n| n.user == user && n.deleted_at.is_none())
+            .flat_map(|n| &n.tags)
+        {
+            *counts.entry(tag.clone()).or_default() += 1;
+        }
+
+        Ok(counts
+            .into_iter()
+            .map(|(tag, count)| TagCount { tag, count })
+            .collect())
+    }
+
+    async fn rename_tag(&self, user: &str, from: &str, to: &str) -> Result<Vec<Notes>, Errors> {
+        let mut notes = self.notes.write().await;
+        let mut changed = Vec::new();
+
+        for note in notes
+            .iter_mut()
+            .filter(|n| n.user == user && n.tags.iter().any(|t| t == from))
+        {
+            note.tags.retain(|t| t != from);
+            note.tags.push(to.to_string());
+            note.tags.sort();
+            note.tags.dedup();
+            note.revision += 1;
+            changed.push(note.clone());
+        }
+
+        Ok(changed)
+    }
+
+    async fn remove_tag(&self, user: &str, tag: &str) -> Result<Vec<Notes>, Errors> {
+        let mut notes = self.notes.write().await;
+        let mut changed = Vec::new();
+
+        for note in notes
+            .iter_mut()
+            .filter(|n| n.user == user && n.tags.iter().any(|t| t == tag))
+        {
+            note.tags.retain(|t| t != tag);
+            note.revision += 1;
+            changed.push(note.clone());
+        }
+
+        Ok(changed)
+    }
+}
+
+#[async_trait]
+impl RevisionStore for MemoryStore {
+    async fn insert_revision(&self, revision: &NoteRevision) -> Result<(), Errors> {
+        let mut revisions = self.revisions.write().await;
+
+        if !revisions
+            .iter()
+            .any(|r| r.note_id == revision.note_id && r.revision == revision.revision)
+        {
+            revisions.push(revision.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn list_revisions(&self, user: &str, note_id: &str) -> Result<Vec<NoteRevision>, Errors> {
+        let mut revisions: Vec<NoteRevision> = self
+            .revisions
+            .read()
+            .await
+            .iter()
+            .filter(|r| r.user == user && r.note_id == note_id)
+            .cloned()
+            .collect();
+
+        revisions.sort_by_key(|r| std::cmp::Reverse(r.revision));
+
+        Ok(revisions)
+    }
+
+    async fn find_revision(
+        &self,
+        user: &str,
+        note_id: &str,
+        revision: u64,
+    ) -> Result<Option<NoteRevision>, Errors> {
+        let revisions = self.revisions.read().await;
+
+        Ok(revisions
+            .iter()
+            .find(|r| r.user == user && r.note_id == note_id && r.revision == revision)
+            .cloned())
+    }
+
+    async fn prune_revisions(&self, user: &str, note_id: &str, below: u64) -> Result<u64, Errors> {
+        let mut revisions = self.revisions.write().await;
+        let before = revisions.len();
+
+        revisions.retain(|r| !(r.user == user && r.note_id == note_id && r.revision < below));
+
+        Ok((before - revisions.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl NotebookStore for MemoryStore {
+    async fn list_notebooks(&self, user: &str) -> Result<Vec<Notebook>, Errors> {
+        let mut notebooks: Vec<Notebook> = self
+            .notebooks
+            .read()
+            .await
+            .iter()
+            .filter(|n| n.user == user)
+            .cloned()
+            .collect();
+
+        notebooks.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(notebooks)
+    }
+
+    async fn find_notebook(
+        &self,
+        user: &str,
+        notebook_id: &str,
+    ) -> Result<Option<Notebook>, Errors> {
+        let notebooks = self.notebooks.read().await;
+
+        Ok(notebooks
+            .iter()
+            .find(|n| n.user == user && n.notebook_id == notebook_id)
+            .cloned())
+    }
+
+    async fn insert_notebook(&self, notebook: &Notebook) -> Result<(), Errors> {
+        self.notebooks.write().await.push(notebook.clone());
+
+        Ok(())
+    }
+
+    async fn update_notebook(&self, notebook: &Notebook) -> Result<bool, Errors> {
+        let mut notebooks = self.notebooks.write().await;
+
+        match notebooks
+            .iter_mut()
+            .find(|n| n.user == notebook.user && n.notebook_id == notebook.notebook_id)
+        {
+            Some(found) => {
+                found.name = notebook.name.clone();
+                found.parent_id = notebook.parent_id.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn reparent_notebooks(
+        &self,
+        user: &str,
+        parent_id: &str,
+        new_parent_id: Option<&str>,
+    ) -> Result<u64, Errors> {
+        let mut notebooks = self.notebooks.write().await;
+        let mut matched = 0;
+
+        for notebook in notebooks
+            .iter_mut()
+            .filter(|n| n.user == user && n.parent_id.as_deref() == Some(parent_id))
+        {
+            notebook.parent_id = new_parent_id.map(String::from);
+            matched += 1;
+        }
+
+        Ok(matched)
+    }
+
+    async fn delete_notebooks(&self, user: &str, notebook_ids: &[String]) -> Result<u64, Errors> {
+        let mut notebooks = self.notebooks.write().await;
+        let before = notebooks.len();
+
+        notebooks.retain(|n| !(n.user == user && notebook_ids.contains(&n.notebook_id)));
+
+        Ok((before - notebooks.len()) as u64)
+    }
+
+    async fn delete_user_notebooks(&self, user: &str) -> Result<u64, Errors> {
+        let mut notebooks = self.notebooks.write().await;
+        let before = notebooks.len();
+
+        notebooks.retain(|n| n.user != user);
+
+        Ok((before - notebooks.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl OptionsStore for MemoryStore {
+    async fn find_options(&self, user: &str) -> Result<Option<UserOptions>, Errors> {
+        let options = self.options.read().await;
+
+        Ok(options.iter().find(|o| o.user == user).cloned())
+    }
+
+    async fn insert_options(&self, options: &UserOptions) -> Result<(), Errors> {
+        self.options.write().await.push(options.clone());
+
+        Ok(())
+    }
+
+    async fn update_options(&self, options: &UserOptions) -> Result<bool, Errors> {
+        let mut stored = self.options.write().await;
+
+        match stored.iter_mut().find(|o| o.user == options.user) {
+            Some(res) => {
+                *res = options.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_options(&self, user: &str) -> Result<bool, Errors> {
+        let mut options = self.options.write().await;
+        let before = options.len();
+
+        options.retain(|o| o.user != user);
+
+        Ok(options.len() < before)
+    }
+}
+
+#[async_trait]
+impl TokenStore for MemoryStore {
+    async fn insert_refresh_token(&self, token: &RefreshToken) -> Result<(), Errors> {
+        self.refresh_tokens.write().await.push(token.clone());
+
+        Ok(())
+    }
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, Errors> {
+        let tokens = self.refresh_tokens.read().await;
+
+        Ok(tokens.iter().find(|t| t.token_hash == token_hash).cloned())
+    }
+
+    async fn mark_refresh_token_used(&self, token_hash: &str) -> Result<bool, Errors> {
+        let mut tokens = self.refresh_tokens.write().await;
+
+        match tokens
+            .iter_mut()
+            .find(|t| t.token_hash == token_hash && !t.used)
+        {
+            Some(token) => {
+                token.used = true;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn revoke_token_family(&self, family_id: &str) -> Result<u64, Errors> {
+        let mut tokens = self.refresh_tokens.write().await;
+        let mut revoked = 0;
+
+        for token in tokens.iter_mut().filter(|t| t.family_id == family_id) {
+            token.revoked = true;
+            revoked += 1;
+        }
+
+        Ok(revoked)
+    }
+
+    async fn revoke_user_tokens(&self, user_id: &str) -> Result<u64, Errors> {
+        let mut tokens = self.refresh_tokens.write().await;
+        let mut revoked = 0;
+
+        for token in tokens.iter_mut().filter(|t| t.user_id == user_id) {
+            token.revoked = true;
+            revoked += 1;
+        }
+
+        Ok(revoked)
+    }
+
+    async fn delete_user_refresh_tokens(&self, user_id: &str) -> Result<u64, Errors> {
+        let mut tokens = self.refresh_tokens.write().await;
+        let before = tokens.len();
+
+        tokens.retain(|t| t.user_id != user_id);
+
+        Ok((before - tokens.len()) as u64)
+    }
+
+    async fn delete_expired_refresh_tokens(&self, now: DateTime<Utc>) -> Result<u64, Errors> {
+        let mut tokens = self.refresh_tokens.write().await;
+        let before = tokens.len();
+
+        tokens.retain(|t| t.expires_at >= now);
+
+        Ok((before - tokens.len()) as u64)
+    }
+
+    async fn insert_revocation(&self, revocation: &RevokedToken) -> Result<(), Errors> {
+        self.revoked_tokens.write().await.push(revocation.clone());
+
+        Ok(())
+    }
+
+    async fn is_token_revoked(
+        &self,
+        jti: &str,
+        user_id: &str,
+        issued_at_ms: i64,
+    ) -> Result<bool, Errors> {
+        let revoked = self.revoked_tokens.read().await;
+
+        Ok(revoked.iter().any(|r| {
+            r.jti.as_deref() == Some(jti)
+                || (r.user_id == user_id && r.issued_before_ms.is_some_and(|b| b > issued_at_ms))
+        }))
+    }
+
+    async fn delete_expired_revocations(&self, now: DateTime<Utc>) -> Result<u64, Errors> {
+        let mut revoked = self.revoked_tokens.write().await;
+        let before = revoked.len();
+
+        revoked.retain(|r| r.expires_at >= now);
+
+        Ok((before - revoked.len()) as u64)
+    }
+
+    async fn insert_one_time_token(&self, token: &OneTimeToken) -> Result<(), Errors> {
+        self.one_time_tokens.write().await.push(token.clone());
+
+        Ok(())
+    }
+
+    async fn consume_one_time_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+        now: DateTime<Utc>,
+    ) -> Result<Option<OneTimeToken>, Errors> {
+        let mut tokens = self.one_time_tokens.write().await;
+
+        match tokens.iter_mut().find(|t| {
+            t.token_hash == token_hash && t.purpose == purpose && !t.used && t.expires_at >= now
+        }) {
+            Some(token) => {
+                token.used = true;
+                Ok(Some(token.clone()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn find_latest_one_time_token(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<OneTimeToken>, Errors> {
+        let tokens = self.one_time_tokens.read().await;
+
+        Ok(tokens
+            .iter()
+            .filter(|t| t.user_id == user_id && t.purpose == purpose)
+            .max_by_key(|t| t.created_at)
+            .cloned())
+    }
+
+    async fn delete_user_one_time_tokens(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+    ) -> Result<u64, Errors> {
+        let mut tokens = self.one_time_tokens.write().await;
+        let before = tokens.len();
+
+        tokens.retain(|t| !(t.user_id == user_id && t.purpose == purpose));
+
+        Ok((before - tokens.len()) as u64)
+    }
+
+    async fn delete_expired_one_time_tokens(&self, now: DateTime<Utc>) -> Result<u64, Errors> {
+        let mut tokens = self.one_time_tokens.write().await;
+        let before = tokens.len();
+
+        tokens.retain(|t| t.expires_at >= now);
+
+        Ok((before - tokens.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn insert_session(&self, session: &Session) -> Result<(), Errors> {
+        self.sessions.write().await.push(session.clone());
+
+        Ok(())
+    }
+
+    async fn find_session(&self, session_id: &str) -> Result<Option<Session>, Errors> {
+        let sessions = self.sessions.read().await;
+
+        Ok(sessions
+            .iter()
+            .find(|s| s.session_id == session_id)
+            .cloned())
+    }
+
+    async fn list_sessions(&self, user_id: &str) -> Result<Vec<Session>, Errors> {
+        let mut sessions: Vec<Session> = self
+            .sessions
+            .read()
+            .await
+            .iter()
+            .filter(|s| s.user_id == user_id)
+            .cloned()
+            .collect();
+
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_seen));
+
+        Ok(sessions)
+    }
+
+    async fn update_session(&self, session: &Session) -> Result<bool, Errors> {
+        let mut sessions = self.sessions.write().await;
+
+        match sessions
+            .iter_mut()
+            .find(|s| s.session_id == session.session_id)
+        {
+            Some(stored) => {
+                *stored = session.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_session(&self, user_id: &str, session_id: &str) -> Result<bool, Errors> {
+        let mut sessions = self.sessions.write().await;
+        let before = sessions.len();
+
+        sessions.retain(|s| !(s.user_id == user_id && s.session_id == session_id));
+
+        Ok(sessions.len() < before)
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str) -> Result<u64, Errors> {
+        let mut sessions = self.sessions.write().await;
+        let before = sessions.len();
+
+        sessions.retain(|s| s.user_id != user_id);
+
+        Ok((before - sessions.len()) as u64)
+    }
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64, Errors> {
+        let mut sessions = self.sessions.write().await;
+        let before = sessions.len();
+
+        sessions.retain(|s| s.expires_at >= now);
+
+        Ok((before - sessions.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl AttemptStore for MemoryStore {
+    async fn find_login_attempts(&self, key: &str) -> Result<Option<LoginAttempts>, Errors> {
+        let attempts = self.login_attempts.read().await;
+
+        Ok(attempts.iter().find(|a| a.key == key).cloned())
+    }
+
+    async fn record_login_failure(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<LoginAttempts, Errors> {
+        let mut attempts = self.login_attempts.write().await;
+
+        match attempts.iter_mut().find(|a| a.key == key) {
+            Some(entry) => {
+                entry.failures += 1;
+                entry.last_failure = now;
+                Ok(entry.clone())
+            }
+            None => {
+                let entry = LoginAttempts {
+                    key: key.to_string(),
+                    failures: 1,
+                    last_failure: now,
+                    locked_until: now,
+                };
+
+                attempts.push(entry.clone());
+                Ok(entry)
+            }
+        }
+    }
+
+    async fn lock_login(&self, key: &str, until: DateTime<Utc>) -> Result<(), Errors> {
+        let mut attempts = self.login_attempts.write().await;
+
+        if let Some(entry) = attempts.iter_mut().find(|a| a.key == key) {
+            entry.locked_until = until;
+        }
+
+        Ok(())
+    }
+
+    async fn clear_login_attempts(&self, key: &str) -> Result<(), Errors> {
+        self.login_attempts.write().await.retain(|a| a.key != key);
+
+        Ok(())
+    }
+
+    async fn delete_stale_login_attempts(&self, before: DateTime<Utc>) -> Result<u64, Errors> {
+        let mut attempts = self.login_attempts.write().await;
+        let count = attempts.len();
+
+        attempts.retain(|a| a.last_failure >= before || a.locked_until >= before);
+
+        Ok((count - attempts.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for MemoryStore {
+    async fn insert_api_key(&self, key: &ApiKey) -> Result<(), Errors> {
+        self.api_keys.write().await.push(key.clone());
+
+        Ok(())
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, Errors> {
+        let keys = self.api_keys.read().await;
+
+        Ok(keys.iter().find(|k| k.key_hash == key_hash).cloned())
+    }
+
+    async fn list_api_keys(&self, user_id: &str) -> Result<Vec<ApiKey>, Errors> {
+        let mut keys: Vec<ApiKey> = self
+            .api_keys
+            .read()
+            .await
+            .iter()
+            .filter(|k| k.user_id == user_id)
+            .cloned()
+            .collect();
+
+        keys.sort_by_key(|k| std::cmp::Reverse(k.created_at));
+
+        Ok(keys)
+    }
+
+    async fn touch_api_key(&self, key_id: &str, now: DateTime<Utc>) -> Result<(), Errors> {
+        let mut keys = self.api_keys.write().await;
+
+        if let Some(key) = keys.iter_mut().find(|k| k.key_id == key_id) {
+            key.last_used = Some(now);
+        }
+
+        Ok(())
+    }
+
+    async fn delete_api_key(&self, user_id: &str, key_id: &str) -> Result<bool, Errors> {
+        let mut keys = self.api_keys.write().await;
+        let before = keys.len();
+
+        keys.retain(|k| !(k.user_id == user_id && k.key_id == key_id));
+
+        Ok(keys.len() < before)
+    }
+
+    async fn delete_user_api_keys(&self, user_id: &str) -> Result<u64, Errors> {
+        let mut keys = self.api_keys.write().await;
+        let before = keys.len();
+
+        keys.retain(|k| k.user_id != user_id);
+
+        Ok((before - keys.len()) as u64)
+    }
+}
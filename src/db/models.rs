@@ -1,26 +1,75 @@
-use bson::{doc, serde_helpers::chrono_datetime_as_bson_datetime, Bson};
+use bson::{serde_helpers::chrono_datetime_as_bson_datetime, Bson};
 use chrono::prelude::*;
 use hyper::StatusCode;
 use jsonwebtoken::TokenData;
-use mongodb::Collection;
 use serde::{Deserialize, Serialize};
-use tokio::runtime::Handle;
-use tokio::task;
 
-use crate::{auth::jwt::Claims, StateExtension};
+use crate::auth::{jwt::Claims, permissions::Permission};
 
-use super::connect::{database_coll, USERS_OPTIONS};
+use super::connect::DbState;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct User {
     pub user_id: String,
     pub username: String,
+    /// What `username` is looked up and compared by for uniqueness, see
+    /// `policy::username_key`.
+    #[serde(default)]
+    pub username_key: String,
     pub password: String,
     pub email: Option<String>,
     pub ip: Option<String>,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub disabled: bool,
+    /// Whether the owner of `email` confirmed it. Reset whenever it changes.
+    #[serde(default)]
+    pub email_verified: bool,
+    /// Base32 TOTP secret. Set when enrollment starts, only checked once
+    /// `totp_enabled` is true.
+    #[serde(default)]
+    pub totp_secret: Option<String>,
+    #[serde(default)]
+    pub totp_enabled: bool,
+    /// Time step of the last accepted TOTP code, so a code can't be replayed.
+    #[serde(default)]
+    pub totp_last_step: Option<i64>,
+    /// SHA-256 hashes of the recovery codes not used yet.
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+    /// `sub` of the account at the OpenID Connect provider it is linked to.
+    #[serde(default)]
+    pub oidc_subject: Option<String>,
+    /// When the account gets purged, set while its deletion can still be
+    /// cancelled.
+    #[serde(default, with = "optional_bson_datetime")]
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
 }
 
+/// What of a `User` may leave the server. Never serialize `User` itself in a
+/// response, it carries the password hash.
 #[derive(Debug, Serialize, Deserialize)]
+pub struct UserView {
+    pub user_id: String,
+    pub username: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub totp_enabled: bool,
+    pub role: Role,
+    pub disabled: bool,
+    pub deletion_scheduled_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Notes {
     pub note_id: String,
     pub title: String,
@@ -29,165 +78,312 @@ pub struct Notes {
     pub user: String,
     #[serde(with = "chrono_datetime_as_bson_datetime")]
     pub date: DateTime<Utc>,
+    /// Normalized and without duplicates, see `tags::normalize_tags`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// `None` for notes outside of any notebook.
+    #[serde(default)]
+    pub notebook_id: Option<String>,
+    /// When the note was moved to the trash, `None` for live notes.
+    #[serde(default, with = "optional_bson_datetime")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Number of the latest `NoteRevision`, raised by every update. 0 for
+    /// notes written before revisions were kept.
+    #[serde(default)]
+    pub revision: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The content of a note as saved by one update, `revision` counting up
+/// from 1 for each note.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoteRevision {
+    pub note_id: String,
+    pub user: String,
+    pub revision: u64,
+    pub title: String,
+    pub priority: u32,
+    pub text: String,
+    pub tags: Vec<String>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// A folder of notes. Notebooks nest through `parent_id`, `None` for the
+/// top level ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notebook {
+    pub notebook_id: String,
+    pub user: String,
+    pub name: String,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// A tag and how many notes of the user carry it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: u64,
+}
+
+/// A rotating refresh token. Only the SHA-256 of the token is stored. Every
+/// token issued from the same login shares a `family_id`, so presenting an
+/// already rotated token revokes the whole chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshToken {
+    pub token_hash: String,
+    pub family_id: String,
+    pub user_id: String,
+    pub used: bool,
+    pub revoked: bool,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Denylist entry for access tokens. Either revokes the single token `jti`,
+/// or with `issued_before_ms` every token of `user_id` issued before that
+/// millisecond. Kept until `expires_at`, after which the tokens it covers
+/// expire anyway.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevokedToken {
+    pub jti: Option<String>,
+    pub user_id: String,
+    pub issued_before_ms: Option<i64>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Single-use token mailed to a user, e.g. to reset a password. Like refresh
+/// tokens only the SHA-256 of the token is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OneTimeToken {
+    pub token_hash: String,
+    pub user_id: String,
+    pub purpose: TokenPurpose,
+    pub used: bool,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    PasswordReset,
+    EmailVerification,
+    LoginChallenge,
+    OidcLogin,
+}
+
+/// Failed login attempts counted against `key`, a username or a client IP.
+/// Logins for the key are refused until `locked_until`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginAttempts {
+    pub key: String,
+    pub failures: u32,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub last_failure: DateTime<Utc>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub locked_until: DateTime<Utc>,
+}
+
+/// Long-lived key a user hands to scripts instead of their password, limited
+/// to `scopes`. Only the SHA-256 of the key is stored, `prefix` is kept so
+/// users can tell their keys apart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub key_id: String,
+    pub user_id: String,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub scopes: Vec<Permission>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default, with = "optional_bson_datetime")]
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+/// `chrono_datetime_as_bson_datetime` for optional dates.
+mod optional_bson_datetime {
+    use bson::serde_helpers::chrono_datetime_as_bson_datetime;
+    use chrono::prelude::*;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<DateTime<Utc>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(date) => chrono_datetime_as_bson_datetime::serialize(date, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error> {
+        let value = Option::<bson::DateTime>::deserialize(deserializer)?;
+
+        Ok(value.map(|date| date.to_chrono()))
+    }
+}
+
+/// One login of a user, shared by every refresh token of the family with the
+/// same id and by the access tokens issued from it (their `sid` claim).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub session_id: String,
+    pub user_id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub last_seen: DateTime<Utc>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserOptions {
-    user: String,
-    picture: String,
-    theme: String,
-    filter_order: OrderType,
-    filter_by: FilterType,
+    pub user: String,
+    pub picture: String,
+    pub theme: String,
+    pub filter_order: OrderType,
+    pub filter_by: FilterType,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-enum OrderType {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum OrderType {
     Newest,
     Latest,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-enum FilterType {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FilterType {
     ByName,
     ByPrio,
 }
 
+#[derive(Debug)]
 pub enum Errors {
     Mongo(mongodb::error::Error),
+    Sqlite(rusqlite::Error),
     Status(StatusCode),
 }
 
+impl From<User> for UserView {
+    fn from(user: User) -> Self {
+        UserView {
+            user_id: user.user_id,
+            username: user.username,
+            email: user.email,
+            email_verified: user.email_verified,
+            totp_enabled: user.totp_enabled,
+            role: user.role,
+            disabled: user.disabled,
+            deletion_scheduled_at: user.deletion_scheduled_at.map(|date| date.to_rfc3339()),
+        }
+    }
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::PasswordReset => "password_reset",
+            TokenPurpose::EmailVerification => "email_verification",
+            TokenPurpose::LoginChallenge => "login_challenge",
+            TokenPurpose::OidcLogin => "oidc_login",
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for Errors {
+    fn from(e: mongodb::error::Error) -> Self {
+        Errors::Mongo(e)
+    }
+}
+
+impl From<rusqlite::Error> for Errors {
+    fn from(e: rusqlite::Error) -> Self {
+        Errors::Sqlite(e)
+    }
+}
+
+impl From<Errors> for StatusCode {
+    fn from(e: Errors) -> Self {
+        match e {
+            Errors::Status(status) => status,
+            e => {
+                println!("Error: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
 impl UserOptions {
-    pub fn get(
-        coll_search: Collection<User>,
-        user: String,
-        state: StateExtension,
-    ) -> Result<UserOptions, Errors> {
-        task::block_in_place(move || {
-            Handle::current().block_on(async move {
-                let coll = database_coll::<UserOptions>(&state.db, USERS_OPTIONS).await;
-
-                let filters = doc! {"user_id": &user};
-
-                match coll_search.find_one(filters.clone(), None).await {
-                    Ok(res) => match res {
-                        Some(_) => {}
-                        None => return Err(Errors::Status(StatusCode::NOT_FOUND)),
-                    },
-                    Err(e) => return Err(Errors::Mongo(e)),
-                }
-
-                let filters = doc! {"user": user};
-
-                let user_options = match coll.find_one(filters, None).await {
-                    Ok(res) => match res {
-                        Some(user_options) => user_options,
-                        None => return Err(Errors::Status(StatusCode::NOT_FOUND)),
-                    },
-                    Err(e) => return Err(Errors::Mongo(e)),
-                };
-
-                Ok(user_options)
-            })
-        })
+    pub async fn get(state: &DbState, user: String) -> Result<UserOptions, Errors> {
+        if state.users.find_user_by_id(&user).await?.is_none() {
+            return Err(Errors::Status(StatusCode::NOT_FOUND));
+        }
+
+        match state.options.find_options(&user).await? {
+            Some(user_options) => Ok(user_options),
+            None => Err(Errors::Status(StatusCode::NOT_FOUND)),
+        }
     }
 
-    pub fn create(
-        coll_search: Collection<User>,
-        user: String,
-        state: StateExtension,
-    ) -> Result<String, Errors> {
-        task::block_in_place(move || {
-            Handle::current().block_on(async move {
-                let coll = database_coll::<UserOptions>(&state.db, USERS_OPTIONS).await;
-
-                let filters = doc! {"user_id": &user};
-
-                let exists = match coll_search.find_one(filters.clone(), None).await {
-                    Ok(res) => match res {
-                        Some(res2) => res2,
-                        None => {
-                            println!("Aqui es problema");
-                            return Err(Errors::Status(StatusCode::NOT_FOUND));
-                        }
-                    },
-                    Err(e) => {
-                        println!("Error: {:?}", e);
-                        return Err(Errors::Mongo(e));
-                    }
-                };
-
-                let data = UserOptions {
-                    user: exists.user_id,
-                    picture: String::from("default.jpg"),
-                    theme: String::from("default"),
-                    filter_order: OrderType::Newest,
-                    filter_by: FilterType::ByName,
-                };
-
-                let filters = doc! {"user": user};
-
-                match coll.find_one(filters, None).await {
-                    Ok(res) => match res {
-                        None => {}
-                        Some(_) => return Err(Errors::Status(StatusCode::CONFLICT)),
-                    },
-                    Err(e) => {
-                        println!("Error: {:?}", e);
-                        return Err(Errors::Mongo(e));
-                    }
-                };
-
-                match coll.insert_one(data, None).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error: {:?}", e);
-                        return Err(Errors::Mongo(e));
-                    }
-                };
-
-                Ok(String::from("User options created with no trouble."))
-            })
-        })
+    pub async fn create(state: &DbState, user: String) -> Result<String, Errors> {
+        let exists = match state.users.find_user_by_id(&user).await? {
+            Some(res) => res,
+            None => return Err(Errors::Status(StatusCode::NOT_FOUND)),
+        };
+
+        if state.options.find_options(&user).await?.is_some() {
+            return Err(Errors::Status(StatusCode::CONFLICT));
+        }
+
+        let data = UserOptions {
+            user: exists.user_id,
+            picture: String::from("default.jpg"),
+            theme: String::from("default"),
+            filter_order: OrderType::Newest,
+            filter_by: FilterType::ByName,
+        };
+
+        state.options.insert_options(&data).await?;
+
+        Ok(String::from("User options created with no trouble."))
     }
 
-    pub fn update(
+    pub async fn update(
         claims: TokenData<Claims>,
-        state: StateExtension,
+        state: &DbState,
         update: UserOptions,
     ) -> Result<String, Errors> {
-        task::block_in_place(move || {
-            Handle::current().block_on(async move {
-                let coll = database_coll::<UserOptions>(&state.db, USERS_OPTIONS).await;
-
-                let filters = doc! {"user": claims.claims.userid};
-
-                let exists = match coll.find_one(filters.clone(), None).await {
-                    Ok(res) => match res {
-                        Some(options) => options,
-                        None => return Err(Errors::Status(StatusCode::NOT_FOUND)),
-                    },
-                    Err(e) => {
-                        println!("Error {:?}", e);
-                        return Err(Errors::Mongo(e));
-                    }
-                };
-
-                let data = doc! {"user": exists.user,
-                "picture": update.picture,
-                "theme": update.theme,
-                "filter_order": OrderType::Newest,
-                "filter_by": FilterType::ByName};
-
-                match coll.update_one(filters, data, None).await {
-                    Ok(_) => Ok(String::from("User options updated succesfully")),
-                    Err(e) => {
-                        println!("Error: {:?}", e);
-                        Err(Errors::Mongo(e))
-                    }
-                }
-            })
-        })
+        let exists = match state.options.find_options(&claims.claims.userid).await? {
+            Some(options) => options,
+            None => return Err(Errors::Status(StatusCode::NOT_FOUND)),
+        };
+
+        let data = UserOptions {
+            user: exists.user,
+            picture: update.picture,
+            theme: update.theme,
+            filter_order: OrderType::Newest,
+            filter_by: FilterType::ByName,
+        };
+
+        state.options.update_options(&data).await?;
+
+        Ok(String::from("User options updated succesfully"))
     }
 }
 
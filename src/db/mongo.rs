@@ -0,0 +1,1089 @@
+use async_trait::async_trait;
+use chrono::prelude::*;
+use futures::TryStreamExt;
+use hyper::StatusCode;
+use mongodb::{
+    bson::{doc, Bson, Document},
+    options::{
+        FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReturnDocument, UpdateModifications,
+        UpdateOptions,
+    },
+    Client,
+};
+
+use crate::auth::policy::username_key;
+
+use super::{
+    connect::{
+        database_coll, API_KEYS, LOGIN_ATTEMPTS, NOTEBOOKS, NOTES, NOTE_REVISIONS, ONE_TIME_TOKENS,
+        REFRESH_TOKENS, REVOKED_TOKENS, SESSIONS, USERS, USERS_OPTIONS,
+    },
+    models::{
+        ApiKey, Errors, LoginAttempts, NoteRevision, Notebook, Notes, OneTimeToken, RefreshToken,
+        RevokedToken, Session, TagCount, TokenPurpose, User, UserOptions,
+    },
+    store::{
+        ApiKeyStore, AttemptStore, NoteStore, NotebookStore, OptionsStore, RevisionStore,
+        SessionStore, TokenStore, UserStore,
+    },
+};
+
+pub struct MongoStore {
+    pub client: Client,
+}
+
+impl MongoStore {
+    /// Deletes the notes matching `filters` and their revisions. Revisions
+    /// go first, so a delete that fails halfway can simply be run again.
+    async fn delete_notes_with_revisions(&self, filters: Document) -> Result<u64, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+        let revisions = database_coll::<NoteRevision>(&self.client, NOTE_REVISIONS).await;
+
+        let note_ids = coll.distinct("note_id", filters.clone(), None).await?;
+
+        if note_ids.is_empty() {
+            return Ok(0);
+        }
+
+        revisions
+            .delete_many(doc! {"note_id": {"$in": note_ids}}, None)
+            .await?;
+
+        let res = coll.delete_many(filters, None).await?;
+
+        Ok(res.deleted_count)
+    }
+
+    /// Applies `update` to every note of `user` carrying `tag` and returns
+    /// them as updated. Each note is written on its own, guarded by its
+    /// revision like `update_note`, so a note changed in the meantime is
+    /// read again rather than overwritten. Notes are rewritten one by one,
+    /// not all at once: a failure halfway leaves the rest untouched and the
+    /// same call can be run again.
+    async fn rewrite_tag(
+        &self,
+        user: &str,
+        tag: &str,
+        update: impl Into<UpdateModifications>,
+    ) -> Result<Vec<Notes>, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+        let update = update.into();
+        let opts = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let mut notes: Vec<Notes> = Vec::new();
+
+        loop {
+            let done: Vec<&String> = notes.iter().map(|note| &note.note_id).collect();
+            let filters = doc! {"user": user, "tags": tag, "note_id": {"$nin": done}};
+
+            let cursor = coll.find(filters, None).await?;
+            let pending: Vec<Notes> = cursor.try_collect().await?;
+
+            if pending.is_empty() {
+                return Ok(notes);
+            }
+
+            for note in pending {
+                let filters = doc! {
+                    "note_id": &note.note_id,
+                    "user": user,
+                    "revision": revision_filter(note.revision),
+                };
+
+                // `None` when the note changed since it was read, the next
+                // round reads it again if it still carries the tag.
+                if let Some(updated) = coll
+                    .find_one_and_update(filters, update.clone(), opts.clone())
+                    .await?
+                {
+                    notes.push(updated);
+                }
+            }
+        }
+    }
+}
+
+/// Matches notes at `revision`. Notes written before revisions were kept
+/// have no `revision` field, they are at revision 0.
+fn revision_filter(revision: u64) -> Bson {
+    match revision {
+        0 => Bson::Document(doc! {"$in": [0_i64, null]}),
+        revision => Bson::Int64(revision as i64),
+    }
+}
+
+#[async_trait]
+impl UserStore for MongoStore {
+    async fn list_users(&self, skip: u64, limit: u64) -> Result<Vec<User>, Errors> {
+        let coll = database_coll::<User>(&self.client, USERS).await;
+
+        let opts = FindOptions::builder()
+            .sort(doc! {"username": 1})
+            .skip(skip)
+            .limit(limit as i64)
+            .build();
+        let cursor = coll.find(None, opts).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn count_users(&self) -> Result<u64, Errors> {
+        let coll = database_coll::<User>(&self.client, USERS).await;
+
+        Ok(coll.count_documents(None, None).await?)
+    }
+
+    async fn find_user_by_id(&self, user_id: &str) -> Result<Option<User>, Errors> {
+        let coll = database_coll::<User>(&self.client, USERS).await;
+
+        Ok(coll.find_one(doc! {"user_id": user_id}, None).await?)
+    }
+
+    async fn find_user_by_username(&self, username: &str) -> Result<Option<User>, Errors> {
+        let coll = database_coll::<User>(&self.client, USERS).await;
+
+        // Users created before usernames were normalized have no key yet.
+        let filter = doc! {"$or": [
+            {"username_key": username_key(username)},
+            {"username": username, "username_key": {"$in": [Bson::Null, ""]}},
+        ]};
+
+        Ok(coll.find_one(filter, None).await?)
+    }
+
+    async fn list_users_by_email(&self, email: &str) -> Result<Vec<User>, Errors> {
+        let coll = database_coll::<User>(&self.client, USERS).await;
+
+        let cursor = coll.find(doc! {"email": email}, None).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn find_user_by_oidc_subject(&self, subject: &str) -> Result<Option<User>, Errors> {
+        let coll = database_coll::<User>(&self.client, USERS).await;
+
+        Ok(coll.find_one(doc! {"oidc_subject": subject}, None).await?)
+    }
+
+    async fn list_users_due_for_deletion(&self, now: DateTime<Utc>) -> Result<Vec<User>, Errors> {
+        let coll = database_coll::<User>(&self.client, USERS).await;
+
+        let cursor = coll
+            .find(
+                doc! {"deletion_scheduled_at": {"$lte": bson::DateTime::from_chrono(now)}},
+                None,
+            )
+            .await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn insert_user(&self, user: &User) -> Result<(), Errors> {
+        let coll = database_coll::<User>(&self.client, USERS).await;
+
+        coll.insert_one(user, None).await?;
+
+        Ok(())
+    }
+
+    async fn update_user(&self, user: &User) -> Result<bool, Errors> {
+        let coll = database_coll::<User>(&self.client, USERS).await;
+
+        let res = coll
+            .replace_one(doc! {"user_id": &user.user_id}, user, None)
+            .await?;
+
+        Ok(res.matched_count > 0)
+    }
+
+    async fn delete_user(&self, user_id: &str) -> Result<bool, Errors> {
+        let coll = database_coll::<User>(&self.client, USERS).await;
+
+        let res = coll.delete_one(doc! {"user_id": user_id}, None).await?;
+
+        Ok(res.deleted_count > 0)
+    }
+}
+
+#[async_trait]
+impl NoteStore for MongoStore {
+    async fn list_notes(&self, user: &str) -> Result<Vec<Notes>, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        let opts = FindOptions::builder().sort(doc! {"date": -1}).build();
+        let cursor = coll
+            .find(doc! {"user": user, "deleted_at": null}, opts)
+            .await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn list_notes_after(
+        &self,
+        user: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Result<Vec<Notes>, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        let mut filter = doc! {"user": user};
+
+        if let Some(after) = after {
+            filter.insert("note_id", doc! {"$gt": after});
+        }
+
+        let opts = FindOptions::builder()
+            .sort(doc! {"note_id": 1})
+            .limit(limit as i64)
+            .build();
+        let cursor = coll.find(filter, opts).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn search_notes(&self, user: &str, pattern: &str) -> Result<Vec<Notes>, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        let formated = mongodb::bson::Regex {
+            pattern: pattern.to_string(),
+            options: String::from("i"),
+        };
+
+        let filters = doc! {"title": formated, "user": user, "deleted_at": null};
+        let opts = FindOptions::builder().sort(doc! {"date": -1}).build();
+        let cursor = coll.find(filters, opts).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn list_notes_with_tags(
+        &self,
+        user: &str,
+        tags: &[String],
+        all: bool,
+    ) -> Result<Vec<Notes>, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        let operator = if all { "$all" } else { "$in" };
+        let filters = doc! {"user": user, "tags": {operator: tags}, "deleted_at": null};
+        let opts = FindOptions::builder().sort(doc! {"date": -1}).build();
+        let cursor = coll.find(filters, opts).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn list_notes_in_notebook(
+        &self,
+        user: &str,
+        notebook_id: Option<&str>,
+    ) -> Result<Vec<Notes>, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        // A null also matches the notes written before notebooks existed.
+        let filters = doc! {"user": user, "notebook_id": notebook_id, "deleted_at": null};
+        let opts = FindOptions::builder().sort(doc! {"date": -1}).build();
+        let cursor = coll.find(filters, opts).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn find_note(&self, user: &str, note_id: &str) -> Result<Option<Notes>, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        Ok(coll
+            .find_one(doc! {"note_id": note_id, "user": user}, None)
+            .await?)
+    }
+
+    async fn insert_note(&self, note: &Notes) -> Result<(), Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        coll.insert_one(note, None).await?;
+
+        Ok(())
+    }
+
+    async fn update_note(&self, note: &Notes) -> Result<Option<Notes>, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        let filters = doc! {
+            "note_id": &note.note_id,
+            "user": &note.user,
+            "revision": revision_filter(note.revision),
+            "deleted_at": null,
+        };
+        let mods = doc! {
+            "$set": {
+                "title": &note.title,
+                "priority": note.priority,
+                "text": &note.text,
+                "tags": &note.tags,
+            },
+            "$inc": {"revision": 1_i64},
+        };
+        let opts = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        Ok(coll.find_one_and_update(filters, mods, opts).await?)
+    }
+
+    async fn trash_note(
+        &self,
+        user: &str,
+        note_id: &str,
+        revision: Option<u64>,
+        now: DateTime<Utc>,
+    ) -> Result<bool, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        let mut filters = doc! {"note_id": note_id, "user": user, "deleted_at": null};
+
+        if let Some(revision) = revision {
+            filters.insert("revision", revision_filter(revision));
+        }
+
+        let res = coll
+            .update_one(
+                filters,
+                doc! {"$set": {"deleted_at": bson::DateTime::from_chrono(now)}},
+                None,
+            )
+            .await?;
+
+        Ok(res.matched_count > 0)
+    }
+
+    async fn trash_all_notes(&self, user: &str, now: DateTime<Utc>) -> Result<u64, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        let res = coll
+            .update_many(
+                doc! {"user": user, "deleted_at": null},
+                doc! {"$set": {"deleted_at": bson::DateTime::from_chrono(now)}},
+                None,
+            )
+            .await?;
+
+        Ok(res.modified_count)
+    }
+
+    async fn list_trash(&self, user: &str) -> Result<Vec<Notes>, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        let opts = FindOptions::builder().sort(doc! {"deleted_at": -1}).build();
+        let cursor = coll
+            .find(doc! {"user": user, "deleted_at": {"$ne": null}}, opts)
+            .await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn restore_notes(&self, user: &str, note_ids: &[String]) -> Result<u64, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        let res = coll
+            .update_many(
+                doc! {"user": user, "note_id": {"$in": note_ids}, "deleted_at": {"$ne": null}},
+                doc! {"$set": {"deleted_at": null}},
+                None,
+            )
+            .await?;
+
+        Ok(res.modified_count)
+    }
+
+    async fn empty_trash(&self, user: &str) -> Result<u64, Errors> {
+        self.delete_notes_with_revisions(doc! {"user": user, "deleted_at": {"$ne": null}})
+            .await
+    }
+
+    async fn purge_trash(&self, before: DateTime<Utc>) -> Result<u64, Errors> {
+        self.delete_notes_with_revisions(
+            doc! {"deleted_at": {"$lt": bson::DateTime::from_chrono(before)}},
+        )
+        .await
+    }
+
+    async fn delete_all_notes(&self, user: &str) -> Result<u64, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+        let revisions = database_coll::<NoteRevision>(&self.client, NOTE_REVISIONS).await;
+
+        revisions.delete_many(doc! {"user": user}, None).await?;
+
+        let res = coll.delete_many(doc! {"user": user}, None).await?;
+
+        Ok(res.deleted_count)
+    }
+
+    async fn move_notes(
+        &self,
+        user: &str,
+        note_ids: &[String],
+        notebook_id: Option<&str>,
+    ) -> Result<u64, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        let res = coll
+            .update_many(
+                doc! {"user": user, "note_id": {"$in": note_ids}, "deleted_at": null},
+                doc! {"$set": {"notebook_id": notebook_id}},
+                None,
+            )
+            .await?;
+
+        Ok(res.matched_count)
+    }
+
+    async fn move_notebook_notes(
+        &self,
+        user: &str,
+        notebook_ids: &[String],
+        notebook_id: Option<&str>,
+    ) -> Result<u64, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        let res = coll
+            .update_many(
+                doc! {"user": user, "notebook_id": {"$in": notebook_ids}},
+                doc! {"$set": {"notebook_id": notebook_id}},
+                None,
+            )
+            .await?;
+
+        Ok(res.matched_count)
+    }
+
+    async fn trash_notebook_notes(
+        &self,
+        user: &str,
+        notebook_ids: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<u64, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        // Notes already in the trash keep their date, so they aren't kept
+        // around longer.
+        let update = vec![doc! {"$set": {
+            "notebook_id": null,
+            "deleted_at": {"$ifNull": ["$deleted_at", bson::DateTime::from_chrono(now)]},
+        }}];
+
+        let res = coll
+            .update_many(
+                doc! {"user": user, "notebook_id": {"$in": notebook_ids}},
+                update,
+                None,
+            )
+            .await?;
+
+        Ok(res.matched_count)
+    }
+
+    async fn list_tags(&self, user: &str) -> Result<Vec<TagCount>, Errors> {
+        let coll = database_coll::<Notes>(&self.client, NOTES).await;
+
+        let pipeline = vec![
+            doc! {"$match": {"user": user, "deleted_at": null}},
+            doc! {"$unwind": "$tags"},
+            doc! {"$group": {"_id": "$tags", "count": {"$sum": 1}}},
+            doc! {"$sort": {"_id": 1}},
+            doc! {"$project": {"_id": 0, "tag": "$_id", "count": 1}},
+        ];
+        let cursor = coll.aggregate(pipeline, None).await?;
+
+        Ok(cursor.with_type::<TagCount>().try_collect().await?)
+    }
+
+    async fn rename_tag(&self, user: &str, from: &str, to: &str) -> Result<Vec<Notes>, Errors> {
+        // A pipeline update, so dropping `from` and adding `to` happen in
+        // the same write of each note.
+        let update = vec![doc! {"$set": {
+            "tags": {"$setUnion": [{"$setDifference": ["$tags", [from]]}, [to]]},
+            "revision": {"$add": [{"$ifNull": ["$revision", 0_i64]}, 1_i64]},
+        }}];
+
+        self.rewrite_tag(user, from, update).await
+    }
+
+    async fn remove_tag(&self, user: &str, tag: &str) -> Result<Vec<Notes>, Errors> {
+        let update = doc! {"$pull": {"tags": tag}, "$inc": {"revision": 1_i64}};
+
+        self.rewrite_tag(user, tag, update).await
+    }
+}
+
+#[async_trait]
+impl RevisionStore for MongoStore {
+    async fn insert_revision(&self, revision: &NoteRevision) -> Result<(), Errors> {
+        let coll = database_coll::<NoteRevision>(&self.client, NOTE_REVISIONS).await;
+
+        let filters = doc! {
+            "note_id": &revision.note_id,
+            "user": &revision.user,
+            "revision": revision.revision as i64,
+        };
+        let mods = doc! {"$setOnInsert": {
+            "title": &revision.title,
+            "priority": revision.priority,
+            "text": &revision.text,
+            "tags": &revision.tags,
+            "created_at": bson::DateTime::from_chrono(revision.created_at),
+        }};
+        let opts = UpdateOptions::builder().upsert(true).build();
+
+        coll.update_one(filters, mods, opts).await?;
+
+        Ok(())
+    }
+
+    async fn list_revisions(&self, user: &str, note_id: &str) -> Result<Vec<NoteRevision>, Errors> {
+        let coll = database_coll::<NoteRevision>(&self.client, NOTE_REVISIONS).await;
+
+        let opts = FindOptions::builder().sort(doc! {"revision": -1}).build();
+        let cursor = coll
+            .find(doc! {"user": user, "note_id": note_id}, opts)
+            .await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn find_revision(
+        &self,
+        user: &str,
+        note_id: &str,
+        revision: u64,
+    ) -> Result<Option<NoteRevision>, Errors> {
+        let coll = database_coll::<NoteRevision>(&self.client, NOTE_REVISIONS).await;
+
+        Ok(coll
+            .find_one(
+                doc! {"user": user, "note_id": note_id, "revision": revision as i64},
+                None,
+            )
+            .await?)
+    }
+
+    async fn prune_revisions(&self, user: &str, note_id: &str, below: u64) -> Result<u64, Errors> {
+        let coll = database_coll::<NoteRevision>(&self.client, NOTE_REVISIONS).await;
+
+        let res = coll
+            .delete_many(
+                doc! {"user": user, "note_id": note_id, "revision": {"$lt": below as i64}},
+                None,
+            )
+            .await?;
+
+        Ok(res.deleted_count)
+    }
+}
+
+#[async_trait]
+impl NotebookStore for MongoStore {
+    async fn list_notebooks(&self, user: &str) -> Result<Vec<Notebook>, Errors> {
+        let coll = database_coll::<Notebook>(&self.client, NOTEBOOKS).await;
+
+        let opts = FindOptions::builder().sort(doc! {"name": 1}).build();
+        let cursor = coll.find(doc! {"user": user}, opts).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn find_notebook(
+        &self,
+        user: &str,
+        notebook_id: &str,
+    ) -> Result<Option<Notebook>, Errors> {
+        let coll = database_coll::<Notebook>(&self.client, NOTEBOOKS).await;
+
+        Ok(coll
+            .find_one(doc! {"notebook_id": notebook_id, "user": user}, None)
+            .await?)
+    }
+
+    async fn insert_notebook(&self, notebook: &Notebook) -> Result<(), Errors> {
+        let coll = database_coll::<Notebook>(&self.client, NOTEBOOKS).await;
+
+        coll.insert_one(notebook, None).await?;
+
+        Ok(())
+    }
+
+    async fn update_notebook(&self, notebook: &Notebook) -> Result<bool, Errors> {
+        let coll = database_coll::<Notebook>(&self.client, NOTEBOOKS).await;
+
+        let res = coll
+            .update_one(
+                doc! {"notebook_id": &notebook.notebook_id, "user": &notebook.user},
+                doc! {"$set": {"name": &notebook.name, "parent_id": &notebook.parent_id}},
+                None,
+            )
+            .await?;
+
+        Ok(res.matched_count > 0)
+    }
+
+    async fn reparent_notebooks(
+        &self,
+        user: &str,
+        parent_id: &str,
+        new_parent_id: Option<&str>,
+    ) -> Result<u64, Errors> {
+        let coll = database_coll::<Notebook>(&self.client, NOTEBOOKS).await;
+
+        let res = coll
+            .update_many(
+                doc! {"user": user, "parent_id": parent_id},
+                doc! {"$set": {"parent_id": new_parent_id}},
+                None,
+            )
+            .await?;
+
+        Ok(res.matched_count)
+    }
+
+    async fn delete_notebooks(&self, user: &str, notebook_ids: &[String]) -> Result<u64, Errors> {
+        let coll = database_coll::<Notebook>(&self.client, NOTEBOOKS).await;
+
+        let res = coll
+            .delete_many(
+                doc! {"user": user, "notebook_id": {"$in": notebook_ids}},
+                None,
+            )
+            .await?;
+
+        Ok(res.deleted_count)
+    }
+
+    async fn delete_user_notebooks(&self, user: &str) -> Result<u64, Errors> {
+        let coll = database_coll::<Notebook>(&self.client, NOTEBOOKS).await;
+
+        let res = coll.delete_many(doc! {"user": user}, None).await?;
+
+        Ok(res.deleted_count)
+    }
+}
+
+#[async_trait]
+impl OptionsStore for MongoStore {
+    async fn find_options(&self, user: &str) -> Result<Option<UserOptions>, Errors> {
+        let coll = database_coll::<UserOptions>(&self.client, USERS_OPTIONS).await;
+
+        Ok(coll.find_one(doc! {"user": user}, None).await?)
+    }
+
+    async fn insert_options(&self, options: &UserOptions) -> Result<(), Errors> {
+        let coll = database_coll::<UserOptions>(&self.client, USERS_OPTIONS).await;
+
+        coll.insert_one(options, None).await?;
+
+        Ok(())
+    }
+
+    async fn update_options(&self, options: &UserOptions) -> Result<bool, Errors> {
+        let coll = database_coll::<UserOptions>(&self.client, USERS_OPTIONS).await;
+
+        let res = coll
+            .replace_one(doc! {"user": &options.user}, options, None)
+            .await?;
+
+        Ok(res.matched_count > 0)
+    }
+
+    async fn delete_options(&self, user: &str) -> Result<bool, Errors> {
+        let coll = database_coll::<UserOptions>(&self.client, USERS_OPTIONS).await;
+
+        let res = coll.delete_one(doc! {"user": user}, None).await?;
+
+        Ok(res.deleted_count > 0)
+    }
+}
+
+#[async_trait]
+impl TokenStore for MongoStore {
+    async fn insert_refresh_token(&self, token: &RefreshToken) -> Result<(), Errors> {
+        let coll = database_coll::<RefreshToken>(&self.client, REFRESH_TOKENS).await;
+
+        coll.insert_one(token, None).await?;
+
+        Ok(())
+    }
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, Errors> {
+        let coll = database_coll::<RefreshToken>(&self.client, REFRESH_TOKENS).await;
+
+        Ok(coll.find_one(doc! {"token_hash": token_hash}, None).await?)
+    }
+
+    async fn mark_refresh_token_used(&self, token_hash: &str) -> Result<bool, Errors> {
+        let coll = database_coll::<RefreshToken>(&self.client, REFRESH_TOKENS).await;
+
+        let res = coll
+            .update_one(
+                doc! {"token_hash": token_hash, "used": false},
+                doc! {"$set": {"used": true}},
+                None,
+            )
+            .await?;
+
+        Ok(res.modified_count > 0)
+    }
+
+    async fn revoke_token_family(&self, family_id: &str) -> Result<u64, Errors> {
+        let coll = database_coll::<RefreshToken>(&self.client, REFRESH_TOKENS).await;
+
+        let res = coll
+            .update_many(
+                doc! {"family_id": family_id},
+                doc! {"$set": {"revoked": true}},
+                None,
+            )
+            .await?;
+
+        Ok(res.modified_count)
+    }
+
+    async fn revoke_user_tokens(&self, user_id: &str) -> Result<u64, Errors> {
+        let coll = database_coll::<RefreshToken>(&self.client, REFRESH_TOKENS).await;
+
+        let res = coll
+            .update_many(
+                doc! {"user_id": user_id},
+                doc! {"$set": {"revoked": true}},
+                None,
+            )
+            .await?;
+
+        Ok(res.modified_count)
+    }
+
+    async fn delete_user_refresh_tokens(&self, user_id: &str) -> Result<u64, Errors> {
+        let coll = database_coll::<RefreshToken>(&self.client, REFRESH_TOKENS).await;
+
+        let res = coll.delete_many(doc! {"user_id": user_id}, None).await?;
+
+        Ok(res.deleted_count)
+    }
+
+    async fn delete_expired_refresh_tokens(&self, now: DateTime<Utc>) -> Result<u64, Errors> {
+        let coll = database_coll::<RefreshToken>(&self.client, REFRESH_TOKENS).await;
+
+        let res = coll
+            .delete_many(
+                doc! {"expires_at": {"$lt": bson::DateTime::from_chrono(now)}},
+                None,
+            )
+            .await?;
+
+        Ok(res.deleted_count)
+    }
+
+    async fn insert_revocation(&self, revocation: &RevokedToken) -> Result<(), Errors> {
+        let coll = database_coll::<RevokedToken>(&self.client, REVOKED_TOKENS).await;
+
+        coll.insert_one(revocation, None).await?;
+
+        Ok(())
+    }
+
+    async fn is_token_revoked(
+        &self,
+        jti: &str,
+        user_id: &str,
+        issued_at_ms: i64,
+    ) -> Result<bool, Errors> {
+        let coll = database_coll::<RevokedToken>(&self.client, REVOKED_TOKENS).await;
+
+        let filters = doc! {"$or": [
+            {"jti": jti},
+            {"user_id": user_id, "issued_before_ms": {"$gt": issued_at_ms}},
+        ]};
+
+        Ok(coll.find_one(filters, None).await?.is_some())
+    }
+
+    async fn delete_expired_revocations(&self, now: DateTime<Utc>) -> Result<u64, Errors> {
+        let coll = database_coll::<RevokedToken>(&self.client, REVOKED_TOKENS).await;
+
+        let res = coll
+            .delete_many(
+                doc! {"expires_at": {"$lt": bson::DateTime::from_chrono(now)}},
+                None,
+            )
+            .await?;
+
+        Ok(res.deleted_count)
+    }
+
+    async fn insert_one_time_token(&self, token: &OneTimeToken) -> Result<(), Errors> {
+        let coll = database_coll::<OneTimeToken>(&self.client, ONE_TIME_TOKENS).await;
+
+        coll.insert_one(token, None).await?;
+
+        Ok(())
+    }
+
+    async fn consume_one_time_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+        now: DateTime<Utc>,
+    ) -> Result<Option<OneTimeToken>, Errors> {
+        let coll = database_coll::<OneTimeToken>(&self.client, ONE_TIME_TOKENS).await;
+
+        let filters = doc! {
+            "token_hash": token_hash,
+            "purpose": purpose.as_str(),
+            "used": false,
+            "expires_at": {"$gte": bson::DateTime::from_chrono(now)},
+        };
+
+        Ok(coll
+            .find_one_and_update(filters, doc! {"$set": {"used": true}}, None)
+            .await?)
+    }
+
+    async fn find_latest_one_time_token(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<OneTimeToken>, Errors> {
+        let coll = database_coll::<OneTimeToken>(&self.client, ONE_TIME_TOKENS).await;
+
+        let opts = FindOneOptions::builder()
+            .sort(doc! {"created_at": -1})
+            .build();
+
+        Ok(coll
+            .find_one(doc! {"user_id": user_id, "purpose": purpose.as_str()}, opts)
+            .await?)
+    }
+
+    async fn delete_user_one_time_tokens(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+    ) -> Result<u64, Errors> {
+        let coll = database_coll::<OneTimeToken>(&self.client, ONE_TIME_TOKENS).await;
+
+        let res = coll
+            .delete_many(doc! {"user_id": user_id, "purpose": purpose.as_str()}, None)
+            .await?;
+
+        Ok(res.deleted_count)
+    }
+
+    async fn delete_expired_one_time_tokens(&self, now: DateTime<Utc>) -> Result<u64, Errors> {
+        let coll = database_coll::<OneTimeToken>(&self.client, ONE_TIME_TOKENS).await;
+
+        let res = coll
+            .delete_many(
+                doc! {"expires_at": {"$lt": bson::DateTime::from_chrono(now)}},
+                None,
+            )
+            .await?;
+
+        Ok(res.deleted_count)
+    }
+}
+
+#[async_trait]
+impl SessionStore for MongoStore {
+    async fn insert_session(&self, session: &Session) -> Result<(), Errors> {
+        let coll = database_coll::<Session>(&self.client, SESSIONS).await;
+
+        coll.insert_one(session, None).await?;
+
+        Ok(())
+    }
+
+    async fn find_session(&self, session_id: &str) -> Result<Option<Session>, Errors> {
+        let coll = database_coll::<Session>(&self.client, SESSIONS).await;
+
+        Ok(coll.find_one(doc! {"session_id": session_id}, None).await?)
+    }
+
+    async fn list_sessions(&self, user_id: &str) -> Result<Vec<Session>, Errors> {
+        let coll = database_coll::<Session>(&self.client, SESSIONS).await;
+
+        let opts = FindOptions::builder().sort(doc! {"last_seen": -1}).build();
+        let cursor = coll.find(doc! {"user_id": user_id}, opts).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn update_session(&self, session: &Session) -> Result<bool, Errors> {
+        let coll = database_coll::<Session>(&self.client, SESSIONS).await;
+
+        let res = coll
+            .replace_one(doc! {"session_id": &session.session_id}, session, None)
+            .await?;
+
+        Ok(res.matched_count > 0)
+    }
+
+    async fn delete_session(&self, user_id: &str, session_id: &str) -> Result<bool, Errors> {
+        let coll = database_coll::<Session>(&self.client, SESSIONS).await;
+
+        let res = coll
+            .delete_one(doc! {"user_id": user_id, "session_id": session_id}, None)
+            .await?;
+
+        Ok(res.deleted_count > 0)
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str) -> Result<u64, Errors> {
+        let coll = database_coll::<Session>(&self.client, SESSIONS).await;
+
+        let res = coll.delete_many(doc! {"user_id": user_id}, None).await?;
+
+        Ok(res.deleted_count)
+    }
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64, Errors> {
+        let coll = database_coll::<Session>(&self.client, SESSIONS).await;
+
+        let res = coll
+            .delete_many(
+                doc! {"expires_at": {"$lt": bson::DateTime::from_chrono(now)}},
+                None,
+            )
+            .await?;
+
+        Ok(res.deleted_count)
+    }
+}
+
+#[async_trait]
+impl AttemptStore for MongoStore {
+    async fn find_login_attempts(&self, key: &str) -> Result<Option<LoginAttempts>, Errors> {
+        let coll = database_coll::<LoginAttempts>(&self.client, LOGIN_ATTEMPTS).await;
+
+        Ok(coll.find_one(doc! {"key": key}, None).await?)
+    }
+
+    async fn record_login_failure(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<LoginAttempts, Errors> {
+        let coll = database_coll::<LoginAttempts>(&self.client, LOGIN_ATTEMPTS).await;
+
+        let now = bson::DateTime::from_chrono(now);
+        let opts = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let res = coll
+            .find_one_and_update(
+                doc! {"key": key},
+                doc! {
+                    "$inc": {"failures": 1},
+                    "$set": {"last_failure": now},
+                    "$setOnInsert": {"locked_until": now},
+                },
+                opts,
+            )
+            .await?;
+
+        match res {
+            Some(attempts) => Ok(attempts),
+            None => Err(Errors::Status(StatusCode::INTERNAL_SERVER_ERROR)),
+        }
+    }
+
+    async fn lock_login(&self, key: &str, until: DateTime<Utc>) -> Result<(), Errors> {
+        let coll = database_coll::<LoginAttempts>(&self.client, LOGIN_ATTEMPTS).await;
+
+        coll.update_one(
+            doc! {"key": key},
+            doc! {"$set": {"locked_until": bson::DateTime::from_chrono(until)}},
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn clear_login_attempts(&self, key: &str) -> Result<(), Errors> {
+        let coll = database_coll::<LoginAttempts>(&self.client, LOGIN_ATTEMPTS).await;
+
+        coll.delete_one(doc! {"key": key}, None).await?;
+
+        Ok(())
+    }
+
+    async fn delete_stale_login_attempts(&self, before: DateTime<Utc>) -> Result<u64, Errors> {
+        let coll = database_coll::<LoginAttempts>(&self.client, LOGIN_ATTEMPTS).await;
+
+        let before = bson::DateTime::from_chrono(before);
+        let res = coll
+            .delete_many(
+                doc! {"last_failure": {"$lt": before}, "locked_until": {"$lt": before}},
+                None,
+            )
+            .await?;
+
+        Ok(res.deleted_count)
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for MongoStore {
+    async fn insert_api_key(&self, key: &ApiKey) -> Result<(), Errors> {
+        let coll = database_coll::<ApiKey>(&self.client, API_KEYS).await;
+
+        coll.insert_one(key, None).await?;
+
+        Ok(())
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, Errors> {
+        let coll = database_coll::<ApiKey>(&self.client, API_KEYS).await;
+
+        Ok(coll.find_one(doc! {"key_hash": key_hash}, None).await?)
+    }
+
+    async fn list_api_keys(&self, user_id: &str) -> Result<Vec<ApiKey>, Errors> {
+        let coll = database_coll::<ApiKey>(&self.client, API_KEYS).await;
+
+        let opts = FindOptions::builder().sort(doc! {"created_at": -1}).build();
+        let cursor = coll.find(doc! {"user_id": user_id}, opts).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn touch_api_key(&self, key_id: &str, now: DateTime<Utc>) -> Result<(), Errors> {
+        let coll = database_coll::<ApiKey>(&self.client, API_KEYS).await;
+
+        coll.update_one(
+            doc! {"key_id": key_id},
+            doc! {"$set": {"last_used": bson::DateTime::from_chrono(now)}},
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_api_key(&self, user_id: &str, key_id: &str) -> Result<bool, Errors> {
+        let coll = database_coll::<ApiKey>(&self.client, API_KEYS).await;
+
+        let res = coll
+            .delete_one(doc! {"user_id": user_id, "key_id": key_id}, None)
+            .await?;
+
+        Ok(res.deleted_count > 0)
+    }
+
+    async fn delete_user_api_keys(&self, user_id: &str) -> Result<u64, Errors> {
+        let coll = database_coll::<ApiKey>(&self.client, API_KEYS).await;
+
+        let res = coll.delete_many(doc! {"user_id": user_id}, None).await?;
+
+        Ok(res.deleted_count)
+    }
+}
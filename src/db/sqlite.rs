@@ -0,0 +1,1872 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::prelude::*;
+use hyper::StatusCode;
+use regex::{Regex, RegexBuilder};
+use rusqlite::{
+    functions::FunctionFlags, params, types::Type, Connection, OptionalExtension, Row, ToSql,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::task;
+
+use crate::auth::policy::username_key;
+
+use super::{
+    models::{
+        ApiKey, Errors, LoginAttempts, NoteRevision, Notebook, Notes, OneTimeToken, RefreshToken,
+        RevokedToken, Session, TagCount, TokenPurpose, User, UserOptions,
+    },
+    store::{
+        ApiKeyStore, AttemptStore, NoteStore, NotebookStore, OptionsStore, RevisionStore,
+        SessionStore, TokenStore, UserStore,
+    },
+};
+
+/// Schema migrations, applied in order. `PRAGMA user_version` stores how many
+/// of them already ran, so only append to this list, never edit an entry.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE users (
+        user_id  TEXT PRIMARY KEY,
+        username TEXT NOT NULL UNIQUE,
+        password TEXT NOT NULL,
+        email    TEXT,
+        ip       TEXT
+    );
+    CREATE TABLE notes (
+        note_id  TEXT PRIMARY KEY,
+        title    TEXT NOT NULL,
+        priority INTEGER NOT NULL,
+        text     TEXT NOT NULL,
+        user     TEXT NOT NULL,
+        date     INTEGER NOT NULL
+    );
+    CREATE INDEX notes_user_date ON notes (user, date DESC);
+    CREATE TABLE users_options (
+        user         TEXT PRIMARY KEY,
+        picture      TEXT NOT NULL,
+        theme        TEXT NOT NULL,
+        filter_order TEXT NOT NULL,
+        filter_by    TEXT NOT NULL
+    );",
+    // 2: roles and disabled accounts
+    "ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
+    ALTER TABLE users ADD COLUMN disabled INTEGER NOT NULL DEFAULT 0;",
+    // 3: refresh tokens
+    "CREATE TABLE refresh_tokens (
+        token_hash TEXT PRIMARY KEY,
+        family_id  TEXT NOT NULL,
+        user_id    TEXT NOT NULL,
+        used       INTEGER NOT NULL,
+        revoked    INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+    CREATE INDEX refresh_tokens_family ON refresh_tokens (family_id);
+    CREATE INDEX refresh_tokens_user ON refresh_tokens (user_id);",
+    // 4: access token denylist
+    "CREATE TABLE revoked_tokens (
+        jti              TEXT,
+        user_id          TEXT NOT NULL,
+        issued_before_ms INTEGER,
+        expires_at       INTEGER NOT NULL
+    );
+    CREATE INDEX revoked_tokens_jti ON revoked_tokens (jti);
+    CREATE INDEX revoked_tokens_user ON revoked_tokens (user_id);",
+    // 5: login sessions
+    "CREATE TABLE sessions (
+        session_id TEXT PRIMARY KEY,
+        user_id    TEXT NOT NULL,
+        user_agent TEXT,
+        ip         TEXT,
+        created_at INTEGER NOT NULL,
+        last_seen  INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+    CREATE INDEX sessions_user ON sessions (user_id, last_seen DESC);",
+    // 6: single-use mailed tokens
+    "CREATE TABLE one_time_tokens (
+        token_hash TEXT PRIMARY KEY,
+        user_id    TEXT NOT NULL,
+        purpose    TEXT NOT NULL,
+        used       INTEGER NOT NULL,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+    CREATE INDEX one_time_tokens_user ON one_time_tokens (user_id, purpose);",
+    // 7: email verification
+    "ALTER TABLE users ADD COLUMN email_verified INTEGER NOT NULL DEFAULT 0;",
+    // 8: TOTP two-factor authentication
+    "ALTER TABLE users ADD COLUMN totp_secret TEXT;
+    ALTER TABLE users ADD COLUMN totp_enabled INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE users ADD COLUMN totp_last_step INTEGER;
+    ALTER TABLE users ADD COLUMN recovery_codes TEXT NOT NULL DEFAULT '[]';",
+    // 9: failed login tracking
+    "CREATE TABLE login_attempts (
+        key          TEXT PRIMARY KEY,
+        failures     INTEGER NOT NULL,
+        last_failure INTEGER NOT NULL,
+        locked_until INTEGER NOT NULL
+    );",
+    // 10: normalized usernames. Not UNIQUE, older databases may hold names
+    // differing only in case, new ones are refused by `create_user`.
+    "ALTER TABLE users ADD COLUMN username_key TEXT NOT NULL DEFAULT '';
+    UPDATE users SET username_key = username_key(username);
+    CREATE INDEX users_username_key ON users (username_key);",
+    // 11: personal API keys
+    "CREATE TABLE api_keys (
+        key_id     TEXT PRIMARY KEY,
+        user_id    TEXT NOT NULL,
+        name       TEXT NOT NULL,
+        prefix     TEXT NOT NULL,
+        key_hash   TEXT NOT NULL UNIQUE,
+        scopes     TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        last_used  INTEGER
+    );
+    CREATE INDEX api_keys_user ON api_keys (user_id, created_at DESC);",
+    // 12: OpenID Connect accounts
+    "ALTER TABLE users ADD COLUMN oidc_subject TEXT;
+    CREATE UNIQUE INDEX users_oidc_subject ON users (oidc_subject);
+    CREATE INDEX users_email ON users (email);",
+    // 13: account deletion grace period
+    "ALTER TABLE users ADD COLUMN deletion_scheduled_at INTEGER;
+    CREATE INDEX users_deletion_scheduled_at ON users (deletion_scheduled_at);",
+    // 14: tags on notes, a JSON array
+    "ALTER TABLE notes ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';",
+    // 15: notebooks
+    "CREATE TABLE notebooks (
+        notebook_id TEXT PRIMARY KEY,
+        user        TEXT NOT NULL,
+        name        TEXT NOT NULL,
+        parent_id   TEXT,
+        created_at  INTEGER NOT NULL
+    );
+    CREATE INDEX notebooks_user ON notebooks (user, name);
+    ALTER TABLE notes ADD COLUMN notebook_id TEXT;
+    CREATE INDEX notes_notebook ON notes (user, notebook_id);",
+    // 16: trash
+    "ALTER TABLE notes ADD COLUMN deleted_at INTEGER;
+    CREATE INDEX notes_deleted_at ON notes (deleted_at);",
+    // 17: note revisions
+    "ALTER TABLE notes ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE note_revisions (
+        note_id    TEXT NOT NULL,
+        user       TEXT NOT NULL,
+        revision   INTEGER NOT NULL,
+        title      TEXT NOT NULL,
+        priority   INTEGER NOT NULL,
+        text       TEXT NOT NULL,
+        tags       TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (note_id, revision)
+    );
+    CREATE INDEX note_revisions_user ON note_revisions (user, note_id);",
+];
+
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<SqliteStore, Errors> {
+        let mut conn = Connection::open(path)?;
+
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        register_regexp(&conn)?;
+        register_username_key(&conn)?;
+        migrate(&mut conn)?;
+
+        Ok(SqliteStore {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Runs `f` with the connection on the blocking thread pool.
+    async fn call<T, F>(&self, f: F) -> Result<T, Errors>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+
+        let res = task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut conn)
+        })
+        .await;
+
+        match res {
+            Ok(res) => Ok(res?),
+            Err(e) => {
+                println!("Error: {:?}", e);
+                Err(Errors::Status(StatusCode::INTERNAL_SERVER_ERROR))
+            }
+        }
+    }
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let current: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", version + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// SQLite ships the REGEXP operator without an implementation, back it with
+/// the regex crate so title searches behave like the Mongo `$regex` ones.
+fn register_regexp(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let regex: Arc<Regex> = ctx.get_or_create_aux(0, |vr| {
+                RegexBuilder::new(vr.as_str()?)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| rusqlite::Error::UserFunctionError(Box::new(e)))
+            })?;
+
+            let text = ctx.get::<String>(1)?;
+
+            Ok(regex.is_match(&text))
+        },
+    )
+}
+
+/// Exposes `policy::username_key` to the migration filling `username_key`.
+fn register_username_key(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "username_key",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(username_key(&ctx.get::<String>(0)?)),
+    )
+}
+
+fn to_text<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(res)) => res,
+        Ok(other) => other.to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+fn from_text<T: DeserializeOwned>(row: &Row, idx: usize) -> rusqlite::Result<T> {
+    let text: String = row.get(idx)?;
+
+    serde_json::from_value(serde_json::Value::String(text))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(e)))
+}
+
+fn json_from_row<T: DeserializeOwned>(row: &Row, idx: usize) -> rusqlite::Result<T> {
+    let text: String = row.get(idx)?;
+
+    serde_json::from_str(&text)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(e)))
+}
+
+fn date_from_row(row: &Row, idx: usize) -> rusqlite::Result<DateTime<Utc>> {
+    let millis: i64 = row.get(idx)?;
+
+    match DateTime::<Utc>::from_timestamp_millis(millis) {
+        Some(res) => Ok(res),
+        None => Err(rusqlite::Error::IntegralValueOutOfRange(idx, millis)),
+    }
+}
+
+fn optional_date_from_row(row: &Row, idx: usize) -> rusqlite::Result<Option<DateTime<Utc>>> {
+    let millis: Option<i64> = row.get(idx)?;
+
+    match millis {
+        Some(millis) => match DateTime::<Utc>::from_timestamp_millis(millis) {
+            Some(res) => Ok(Some(res)),
+            None => Err(rusqlite::Error::IntegralValueOutOfRange(idx, millis)),
+        },
+        None => Ok(None),
+    }
+}
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        user_id: row.get(0)?,
+        username: row.get(1)?,
+        password: row.get(2)?,
+        email: row.get(3)?,
+        ip: row.get(4)?,
+        role: from_text(row, 5)?,
+        disabled: row.get(6)?,
+        email_verified: row.get(7)?,
+        totp_secret: row.get(8)?,
+        totp_enabled: row.get(9)?,
+        totp_last_step: row.get(10)?,
+        recovery_codes: json_from_row(row, 11)?,
+        username_key: row.get(12)?,
+        oidc_subject: row.get(13)?,
+        deletion_scheduled_at: optional_date_from_row(row, 14)?,
+    })
+}
+
+fn note_from_row(row: &Row) -> rusqlite::Result<Notes> {
+    Ok(Notes {
+        note_id: row.get(0)?,
+        title: row.get(1)?,
+        priority: row.get(2)?,
+        text: row.get(3)?,
+        user: row.get(4)?,
+        date: date_from_row(row, 5)?,
+        tags: json_from_row(row, 6)?,
+        notebook_id: row.get(7)?,
+        deleted_at: optional_date_from_row(row, 8)?,
+        revision: row.get(9)?,
+    })
+}
+
+fn revision_from_row(row: &Row) -> rusqlite::Result<NoteRevision> {
+    Ok(NoteRevision {
+        note_id: row.get(0)?,
+        user: row.get(1)?,
+        revision: row.get(2)?,
+        title: row.get(3)?,
+        priority: row.get(4)?,
+        text: row.get(5)?,
+        tags: json_from_row(row, 6)?,
+        created_at: date_from_row(row, 7)?,
+    })
+}
+
+/// Deletes the notes matching `condition` and their revisions, in one
+/// transaction.
+fn delete_notes_where(
+    conn: &mut Connection,
+    condition: &str,
+    values: &[&dyn ToSql],
+) -> rusqlite::Result<u64> {
+    let tx = conn.transaction()?;
+
+    tx.execute(
+        &format!(
+            "DELETE FROM note_revisions WHERE (user, note_id) IN
+             (SELECT user, note_id FROM notes WHERE {})",
+            condition
+        ),
+        values,
+    )?;
+    let changed = tx.execute(&format!("DELETE FROM notes WHERE {}", condition), values)?;
+
+    tx.commit()?;
+
+    Ok(changed as u64)
+}
+
+fn notebook_from_row(row: &Row) -> rusqlite::Result<Notebook> {
+    Ok(Notebook {
+        notebook_id: row.get(0)?,
+        user: row.get(1)?,
+        name: row.get(2)?,
+        parent_id: row.get(3)?,
+        created_at: date_from_row(row, 4)?,
+    })
+}
+
+/// Replaces `tag` with `replacement` on every note of `user` carrying it, or
+/// just drops it. One transaction, so no note is left half renamed. Returns
+/// the notes changed.
+fn rewrite_tag(
+    conn: &mut Connection,
+    user: &str,
+    tag: &str,
+    replacement: Option<&str>,
+) -> rusqlite::Result<Vec<Notes>> {
+    let tx = conn.transaction()?;
+
+    let notes: Vec<(String, String)> = {
+        let mut stmt = tx.prepare(
+            "SELECT note_id, tags FROM notes WHERE user = ?1
+             AND EXISTS (SELECT 1 FROM json_each(notes.tags) WHERE value = ?2)",
+        )?;
+        let rows = stmt.query_map(params![user, tag], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+
+    let mut changed = Vec::with_capacity(notes.len());
+
+    for (note_id, tags) in &notes {
+        let mut tags: Vec<String> = serde_json::from_str(tags)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(1, Type::Text, Box::new(e)))?;
+
+        tags.retain(|t| t != tag);
+
+        if let Some(replacement) = replacement {
+            tags.push(replacement.to_string());
+            tags.sort();
+            tags.dedup();
+        }
+
+        changed.push(tx.query_row(
+            &format!(
+                "UPDATE notes SET tags = ?1, revision = revision + 1 WHERE note_id = ?2
+                 RETURNING {}",
+                NOTE_COLUMNS
+            ),
+            params![to_text(&tags), note_id],
+            note_from_row,
+        )?);
+    }
+
+    tx.commit()?;
+
+    Ok(changed)
+}
+
+fn options_from_row(row: &Row) -> rusqlite::Result<UserOptions> {
+    Ok(UserOptions {
+        user: row.get(0)?,
+        picture: row.get(1)?,
+        theme: row.get(2)?,
+        filter_order: from_text(row, 3)?,
+        filter_by: from_text(row, 4)?,
+    })
+}
+
+fn refresh_token_from_row(row: &Row) -> rusqlite::Result<RefreshToken> {
+    Ok(RefreshToken {
+        token_hash: row.get(0)?,
+        family_id: row.get(1)?,
+        user_id: row.get(2)?,
+        used: row.get(3)?,
+        revoked: row.get(4)?,
+        created_at: date_from_row(row, 5)?,
+        expires_at: date_from_row(row, 6)?,
+    })
+}
+
+fn session_from_row(row: &Row) -> rusqlite::Result<Session> {
+    Ok(Session {
+        session_id: row.get(0)?,
+        user_id: row.get(1)?,
+        user_agent: row.get(2)?,
+        ip: row.get(3)?,
+        created_at: date_from_row(row, 4)?,
+        last_seen: date_from_row(row, 5)?,
+        expires_at: date_from_row(row, 6)?,
+    })
+}
+
+fn api_key_from_row(row: &Row) -> rusqlite::Result<ApiKey> {
+    Ok(ApiKey {
+        key_id: row.get(0)?,
+        user_id: row.get(1)?,
+        name: row.get(2)?,
+        prefix: row.get(3)?,
+        key_hash: row.get(4)?,
+        scopes: json_from_row(row, 5)?,
+        created_at: date_from_row(row, 6)?,
+        last_used: optional_date_from_row(row, 7)?,
+    })
+}
+
+fn one_time_token_from_row(row: &Row) -> rusqlite::Result<OneTimeToken> {
+    Ok(OneTimeToken {
+        token_hash: row.get(0)?,
+        user_id: row.get(1)?,
+        purpose: from_text(row, 2)?,
+        used: row.get(3)?,
+        created_at: date_from_row(row, 4)?,
+        expires_at: date_from_row(row, 5)?,
+    })
+}
+
+fn login_attempts_from_row(row: &Row) -> rusqlite::Result<LoginAttempts> {
+    Ok(LoginAttempts {
+        key: row.get(0)?,
+        failures: row.get(1)?,
+        last_failure: date_from_row(row, 2)?,
+        locked_until: date_from_row(row, 3)?,
+    })
+}
+
+const USER_COLUMNS: &str = "user_id, username, password, email, ip, role, disabled, \
+    email_verified, totp_secret, totp_enabled, totp_last_step, recovery_codes, username_key, \
+    oidc_subject, deletion_scheduled_at";
+const NOTE_COLUMNS: &str =
+    "note_id, title, priority, text, user, date, tags, notebook_id, deleted_at, revision";
+const REVISION_COLUMNS: &str = "note_id, user, revision, title, priority, text, tags, created_at";
+const NOTEBOOK_COLUMNS: &str = "notebook_id, user, name, parent_id, created_at";
+const OPTIONS_COLUMNS: &str = "user, picture, theme, filter_order, filter_by";
+const REFRESH_TOKEN_COLUMNS: &str =
+    "token_hash, family_id, user_id, used, revoked, created_at, expires_at";
+const ONE_TIME_TOKEN_COLUMNS: &str = "token_hash, user_id, purpose, used, created_at, expires_at";
+const LOGIN_ATTEMPTS_COLUMNS: &str = "key, failures, last_failure, locked_until";
+const API_KEY_COLUMNS: &str =
+    "key_id, user_id, name, prefix, key_hash, scopes, created_at, last_used";
+const SESSION_COLUMNS: &str =
+    "session_id, user_id, user_agent, ip, created_at, last_seen, expires_at";
+
+#[async_trait]
+impl UserStore for SqliteStore {
+    async fn list_users(&self, skip: u64, limit: u64) -> Result<Vec<User>, Errors> {
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM users ORDER BY username LIMIT ?1 OFFSET ?2",
+                USER_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![limit, skip], user_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn count_users(&self) -> Result<u64, Errors> {
+        self.call(|conn| conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0)))
+            .await
+    }
+
+    async fn find_user_by_id(&self, user_id: &str) -> Result<Option<User>, Errors> {
+        let user_id = user_id.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM users WHERE user_id = ?1", USER_COLUMNS),
+                params![user_id],
+                user_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn find_user_by_username(&self, username: &str) -> Result<Option<User>, Errors> {
+        let key = username_key(username);
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM users WHERE username_key = ?1", USER_COLUMNS),
+                params![key],
+                user_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn list_users_by_email(&self, email: &str) -> Result<Vec<User>, Errors> {
+        let email = email.to_string();
+
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM users WHERE email = ?1",
+                USER_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![email], user_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn find_user_by_oidc_subject(&self, subject: &str) -> Result<Option<User>, Errors> {
+        let subject = subject.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM users WHERE oidc_subject = ?1", USER_COLUMNS),
+                params![subject],
+                user_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn list_users_due_for_deletion(&self, now: DateTime<Utc>) -> Result<Vec<User>, Errors> {
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM users WHERE deletion_scheduled_at <= ?1",
+                USER_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![now.timestamp_millis()], user_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn insert_user(&self, user: &User) -> Result<(), Errors> {
+        let user = user.clone();
+
+        self.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO users ({})
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+                    USER_COLUMNS
+                ),
+                params![
+                    user.user_id,
+                    user.username,
+                    user.password,
+                    user.email,
+                    user.ip,
+                    to_text(&user.role),
+                    user.disabled,
+                    user.email_verified,
+                    user.totp_secret,
+                    user.totp_enabled,
+                    user.totp_last_step,
+                    to_text(&user.recovery_codes),
+                    user.username_key,
+                    user.oidc_subject,
+                    user.deletion_scheduled_at
+                        .map(|date| date.timestamp_millis())
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_user(&self, user: &User) -> Result<bool, Errors> {
+        let user = user.clone();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE users SET username = ?1, password = ?2, email = ?3, ip = ?4,
+                 role = ?5, disabled = ?6, email_verified = ?7, totp_secret = ?8,
+                 totp_enabled = ?9, totp_last_step = ?10, recovery_codes = ?11,
+                 username_key = ?12, oidc_subject = ?13, deletion_scheduled_at = ?14
+                 WHERE user_id = ?15",
+                params![
+                    user.username,
+                    user.password,
+                    user.email,
+                    user.ip,
+                    to_text(&user.role),
+                    user.disabled,
+                    user.email_verified,
+                    user.totp_secret,
+                    user.totp_enabled,
+                    user.totp_last_step,
+                    to_text(&user.recovery_codes),
+                    user.username_key,
+                    user.oidc_subject,
+                    user.deletion_scheduled_at
+                        .map(|date| date.timestamp_millis()),
+                    user.user_id
+                ],
+            )?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    async fn delete_user(&self, user_id: &str) -> Result<bool, Errors> {
+        let user_id = user_id.to_string();
+
+        self.call(move |conn| {
+            let changed = conn.execute("DELETE FROM users WHERE user_id = ?1", params![user_id])?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl NoteStore for SqliteStore {
+    async fn list_notes(&self, user: &str) -> Result<Vec<Notes>, Errors> {
+        let user = user.to_string();
+
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM notes WHERE user = ?1 AND deleted_at IS NULL ORDER BY date DESC",
+                NOTE_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![user], note_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn list_notes_after(
+        &self,
+        user: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Result<Vec<Notes>, Errors> {
+        let user = user.to_string();
+        let after = after.unwrap_or_default().to_string();
+
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM notes WHERE user = ?1 AND note_id > ?2
+                 ORDER BY note_id LIMIT ?3",
+                NOTE_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![user, after, limit], note_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn search_notes(&self, user: &str, pattern: &str) -> Result<Vec<Notes>, Errors> {
+        if Regex::new(pattern).is_err() {
+            return Err(Errors::Status(StatusCode::BAD_REQUEST));
+        }
+
+        let user = user.to_string();
+        let pattern = pattern.to_string();
+
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM notes WHERE user = ?1 AND deleted_at IS NULL AND title REGEXP ?2
+                 ORDER BY date DESC",
+                NOTE_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![user, pattern], note_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn list_notes_with_tags(
+        &self,
+        user: &str,
+        tags: &[String],
+        all: bool,
+    ) -> Result<Vec<Notes>, Errors> {
+        let user = user.to_string();
+        let wanted = to_text(&tags);
+        let mut distinct = tags.to_vec();
+        distinct.sort();
+        distinct.dedup();
+
+        // With `all`, a note must carry every wanted tag, so it matches as
+        // many of them as there are.
+        let needed = if all { distinct.len() as i64 } else { 1 };
+
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM notes WHERE user = ?1 AND deleted_at IS NULL
+                 AND (SELECT COUNT(DISTINCT value) FROM json_each(notes.tags)
+                      WHERE value IN (SELECT value FROM json_each(?2))) >= ?3
+                 ORDER BY date DESC",
+                NOTE_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![user, wanted, needed], note_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn list_notes_in_notebook(
+        &self,
+        user: &str,
+        notebook_id: Option<&str>,
+    ) -> Result<Vec<Notes>, Errors> {
+        let user = user.to_string();
+        let notebook_id = notebook_id.map(String::from);
+
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM notes WHERE user = ?1 AND notebook_id IS ?2 AND deleted_at IS NULL
+                 ORDER BY date DESC",
+                NOTE_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![user, notebook_id], note_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn find_note(&self, user: &str, note_id: &str) -> Result<Option<Notes>, Errors> {
+        let user = user.to_string();
+        let note_id = note_id.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM notes WHERE note_id = ?1 AND user = ?2",
+                    NOTE_COLUMNS
+                ),
+                params![note_id, user],
+                note_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn insert_note(&self, note: &Notes) -> Result<(), Errors> {
+        let note = note.clone();
+
+        self.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO notes ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    NOTE_COLUMNS
+                ),
+                params![
+                    note.note_id,
+                    note.title,
+                    note.priority,
+                    note.text,
+                    note.user,
+                    note.date.timestamp_millis(),
+                    to_text(&note.tags),
+                    note.notebook_id,
+                    note.deleted_at.map(|date| date.timestamp_millis()),
+                    note.revision
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_note(&self, note: &Notes) -> Result<Option<Notes>, Errors> {
+        let note = note.clone();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "UPDATE notes SET title = ?1, priority = ?2, text = ?3, tags = ?4,
+                     revision = revision + 1
+                     WHERE note_id = ?5 AND user = ?6 AND revision = ?7
+                     AND deleted_at IS NULL RETURNING {}",
+                    NOTE_COLUMNS
+                ),
+                params![
+                    note.title,
+                    note.priority,
+                    note.text,
+                    to_text(&note.tags),
+                    note.note_id,
+                    note.user,
+                    note.revision
+                ],
+                note_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn trash_note(
+        &self,
+        user: &str,
+        note_id: &str,
+        revision: Option<u64>,
+        now: DateTime<Utc>,
+    ) -> Result<bool, Errors> {
+        let user = user.to_string();
+        let note_id = note_id.to_string();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE notes SET deleted_at = ?1
+                 WHERE note_id = ?2 AND user = ?3 AND (?4 IS NULL OR revision = ?4)
+                 AND deleted_at IS NULL",
+                params![now.timestamp_millis(), note_id, user, revision],
+            )?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    async fn trash_all_notes(&self, user: &str, now: DateTime<Utc>) -> Result<u64, Errors> {
+        let user = user.to_string();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE notes SET deleted_at = ?1 WHERE user = ?2 AND deleted_at IS NULL",
+                params![now.timestamp_millis(), user],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn list_trash(&self, user: &str) -> Result<Vec<Notes>, Errors> {
+        let user = user.to_string();
+
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM notes WHERE user = ?1 AND deleted_at IS NOT NULL
+                 ORDER BY deleted_at DESC",
+                NOTE_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![user], note_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn restore_notes(&self, user: &str, note_ids: &[String]) -> Result<u64, Errors> {
+        let user = user.to_string();
+        let note_ids = to_text(&note_ids);
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE notes SET deleted_at = NULL
+                 WHERE user = ?1 AND deleted_at IS NOT NULL
+                 AND note_id IN (SELECT value FROM json_each(?2))",
+                params![user, note_ids],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn empty_trash(&self, user: &str) -> Result<u64, Errors> {
+        let user = user.to_string();
+
+        self.call(move |conn| {
+            delete_notes_where(conn, "user = ?1 AND deleted_at IS NOT NULL", params![user])
+        })
+        .await
+    }
+
+    async fn purge_trash(&self, before: DateTime<Utc>) -> Result<u64, Errors> {
+        self.call(move |conn| {
+            delete_notes_where(conn, "deleted_at < ?1", params![before.timestamp_millis()])
+        })
+        .await
+    }
+
+    async fn delete_all_notes(&self, user: &str) -> Result<u64, Errors> {
+        let user = user.to_string();
+
+        self.call(move |conn| delete_notes_where(conn, "user = ?1", params![user]))
+            .await
+    }
+
+    async fn move_notes(
+        &self,
+        user: &str,
+        note_ids: &[String],
+        notebook_id: Option<&str>,
+    ) -> Result<u64, Errors> {
+        let user = user.to_string();
+        let note_ids = to_text(&note_ids);
+        let notebook_id = notebook_id.map(String::from);
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE notes SET notebook_id = ?1
+                 WHERE user = ?2 AND deleted_at IS NULL
+                 AND note_id IN (SELECT value FROM json_each(?3))",
+                params![notebook_id, user, note_ids],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn move_notebook_notes(
+        &self,
+        user: &str,
+        notebook_ids: &[String],
+        notebook_id: Option<&str>,
+    ) -> Result<u64, Errors> {
+        let user = user.to_string();
+        let notebook_ids = to_text(&notebook_ids);
+        let notebook_id = notebook_id.map(String::from);
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE notes SET notebook_id = ?1
+                 WHERE user = ?2 AND notebook_id IN (SELECT value FROM json_each(?3))",
+                params![notebook_id, user, notebook_ids],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn trash_notebook_notes(
+        &self,
+        user: &str,
+        notebook_ids: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<u64, Errors> {
+        let user = user.to_string();
+        let notebook_ids = to_text(&notebook_ids);
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE notes SET notebook_id = NULL, deleted_at = COALESCE(deleted_at, ?1)
+                 WHERE user = ?2 AND notebook_id IN (SELECT value FROM json_each(?3))",
+                params![now.timestamp_millis(), user, notebook_ids],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn list_tags(&self, user: &str) -> Result<Vec<TagCount>, Errors> {
+        let user = user.to_string();
+
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT tag.value, COUNT(*) FROM notes, json_each(notes.tags) AS tag
+                 WHERE notes.user = ?1 AND notes.deleted_at IS NULL
+                 GROUP BY tag.value ORDER BY tag.value",
+            )?;
+            let rows = stmt.query_map(params![user], |row| {
+                Ok(TagCount {
+                    tag: row.get(0)?,
+                    count: row.get(1)?,
+                })
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn rename_tag(&self, user: &str, from: &str, to: &str) -> Result<Vec<Notes>, Errors> {
+        let user = user.to_string();
+        let from = from.to_string();
+        let to = to.to_string();
+
+        self.call(move |conn| rewrite_tag(conn, &user, &from, Some(&to)))
+            .await
+    }
+
+    async fn remove_tag(&self, user: &str, tag: &str) -> Result<Vec<Notes>, Errors> {
+        let user = user.to_string();
+        let tag = tag.to_string();
+
+        self.call(move |conn| rewrite_tag(conn, &user, &tag, None))
+            .await
+    }
+}
+
+#[async_trait]
+impl RevisionStore for SqliteStore {
+    async fn insert_revision(&self, revision: &NoteRevision) -> Result<(), Errors> {
+        let revision = revision.clone();
+
+        self.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO note_revisions ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                     ON CONFLICT (note_id, revision) DO NOTHING",
+                    REVISION_COLUMNS
+                ),
+                params![
+                    revision.note_id,
+                    revision.user,
+                    revision.revision,
+                    revision.title,
+                    revision.priority,
+                    revision.text,
+                    to_text(&revision.tags),
+                    revision.created_at.timestamp_millis()
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_revisions(&self, user: &str, note_id: &str) -> Result<Vec<NoteRevision>, Errors> {
+        let user = user.to_string();
+        let note_id = note_id.to_string();
+
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM note_revisions WHERE user = ?1 AND note_id = ?2
+                 ORDER BY revision DESC",
+                REVISION_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![user, note_id], revision_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn find_revision(
+        &self,
+        user: &str,
+        note_id: &str,
+        revision: u64,
+    ) -> Result<Option<NoteRevision>, Errors> {
+        let user = user.to_string();
+        let note_id = note_id.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM note_revisions
+                     WHERE user = ?1 AND note_id = ?2 AND revision = ?3",
+                    REVISION_COLUMNS
+                ),
+                params![user, note_id, revision],
+                revision_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn prune_revisions(&self, user: &str, note_id: &str, below: u64) -> Result<u64, Errors> {
+        let user = user.to_string();
+        let note_id = note_id.to_string();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "DELETE FROM note_revisions WHERE user = ?1 AND note_id = ?2 AND revision < ?3",
+                params![user, note_id, below],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl NotebookStore for SqliteStore {
+    async fn list_notebooks(&self, user: &str) -> Result<Vec<Notebook>, Errors> {
+        let user = user.to_string();
+
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM notebooks WHERE user = ?1 ORDER BY name",
+                NOTEBOOK_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![user], notebook_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn find_notebook(
+        &self,
+        user: &str,
+        notebook_id: &str,
+    ) -> Result<Option<Notebook>, Errors> {
+        let user = user.to_string();
+        let notebook_id = notebook_id.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM notebooks WHERE notebook_id = ?1 AND user = ?2",
+                    NOTEBOOK_COLUMNS
+                ),
+                params![notebook_id, user],
+                notebook_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn insert_notebook(&self, notebook: &Notebook) -> Result<(), Errors> {
+        let notebook = notebook.clone();
+
+        self.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO notebooks ({}) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    NOTEBOOK_COLUMNS
+                ),
+                params![
+                    notebook.notebook_id,
+                    notebook.user,
+                    notebook.name,
+                    notebook.parent_id,
+                    notebook.created_at.timestamp_millis()
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_notebook(&self, notebook: &Notebook) -> Result<bool, Errors> {
+        let notebook = notebook.clone();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE notebooks SET name = ?1, parent_id = ?2
+                 WHERE notebook_id = ?3 AND user = ?4",
+                params![
+                    notebook.name,
+                    notebook.parent_id,
+                    notebook.notebook_id,
+                    notebook.user
+                ],
+            )?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    async fn reparent_notebooks(
+        &self,
+        user: &str,
+        parent_id: &str,
+        new_parent_id: Option<&str>,
+    ) -> Result<u64, Errors> {
+        let user = user.to_string();
+        let parent_id = parent_id.to_string();
+        let new_parent_id = new_parent_id.map(String::from);
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE notebooks SET parent_id = ?1 WHERE user = ?2 AND parent_id = ?3",
+                params![new_parent_id, user, parent_id],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn delete_notebooks(&self, user: &str, notebook_ids: &[String]) -> Result<u64, Errors> {
+        let user = user.to_string();
+        let notebook_ids = to_text(&notebook_ids);
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "DELETE FROM notebooks
+                 WHERE user = ?1 AND notebook_id IN (SELECT value FROM json_each(?2))",
+                params![user, notebook_ids],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn delete_user_notebooks(&self, user: &str) -> Result<u64, Errors> {
+        let user = user.to_string();
+
+        self.call(move |conn| {
+            let changed = conn.execute("DELETE FROM notebooks WHERE user = ?1", params![user])?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl OptionsStore for SqliteStore {
+    async fn find_options(&self, user: &str) -> Result<Option<UserOptions>, Errors> {
+        let user = user.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM users_options WHERE user = ?1",
+                    OPTIONS_COLUMNS
+                ),
+                params![user],
+                options_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn insert_options(&self, options: &UserOptions) -> Result<(), Errors> {
+        let options = options.clone();
+
+        self.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO users_options ({}) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    OPTIONS_COLUMNS
+                ),
+                params![
+                    options.user,
+                    options.picture,
+                    options.theme,
+                    to_text(&options.filter_order),
+                    to_text(&options.filter_by)
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_options(&self, options: &UserOptions) -> Result<bool, Errors> {
+        let options = options.clone();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE users_options SET picture = ?1, theme = ?2, filter_order = ?3,
+                 filter_by = ?4 WHERE user = ?5",
+                params![
+                    options.picture,
+                    options.theme,
+                    to_text(&options.filter_order),
+                    to_text(&options.filter_by),
+                    options.user
+                ],
+            )?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    async fn delete_options(&self, user: &str) -> Result<bool, Errors> {
+        let user = user.to_string();
+
+        self.call(move |conn| {
+            let changed =
+                conn.execute("DELETE FROM users_options WHERE user = ?1", params![user])?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl TokenStore for SqliteStore {
+    async fn insert_refresh_token(&self, token: &RefreshToken) -> Result<(), Errors> {
+        let token = token.clone();
+
+        self.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO refresh_tokens ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    REFRESH_TOKEN_COLUMNS
+                ),
+                params![
+                    token.token_hash,
+                    token.family_id,
+                    token.user_id,
+                    token.used,
+                    token.revoked,
+                    token.created_at.timestamp_millis(),
+                    token.expires_at.timestamp_millis()
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, Errors> {
+        let token_hash = token_hash.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM refresh_tokens WHERE token_hash = ?1",
+                    REFRESH_TOKEN_COLUMNS
+                ),
+                params![token_hash],
+                refresh_token_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn mark_refresh_token_used(&self, token_hash: &str) -> Result<bool, Errors> {
+        let token_hash = token_hash.to_string();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE refresh_tokens SET used = 1 WHERE token_hash = ?1 AND used = 0",
+                params![token_hash],
+            )?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    async fn revoke_token_family(&self, family_id: &str) -> Result<u64, Errors> {
+        let family_id = family_id.to_string();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE refresh_tokens SET revoked = 1 WHERE family_id = ?1",
+                params![family_id],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn revoke_user_tokens(&self, user_id: &str) -> Result<u64, Errors> {
+        let user_id = user_id.to_string();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE refresh_tokens SET revoked = 1 WHERE user_id = ?1",
+                params![user_id],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn delete_user_refresh_tokens(&self, user_id: &str) -> Result<u64, Errors> {
+        let user_id = user_id.to_string();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "DELETE FROM refresh_tokens WHERE user_id = ?1",
+                params![user_id],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn delete_expired_refresh_tokens(&self, now: DateTime<Utc>) -> Result<u64, Errors> {
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "DELETE FROM refresh_tokens WHERE expires_at < ?1",
+                params![now.timestamp_millis()],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn insert_revocation(&self, revocation: &RevokedToken) -> Result<(), Errors> {
+        let revocation = revocation.clone();
+
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO revoked_tokens (jti, user_id, issued_before_ms, expires_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    revocation.jti,
+                    revocation.user_id,
+                    revocation.issued_before_ms,
+                    revocation.expires_at.timestamp_millis()
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn is_token_revoked(
+        &self,
+        jti: &str,
+        user_id: &str,
+        issued_at_ms: i64,
+    ) -> Result<bool, Errors> {
+        let jti = jti.to_string();
+        let user_id = user_id.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = ?1
+                 OR (user_id = ?2 AND issued_before_ms > ?3))",
+                params![jti, user_id, issued_at_ms],
+                |row| row.get(0),
+            )
+        })
+        .await
+    }
+
+    async fn delete_expired_revocations(&self, now: DateTime<Utc>) -> Result<u64, Errors> {
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "DELETE FROM revoked_tokens WHERE expires_at < ?1",
+                params![now.timestamp_millis()],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn insert_one_time_token(&self, token: &OneTimeToken) -> Result<(), Errors> {
+        let token = token.clone();
+
+        self.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO one_time_tokens ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    ONE_TIME_TOKEN_COLUMNS
+                ),
+                params![
+                    token.token_hash,
+                    token.user_id,
+                    token.purpose.as_str(),
+                    token.used,
+                    token.created_at.timestamp_millis(),
+                    token.expires_at.timestamp_millis()
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn consume_one_time_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+        now: DateTime<Utc>,
+    ) -> Result<Option<OneTimeToken>, Errors> {
+        let token_hash = token_hash.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "UPDATE one_time_tokens SET used = 1 WHERE token_hash = ?1 AND purpose = ?2
+                     AND used = 0 AND expires_at >= ?3 RETURNING {}",
+                    ONE_TIME_TOKEN_COLUMNS
+                ),
+                params![token_hash, purpose.as_str(), now.timestamp_millis()],
+                one_time_token_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn find_latest_one_time_token(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<OneTimeToken>, Errors> {
+        let user_id = user_id.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM one_time_tokens WHERE user_id = ?1 AND purpose = ?2
+                     ORDER BY created_at DESC LIMIT 1",
+                    ONE_TIME_TOKEN_COLUMNS
+                ),
+                params![user_id, purpose.as_str()],
+                one_time_token_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn delete_user_one_time_tokens(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+    ) -> Result<u64, Errors> {
+        let user_id = user_id.to_string();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "DELETE FROM one_time_tokens WHERE user_id = ?1 AND purpose = ?2",
+                params![user_id, purpose.as_str()],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn delete_expired_one_time_tokens(&self, now: DateTime<Utc>) -> Result<u64, Errors> {
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "DELETE FROM one_time_tokens WHERE expires_at < ?1",
+                params![now.timestamp_millis()],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl SessionStore for SqliteStore {
+    async fn insert_session(&self, session: &Session) -> Result<(), Errors> {
+        let session = session.clone();
+
+        self.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO sessions ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    SESSION_COLUMNS
+                ),
+                params![
+                    session.session_id,
+                    session.user_id,
+                    session.user_agent,
+                    session.ip,
+                    session.created_at.timestamp_millis(),
+                    session.last_seen.timestamp_millis(),
+                    session.expires_at.timestamp_millis()
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn find_session(&self, session_id: &str) -> Result<Option<Session>, Errors> {
+        let session_id = session_id.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM sessions WHERE session_id = ?1",
+                    SESSION_COLUMNS
+                ),
+                params![session_id],
+                session_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn list_sessions(&self, user_id: &str) -> Result<Vec<Session>, Errors> {
+        let user_id = user_id.to_string();
+
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM sessions WHERE user_id = ?1 ORDER BY last_seen DESC",
+                SESSION_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![user_id], session_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn update_session(&self, session: &Session) -> Result<bool, Errors> {
+        let session = session.clone();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "UPDATE sessions SET user_id = ?1, user_agent = ?2, ip = ?3, created_at = ?4,
+                 last_seen = ?5, expires_at = ?6 WHERE session_id = ?7",
+                params![
+                    session.user_id,
+                    session.user_agent,
+                    session.ip,
+                    session.created_at.timestamp_millis(),
+                    session.last_seen.timestamp_millis(),
+                    session.expires_at.timestamp_millis(),
+                    session.session_id
+                ],
+            )?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    async fn delete_session(&self, user_id: &str, session_id: &str) -> Result<bool, Errors> {
+        let user_id = user_id.to_string();
+        let session_id = session_id.to_string();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "DELETE FROM sessions WHERE user_id = ?1 AND session_id = ?2",
+                params![user_id, session_id],
+            )?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str) -> Result<u64, Errors> {
+        let user_id = user_id.to_string();
+
+        self.call(move |conn| {
+            let changed =
+                conn.execute("DELETE FROM sessions WHERE user_id = ?1", params![user_id])?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64, Errors> {
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "DELETE FROM sessions WHERE expires_at < ?1",
+                params![now.timestamp_millis()],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl AttemptStore for SqliteStore {
+    async fn find_login_attempts(&self, key: &str) -> Result<Option<LoginAttempts>, Errors> {
+        let key = key.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM login_attempts WHERE key = ?1",
+                    LOGIN_ATTEMPTS_COLUMNS
+                ),
+                params![key],
+                login_attempts_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn record_login_failure(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<LoginAttempts, Errors> {
+        let key = key.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "INSERT INTO login_attempts ({0}) VALUES (?1, 1, ?2, ?2)
+                     ON CONFLICT (key) DO UPDATE SET failures = failures + 1, last_failure = ?2
+                     RETURNING {0}",
+                    LOGIN_ATTEMPTS_COLUMNS
+                ),
+                params![key, now.timestamp_millis()],
+                login_attempts_from_row,
+            )
+        })
+        .await
+    }
+
+    async fn lock_login(&self, key: &str, until: DateTime<Utc>) -> Result<(), Errors> {
+        let key = key.to_string();
+
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE login_attempts SET locked_until = ?1 WHERE key = ?2",
+                params![until.timestamp_millis(), key],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn clear_login_attempts(&self, key: &str) -> Result<(), Errors> {
+        let key = key.to_string();
+
+        self.call(move |conn| {
+            conn.execute("DELETE FROM login_attempts WHERE key = ?1", params![key])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_stale_login_attempts(&self, before: DateTime<Utc>) -> Result<u64, Errors> {
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "DELETE FROM login_attempts WHERE last_failure < ?1 AND locked_until < ?1",
+                params![before.timestamp_millis()],
+            )?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for SqliteStore {
+    async fn insert_api_key(&self, key: &ApiKey) -> Result<(), Errors> {
+        let key = key.clone();
+
+        self.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO api_keys ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    API_KEY_COLUMNS
+                ),
+                params![
+                    key.key_id,
+                    key.user_id,
+                    key.name,
+                    key.prefix,
+                    key.key_hash,
+                    to_text(&key.scopes),
+                    key.created_at.timestamp_millis(),
+                    key.last_used.map(|date| date.timestamp_millis())
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, Errors> {
+        let key_hash = key_hash.to_string();
+
+        self.call(move |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM api_keys WHERE key_hash = ?1",
+                    API_KEY_COLUMNS
+                ),
+                params![key_hash],
+                api_key_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn list_api_keys(&self, user_id: &str) -> Result<Vec<ApiKey>, Errors> {
+        let user_id = user_id.to_string();
+
+        self.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM api_keys WHERE user_id = ?1 ORDER BY created_at DESC",
+                API_KEY_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![user_id], api_key_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn touch_api_key(&self, key_id: &str, now: DateTime<Utc>) -> Result<(), Errors> {
+        let key_id = key_id.to_string();
+
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE api_keys SET last_used = ?1 WHERE key_id = ?2",
+                params![now.timestamp_millis(), key_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_api_key(&self, user_id: &str, key_id: &str) -> Result<bool, Errors> {
+        let user_id = user_id.to_string();
+        let key_id = key_id.to_string();
+
+        self.call(move |conn| {
+            let changed = conn.execute(
+                "DELETE FROM api_keys WHERE user_id = ?1 AND key_id = ?2",
+                params![user_id, key_id],
+            )?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    async fn delete_user_api_keys(&self, user_id: &str) -> Result<u64, Errors> {
+        let user_id = user_id.to_string();
+
+        self.call(move |conn| {
+            let changed =
+                conn.execute("DELETE FROM api_keys WHERE user_id = ?1", params![user_id])?;
+            Ok(changed as u64)
+        })
+        .await
+    }
+}
@@ -0,0 +1,335 @@
+use async_trait::async_trait;
+
+use chrono::prelude::*;
+
+use super::models::{
+    ApiKey, Errors, LoginAttempts, NoteRevision, Notebook, Notes, OneTimeToken, RefreshToken,
+    RevokedToken, Session, TagCount, TokenPurpose, User, UserOptions,
+};
+
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    /// A page of users ordered by username.
+    async fn list_users(&self, skip: u64, limit: u64) -> Result<Vec<User>, Errors>;
+
+    async fn count_users(&self) -> Result<u64, Errors>;
+
+    async fn find_user_by_id(&self, user_id: &str) -> Result<Option<User>, Errors>;
+
+    /// The user whose `username_key` matches the key of `username`, so case
+    /// and Unicode representation don't matter.
+    async fn find_user_by_username(&self, username: &str) -> Result<Option<User>, Errors>;
+
+    /// Every user with `email`, emails aren't unique.
+    async fn list_users_by_email(&self, email: &str) -> Result<Vec<User>, Errors>;
+
+    async fn find_user_by_oidc_subject(&self, subject: &str) -> Result<Option<User>, Errors>;
+
+    /// Users whose scheduled deletion is due at `now`.
+    async fn list_users_due_for_deletion(&self, now: DateTime<Utc>) -> Result<Vec<User>, Errors>;
+
+    async fn insert_user(&self, user: &User) -> Result<(), Errors>;
+
+    /// Replaces the stored user with the same `user_id`. Returns false when
+    /// none existed.
+    async fn update_user(&self, user: &User) -> Result<bool, Errors>;
+
+    async fn delete_user(&self, user_id: &str) -> Result<bool, Errors>;
+}
+
+#[async_trait]
+pub trait NoteStore: Send + Sync {
+    /// Every note owned by `user` but those in the trash, newest first. The
+    /// other listings leave out the trash as well, unless told otherwise.
+    async fn list_notes(&self, user: &str) -> Result<Vec<Notes>, Errors>;
+
+    /// Up to `limit` notes owned by `user` ordered by `note_id`, starting
+    /// after the note `after`, to go through every note a batch at a time.
+    /// Trashed notes included.
+    async fn list_notes_after(
+        &self,
+        user: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Result<Vec<Notes>, Errors>;
+
+    /// Notes owned by `user` whose title matches `pattern` (case insensitive
+    /// regex), newest first.
+    async fn search_notes(&self, user: &str, pattern: &str) -> Result<Vec<Notes>, Errors>;
+
+    /// Notes owned by `user` carrying any of `tags`, or all of them when
+    /// `all` is set, newest first.
+    async fn list_notes_with_tags(
+        &self,
+        user: &str,
+        tags: &[String],
+        all: bool,
+    ) -> Result<Vec<Notes>, Errors>;
+
+    /// Notes owned by `user` in the notebook, or outside of any when
+    /// `notebook_id` is `None`, newest first.
+    async fn list_notes_in_notebook(
+        &self,
+        user: &str,
+        notebook_id: Option<&str>,
+    ) -> Result<Vec<Notes>, Errors>;
+
+    /// Finds trashed notes too.
+    async fn find_note(&self, user: &str, note_id: &str) -> Result<Option<Notes>, Errors>;
+
+    async fn insert_note(&self, note: &Notes) -> Result<(), Errors>;
+
+    /// Saves the title, priority, text and tags of `note` and raises its
+    /// revision, only while the stored note is still at `note.revision`.
+    /// Returns the updated note, `None` when no note outside the trash
+    /// matched.
+    async fn update_note(&self, note: &Notes) -> Result<Option<Notes>, Errors>;
+
+    /// Moves the note to the trash, when given only while it is still at
+    /// `revision`. Returns false when no note outside the trash matched.
+    async fn trash_note(
+        &self,
+        user: &str,
+        note_id: &str,
+        revision: Option<u64>,
+        now: DateTime<Utc>,
+    ) -> Result<bool, Errors>;
+
+    async fn trash_all_notes(&self, user: &str, now: DateTime<Utc>) -> Result<u64, Errors>;
+
+    /// Notes of `user` in the trash, last trashed first.
+    async fn list_trash(&self, user: &str) -> Result<Vec<Notes>, Errors>;
+
+    /// Takes the notes `note_ids` of `user` out of the trash. Returns how
+    /// many were in it.
+    async fn restore_notes(&self, user: &str, note_ids: &[String]) -> Result<u64, Errors>;
+
+    /// Deletes every note of `user` in the trash for good. Like every call
+    /// deleting notes, their revisions go with them.
+    async fn empty_trash(&self, user: &str) -> Result<u64, Errors>;
+
+    /// Deletes the notes of every user trashed before `before` for good.
+    async fn purge_trash(&self, before: DateTime<Utc>) -> Result<u64, Errors>;
+
+    /// Deletes every note of `user` for good, trashed or not.
+    async fn delete_all_notes(&self, user: &str) -> Result<u64, Errors>;
+
+    /// Puts the notes `note_ids` of `user` into `notebook_id`, `None` taking
+    /// them out of their notebook. Returns how many notes matched.
+    async fn move_notes(
+        &self,
+        user: &str,
+        note_ids: &[String],
+        notebook_id: Option<&str>,
+    ) -> Result<u64, Errors>;
+
+    /// Moves every note of `user` in one of `notebook_ids` to `notebook_id`,
+    /// trashed ones included.
+    async fn move_notebook_notes(
+        &self,
+        user: &str,
+        notebook_ids: &[String],
+        notebook_id: Option<&str>,
+    ) -> Result<u64, Errors>;
+
+    /// Moves every note of `user` in one of `notebook_ids` to the trash, out
+    /// of their notebook so they can be restored once it is gone.
+    async fn trash_notebook_notes(
+        &self,
+        user: &str,
+        notebook_ids: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<u64, Errors>;
+
+    /// Every tag of `user` with its number of notes, ordered by tag.
+    async fn list_tags(&self, user: &str) -> Result<Vec<TagCount>, Errors>;
+
+    /// Replaces `from` with `to` on every note of `user` in one go, merging
+    /// the two where a note has both, and raises their `revision`. Returns
+    /// the notes changed, as updated.
+    async fn rename_tag(&self, user: &str, from: &str, to: &str) -> Result<Vec<Notes>, Errors>;
+
+    /// Removes `tag` from every note of `user` and raises their `revision`.
+    /// Returns the notes changed, as updated.
+    async fn remove_tag(&self, user: &str, tag: &str) -> Result<Vec<Notes>, Errors>;
+}
+
+#[async_trait]
+pub trait RevisionStore: Send + Sync {
+    /// Saves `revision` unless the note already has one with that number.
+    /// Its content never changes, so recording it again is a no-op.
+    async fn insert_revision(&self, revision: &NoteRevision) -> Result<(), Errors>;
+
+    /// Revisions kept of the note, newest first.
+    async fn list_revisions(&self, user: &str, note_id: &str) -> Result<Vec<NoteRevision>, Errors>;
+
+    async fn find_revision(
+        &self,
+        user: &str,
+        note_id: &str,
+        revision: u64,
+    ) -> Result<Option<NoteRevision>, Errors>;
+
+    /// Deletes the revisions of the note numbered below `below`.
+    async fn prune_revisions(&self, user: &str, note_id: &str, below: u64) -> Result<u64, Errors>;
+}
+
+#[async_trait]
+pub trait NotebookStore: Send + Sync {
+    /// Every notebook of `user`, ordered by name.
+    async fn list_notebooks(&self, user: &str) -> Result<Vec<Notebook>, Errors>;
+
+    async fn find_notebook(
+        &self,
+        user: &str,
+        notebook_id: &str,
+    ) -> Result<Option<Notebook>, Errors>;
+
+    async fn insert_notebook(&self, notebook: &Notebook) -> Result<(), Errors>;
+
+    /// Saves the name and parent. Returns false when no notebook matched.
+    async fn update_notebook(&self, notebook: &Notebook) -> Result<bool, Errors>;
+
+    /// Gives the notebooks of `user` under `parent_id` the parent
+    /// `new_parent_id`.
+    async fn reparent_notebooks(
+        &self,
+        user: &str,
+        parent_id: &str,
+        new_parent_id: Option<&str>,
+    ) -> Result<u64, Errors>;
+
+    async fn delete_notebooks(&self, user: &str, notebook_ids: &[String]) -> Result<u64, Errors>;
+
+    async fn delete_user_notebooks(&self, user: &str) -> Result<u64, Errors>;
+}
+
+#[async_trait]
+pub trait OptionsStore: Send + Sync {
+    async fn find_options(&self, user: &str) -> Result<Option<UserOptions>, Errors>;
+
+    async fn insert_options(&self, options: &UserOptions) -> Result<(), Errors>;
+
+    /// Replaces the options of `options.user`. Returns false when none existed.
+    async fn update_options(&self, options: &UserOptions) -> Result<bool, Errors>;
+
+    async fn delete_options(&self, user: &str) -> Result<bool, Errors>;
+}
+
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    async fn insert_refresh_token(&self, token: &RefreshToken) -> Result<(), Errors>;
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, Errors>;
+
+    /// Flags the token as rotated. Returns false when it was already used, so
+    /// two concurrent refreshes can't both succeed.
+    async fn mark_refresh_token_used(&self, token_hash: &str) -> Result<bool, Errors>;
+
+    async fn revoke_token_family(&self, family_id: &str) -> Result<u64, Errors>;
+
+    async fn revoke_user_tokens(&self, user_id: &str) -> Result<u64, Errors>;
+
+    async fn delete_user_refresh_tokens(&self, user_id: &str) -> Result<u64, Errors>;
+
+    async fn delete_expired_refresh_tokens(&self, now: DateTime<Utc>) -> Result<u64, Errors>;
+
+    async fn insert_revocation(&self, revocation: &RevokedToken) -> Result<(), Errors>;
+
+    /// True when the access token `jti`, issued to `user_id` at
+    /// `issued_at_ms` (unix milliseconds), is covered by a revocation.
+    async fn is_token_revoked(
+        &self,
+        jti: &str,
+        user_id: &str,
+        issued_at_ms: i64,
+    ) -> Result<bool, Errors>;
+
+    async fn delete_expired_revocations(&self, now: DateTime<Utc>) -> Result<u64, Errors>;
+
+    async fn insert_one_time_token(&self, token: &OneTimeToken) -> Result<(), Errors>;
+
+    /// Flags the unused, unexpired token as used and returns it. Returns
+    /// `None` when there is no such token, so each one works only once.
+    async fn consume_one_time_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+        now: DateTime<Utc>,
+    ) -> Result<Option<OneTimeToken>, Errors>;
+
+    /// The most recently issued token of `purpose` for `user_id`.
+    async fn find_latest_one_time_token(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<OneTimeToken>, Errors>;
+
+    async fn delete_user_one_time_tokens(
+        &self,
+        user_id: &str,
+        purpose: TokenPurpose,
+    ) -> Result<u64, Errors>;
+
+    async fn delete_expired_one_time_tokens(&self, now: DateTime<Utc>) -> Result<u64, Errors>;
+}
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn insert_session(&self, session: &Session) -> Result<(), Errors>;
+
+    async fn find_session(&self, session_id: &str) -> Result<Option<Session>, Errors>;
+
+    /// Every session of `user_id`, most recently seen first.
+    async fn list_sessions(&self, user_id: &str) -> Result<Vec<Session>, Errors>;
+
+    /// Replaces the stored session with the same `session_id`. Returns false
+    /// when none existed.
+    async fn update_session(&self, session: &Session) -> Result<bool, Errors>;
+
+    /// Returns false when `user_id` has no session `session_id`.
+    async fn delete_session(&self, user_id: &str, session_id: &str) -> Result<bool, Errors>;
+
+    async fn delete_user_sessions(&self, user_id: &str) -> Result<u64, Errors>;
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64, Errors>;
+}
+
+#[async_trait]
+pub trait AttemptStore: Send + Sync {
+    async fn find_login_attempts(&self, key: &str) -> Result<Option<LoginAttempts>, Errors>;
+
+    /// Counts one more failure for `key` at `now`, creating the entry when
+    /// needed, and returns the updated entry. Concurrent calls never lose a
+    /// failure.
+    async fn record_login_failure(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<LoginAttempts, Errors>;
+
+    async fn lock_login(&self, key: &str, until: DateTime<Utc>) -> Result<(), Errors>;
+
+    async fn clear_login_attempts(&self, key: &str) -> Result<(), Errors>;
+
+    /// Drops entries whose last failure is older than `before` and that are
+    /// no longer locked.
+    async fn delete_stale_login_attempts(&self, before: DateTime<Utc>) -> Result<u64, Errors>;
+}
+
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync {
+    async fn insert_api_key(&self, key: &ApiKey) -> Result<(), Errors>;
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, Errors>;
+
+    /// Every key of `user_id`, newest first.
+    async fn list_api_keys(&self, user_id: &str) -> Result<Vec<ApiKey>, Errors>;
+
+    async fn touch_api_key(&self, key_id: &str, now: DateTime<Utc>) -> Result<(), Errors>;
+
+    /// Returns false when `user_id` has no key `key_id`.
+    async fn delete_api_key(&self, user_id: &str, key_id: &str) -> Result<bool, Errors>;
+
+    async fn delete_user_api_keys(&self, user_id: &str) -> Result<u64, Errors>;
+}
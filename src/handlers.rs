@@ -1,2 +1,15 @@
+pub mod account;
+pub mod admin;
+pub mod api_keys;
+pub mod export;
+pub mod keys;
+pub mod notebooks;
 pub mod notes;
+pub mod oidc;
+pub mod profile;
+pub mod revisions;
+pub mod sessions;
+pub mod tags;
+pub mod trash;
+pub mod two_factor;
 pub mod users;
@@ -0,0 +1,84 @@
+use axum::Json;
+use axum_macros::debug_handler;
+use hyper::StatusCode;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    auth::{
+        deletion::schedule_deletion,
+        extractor::{AuthUser, ClientInfo},
+    },
+    StateExtension,
+};
+
+use super::{
+    admin::{find_user, save_user},
+    profile::confirm_password_and_code,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeleteAccount {
+    password: String,
+    /// Only needed with two-factor authentication enabled.
+    code: Option<String>,
+}
+
+/// Deletes the account of the caller with all their data, right away or
+/// after the grace period.
+#[debug_handler]
+pub async fn delete_account(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    client: ClientInfo,
+    Json(req): Json<DeleteAccount>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    if find_user(&state, &claims.userid)
+        .await?
+        .deletion_scheduled_at
+        .is_some()
+    {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let code = req.code.unwrap_or_default();
+
+    let mut user =
+        confirm_password_and_code(&state, &client, &claims, &req.password, &code).await?;
+
+    match schedule_deletion(&state, &mut user).await {
+        Ok(Some(due)) => Ok((
+            StatusCode::OK,
+            Json(json!(doc! {
+                "response": "Account deletion scheduled, log in and cancel it before then to keep the account",
+                "deletion_scheduled_at": due.to_rfc3339(),
+            })),
+        )),
+        Ok(None) => Ok((
+            StatusCode::OK,
+            Json(json!(doc! {"response": "Account deleted succesfully"})),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[debug_handler]
+pub async fn cancel_account_deletion(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let mut user = find_user(&state, &claims.userid).await?;
+
+    if user.deletion_scheduled_at.is_none() {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    user.deletion_scheduled_at = None;
+    save_user(&state, &user).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {"response": "Account deletion cancelled"})),
+    ))
+}
@@ -0,0 +1,248 @@
+use axum::{
+    extract::{Path, Query},
+    Json,
+};
+use axum_macros::debug_handler;
+use hyper::StatusCode;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::utils::random_id::random_token;
+use crate::{
+    auth::{
+        deletion::purge_user,
+        extractor::AuthUser,
+        password::hash_password,
+        policy::{check_password, policy_response},
+        revoke::{expire_access_tokens, revoke_all_sessions},
+    },
+    db::{
+        connect::DbState,
+        models::{Role, User, UserView},
+    },
+    StateExtension,
+};
+
+const DEFAULT_PER_PAGE: u64 = 20;
+const MAX_PER_PAGE: u64 = 100;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Pagination {
+    page: Option<u64>,
+    per_page: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResetPassword {
+    password: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetRole {
+    role: Role,
+}
+
+pub async fn find_user(state: &DbState, user_id: &str) -> Result<User, StatusCode> {
+    match state.users.find_user_by_id(user_id).await {
+        Ok(res) => match res {
+            Some(user) => Ok(user),
+            None => Err(StatusCode::NOT_FOUND),
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn save_user(state: &DbState, user: &User) -> Result<(), StatusCode> {
+    match state.users.update_user(user).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[debug_handler]
+pub async fn list_users(
+    state: StateExtension,
+    Query(req): Query<Pagination>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let page = req.page.unwrap_or(1).max(1);
+    let per_page = req
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+
+    let users: Vec<UserView> = match state
+        .users
+        .list_users((page - 1) * per_page, per_page)
+        .await
+    {
+        Ok(res) => res.into_iter().map(UserView::from).collect(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let total = match state.users.count_users().await {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "users": users,
+            "page": page,
+            "per_page": per_page,
+            "total": total,
+        })),
+    ))
+}
+
+#[debug_handler]
+pub async fn view_user(
+    state: StateExtension,
+    Path(user_id): Path<String>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let user = find_user(&state, &user_id).await?;
+
+    Ok((StatusCode::OK, Json(json!(UserView::from(user)))))
+}
+
+#[debug_handler]
+pub async fn disable_user(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Path(user_id): Path<String>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    if claims.userid == user_id {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let mut user = find_user(&state, &user_id).await?;
+    user.disabled = true;
+    save_user(&state, &user).await?;
+
+    if let Err(e) = revoke_all_sessions(&state, &user_id).await {
+        return Err(e.into());
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {"response": "User disabled succesfully"})),
+    ))
+}
+
+#[debug_handler]
+pub async fn enable_user(
+    state: StateExtension,
+    Path(user_id): Path<String>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let mut user = find_user(&state, &user_id).await?;
+    user.disabled = false;
+    save_user(&state, &user).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {"response": "User enabled succesfully"})),
+    ))
+}
+
+#[debug_handler]
+pub async fn set_role(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Path(user_id): Path<String>,
+    Json(req): Json<SetRole>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    // Demoting yourself could leave the instance without any admin.
+    if claims.userid == user_id {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let mut user = find_user(&state, &user_id).await?;
+    user.role = req.role;
+    save_user(&state, &user).await?;
+
+    // Tokens carry the role, the user gets the new one with the next refresh.
+    if let Err(e) = expire_access_tokens(&state, &user_id).await {
+        return Err(e.into());
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {"response": "User role updated succesfully"})),
+    ))
+}
+
+#[debug_handler]
+pub async fn delete_user(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Path(user_id): Path<String>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    if claims.userid == user_id {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let user = find_user(&state, &user_id).await?;
+
+    if let Err(e) = purge_user(&state, &user).await {
+        return Err(e.into());
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {"response": "User deleted succesfully"})),
+    ))
+}
+
+#[debug_handler]
+pub async fn reset_password(
+    state: StateExtension,
+    Path(user_id): Path<String>,
+    Json(req): Json<ResetPassword>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let mut user = find_user(&state, &user_id).await?;
+
+    // Without an explicit password a temporary one is generated and handed
+    // back once so the admin can pass it on.
+    let (password, generated) = match req.password {
+        Some(password) => {
+            let failures = check_password(&password, Some(&user.username));
+
+            if !failures.is_empty() {
+                return Ok(policy_response(&failures));
+            }
+
+            (password, false)
+        }
+        None => (random_token(), true),
+    };
+
+    user.password = match hash_password(&password).await {
+        Ok(pass) => pass,
+        Err(e) => {
+            println!("Error: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    save_user(&state, &user).await?;
+
+    if let Err(e) = revoke_all_sessions(&state, &user_id).await {
+        return Err(e.into());
+    }
+
+    if generated {
+        Ok((
+            StatusCode::OK,
+            Json(json!(
+                doc! {"response": "Password reset succesfully", "password": password}
+            )),
+        ))
+    } else {
+        Ok((
+            StatusCode::OK,
+            Json(json!(doc! {"response": "Password reset succesfully"})),
+        ))
+    }
+}
@@ -0,0 +1,124 @@
+use axum::{extract::Path, Json};
+use axum_macros::debug_handler;
+use hyper::StatusCode;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    auth::{api_key::create_api_key, extractor::AuthUser, permissions::Permission},
+    db::models::ApiKey,
+    StateExtension,
+};
+
+use super::admin::find_user;
+
+const MAX_API_KEYS: usize = 20;
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiKey {
+    name: String,
+    scopes: Vec<Permission>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyOutgoing {
+    pub key_id: String,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<Permission>,
+    pub created_at: String,
+    pub last_used: Option<String>,
+}
+
+impl From<ApiKey> for ApiKeyOutgoing {
+    fn from(key: ApiKey) -> Self {
+        ApiKeyOutgoing {
+            key_id: key.key_id,
+            name: key.name,
+            prefix: key.prefix,
+            scopes: key.scopes,
+            created_at: key.created_at.to_rfc3339(),
+            last_used: key.last_used.map(|date| date.to_rfc3339()),
+        }
+    }
+}
+
+#[debug_handler]
+pub async fn list_api_keys(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let keys: Vec<ApiKeyOutgoing> = match state.api_keys.list_api_keys(&claims.userid).await {
+        Ok(res) => res.into_iter().map(ApiKeyOutgoing::from).collect(),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok((StatusCode::OK, Json(json!(keys))))
+}
+
+/// Hands back the new key once, only its hash is kept.
+#[debug_handler]
+pub async fn new_api_key(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<CreateApiKey>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let name = req.name.trim();
+
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH || req.scopes.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let user = find_user(&state, &claims.userid).await?;
+
+    // A key can't grant more than the role of its owner.
+    if !req.scopes.iter().all(|scope| user.role.can(*scope)) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    match state.api_keys.list_api_keys(&user.user_id).await {
+        Ok(res) if res.len() >= MAX_API_KEYS => return Err(StatusCode::CONFLICT),
+        Ok(_) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut scopes = Vec::new();
+
+    for scope in req.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+
+    let (key, api_key) = match create_api_key(&state, &user, name, scopes).await {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "response": "API key created, store it now, it won't be shown again",
+            "api_key": key,
+            "key": ApiKeyOutgoing::from(api_key),
+        })),
+    ))
+}
+
+#[debug_handler]
+pub async fn delete_api_key(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Path(key_id): Path<String>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    match state.api_keys.delete_api_key(&claims.userid, &key_id).await {
+        Ok(true) => Ok((
+            StatusCode::OK,
+            Json(json!(doc! {"response": "API key revoked succesfully"})),
+        )),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(e.into()),
+    }
+}
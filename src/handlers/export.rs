@@ -0,0 +1,309 @@
+use std::{io, sync::Arc};
+
+use axum::{body::Body, response::IntoResponse};
+use axum_macros::debug_handler;
+use chrono::prelude::*;
+use hyper::{body::Bytes, header, StatusCode};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{
+    auth::extractor::AuthUser,
+    db::{
+        connect::DbState,
+        models::{Errors, Notes, Role, User},
+    },
+    utils::zip::ZipWriter,
+    StateExtension,
+};
+
+use super::{
+    admin::find_user, api_keys::ApiKeyOutgoing, notebooks::NotebookOutgoing, notes::NotesOutgoing,
+    revisions::RevisionOutgoing, sessions::SessionOutgoing,
+};
+
+/// Notes read from the store at a time.
+const NOTES_BATCH: u64 = 100;
+
+/// Archive bytes held back before they are sent.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Everything kept about a user but the password hash and two-factor
+/// secrets.
+#[derive(Debug, Serialize)]
+pub struct ProfileExport {
+    pub user_id: String,
+    pub username: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub ip: Option<String>,
+    pub role: Role,
+    pub disabled: bool,
+    pub totp_enabled: bool,
+    pub oidc_subject: Option<String>,
+    pub deletion_scheduled_at: Option<String>,
+}
+
+impl From<&User> for ProfileExport {
+    fn from(user: &User) -> Self {
+        ProfileExport {
+            user_id: user.user_id.clone(),
+            username: user.username.clone(),
+            email: user.email.clone(),
+            email_verified: user.email_verified,
+            ip: user.ip.clone(),
+            role: user.role,
+            disabled: user.disabled,
+            totp_enabled: user.totp_enabled,
+            oidc_subject: user.oidc_subject.clone(),
+            deletion_scheduled_at: user.deletion_scheduled_at.map(|date| date.to_rfc3339()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Store(Errors),
+    /// The client stopped reading.
+    Closed,
+}
+
+impl From<io::Error> for ExportError {
+    fn from(e: io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(e: serde_json::Error) -> Self {
+        ExportError::Json(e)
+    }
+}
+
+impl From<Errors> for ExportError {
+    fn from(e: Errors) -> Self {
+        ExportError::Store(e)
+    }
+}
+
+type Sender = mpsc::Sender<Result<Bytes, io::Error>>;
+
+/// Zip of everything stored about the caller: profile, options, sessions,
+/// API keys, notebooks and notes, the latter both as JSON and as Markdown files,
+/// with the revisions of each note. The archive is sent while it is built, a
+/// batch of notes at a time.
+#[debug_handler]
+pub async fn export_data(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+) -> Result<impl IntoResponse, StatusCode> {
+    let user = find_user(&state, &claims.userid).await?;
+
+    let state = state.0.clone();
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        match write_export(&state, &user, &tx).await {
+            Ok(()) | Err(ExportError::Closed) => {}
+            Err(e) => {
+                println!("Error: {:?}", e);
+
+                // Fails the response instead of ending it, so the client
+                // doesn't take the truncated archive for a whole one.
+                let _ = tx.send(Err(io::Error::other("export failed"))).await;
+            }
+        }
+    });
+
+    let body = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+
+    let disposition = format!(
+        "attachment; filename=\"note4keep-export-{}.zip\"",
+        Utc::now().format("%Y-%m-%d")
+    );
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        Body::from_stream(body),
+    ))
+}
+
+async fn write_export(state: &Arc<DbState>, user: &User, tx: &Sender) -> Result<(), ExportError> {
+    let mut zip = ZipWriter::new();
+    let now = Utc::now();
+
+    zip.start_file("profile.json", now)?;
+    zip.write(&serde_json::to_vec_pretty(&ProfileExport::from(user))?)?;
+
+    if let Some(options) = state.options.find_options(&user.user_id).await? {
+        zip.start_file("options.json", now)?;
+        zip.write(&serde_json::to_vec_pretty(&options)?)?;
+    }
+
+    let sessions: Vec<SessionOutgoing> = state
+        .sessions
+        .list_sessions(&user.user_id)
+        .await?
+        .into_iter()
+        .map(|session| SessionOutgoing::new(session, ""))
+        .collect();
+
+    zip.start_file("sessions.json", now)?;
+    zip.write(&serde_json::to_vec_pretty(&sessions)?)?;
+
+    let api_keys: Vec<ApiKeyOutgoing> = state
+        .api_keys
+        .list_api_keys(&user.user_id)
+        .await?
+        .into_iter()
+        .map(ApiKeyOutgoing::from)
+        .collect();
+
+    zip.start_file("api-keys.json", now)?;
+    zip.write(&serde_json::to_vec_pretty(&api_keys)?)?;
+
+    let notebooks: Vec<NotebookOutgoing> = state
+        .notebooks
+        .list_notebooks(&user.user_id)
+        .await?
+        .into_iter()
+        .map(NotebookOutgoing::from)
+        .collect();
+
+    zip.start_file("notebooks.json", now)?;
+    zip.write(&serde_json::to_vec_pretty(&notebooks)?)?;
+
+    // A single zip entry can't be interrupted, so the notes are read twice:
+    // once for notes.json, once for the Markdown files and revisions.
+    zip.start_file("notes.json", now)?;
+    zip.write(b"[")?;
+
+    let mut first = true;
+    let mut after: Option<String> = None;
+
+    loop {
+        let notes = next_notes(state, user, &after).await?;
+
+        for note in &notes {
+            zip.write(if first { b"\n" } else { b",\n" })?;
+            zip.write(&serde_json::to_vec_pretty(&NotesOutgoing::from(
+                note.clone(),
+            ))?)?;
+            first = false;
+        }
+
+        send(&mut zip, tx, false).await?;
+
+        match notes.last() {
+            Some(note) if notes.len() as u64 == NOTES_BATCH => after = Some(note.note_id.clone()),
+            _ => break,
+        }
+    }
+
+    zip.write(b"\n]\n")?;
+
+    after = None;
+
+    loop {
+        let notes = next_notes(state, user, &after).await?;
+
+        for note in &notes {
+            zip.start_file(&format!("notes/{}.md", file_name(note)), note.date)?;
+            zip.write(markdown(note)?.as_bytes())?;
+
+            let revisions: Vec<RevisionOutgoing> = state
+                .revisions
+                .list_revisions(&user.user_id, &note.note_id)
+                .await?
+                .into_iter()
+                .map(RevisionOutgoing::from)
+                .collect();
+
+            if !revisions.is_empty() {
+                zip.start_file(&format!("notes/{}/revisions.json", note.note_id), now)?;
+                zip.write(&serde_json::to_vec_pretty(&revisions)?)?;
+            }
+        }
+
+        send(&mut zip, tx, false).await?;
+
+        match notes.last() {
+            Some(note) if notes.len() as u64 == NOTES_BATCH => after = Some(note.note_id.clone()),
+            _ => break,
+        }
+    }
+
+    zip.finish()?;
+
+    send(&mut zip, tx, true).await
+}
+
+async fn next_notes(
+    state: &DbState,
+    user: &User,
+    after: &Option<String>,
+) -> Result<Vec<Notes>, Errors> {
+    state
+        .notes
+        .list_notes_after(&user.user_id, after.as_deref(), NOTES_BATCH)
+        .await
+}
+
+/// Hands the archive written so far to the response, once there is enough
+/// of it or when `all` is set.
+async fn send(zip: &mut ZipWriter, tx: &Sender, all: bool) -> Result<(), ExportError> {
+    if zip.pending() == 0 || (!all && zip.pending() < CHUNK_BYTES) {
+        return Ok(());
+    }
+
+    match tx.send(Ok(Bytes::from(zip.take_output()))).await {
+        Ok(()) => Ok(()),
+        Err(_) => Err(ExportError::Closed),
+    }
+}
+
+/// Readable and unique: the title squeezed into a slug, then the note id.
+fn file_name(note: &Notes) -> String {
+    let mut slug = String::new();
+
+    for c in note.title.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+
+        if slug.chars().count() >= 48 {
+            break;
+        }
+    }
+
+    let slug = slug.trim_end_matches('-');
+
+    if slug.is_empty() {
+        note.note_id.clone()
+    } else {
+        format!("{}-{}", slug, note.note_id)
+    }
+}
+
+/// The note as Markdown, its fields in a YAML front matter. JSON strings are
+/// valid YAML, so the title is written as one to get it escaped.
+fn markdown(note: &Notes) -> Result<String, serde_json::Error> {
+    Ok(format!(
+        "---\ntitle: {}\npriority: {}\ndate: {}\ntags: {}\n---\n\n{}\n",
+        serde_json::to_string(&note.title)?,
+        note.priority,
+        note.date.to_rfc3339(),
+        serde_json::to_string(&note.tags)?,
+        note.text
+    ))
+}
@@ -0,0 +1,13 @@
+use axum::Json;
+use axum_macros::debug_handler;
+use hyper::StatusCode;
+use jsonwebtoken::jwk::JwkSet;
+
+use crate::auth::keys::KEY_RING;
+
+/// Public keys access tokens are signed with, so other services can verify
+/// them. Empty while tokens are signed with the HS256 `SECRET`.
+#[debug_handler]
+pub async fn jwks() -> (StatusCode, Json<JwkSet>) {
+    (StatusCode::OK, Json(KEY_RING.jwks()))
+}
@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+
+use axum::Json;
+use axum_macros::debug_handler;
+use chrono::prelude::*;
+use hyper::StatusCode;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    auth::extractor::AuthUser,
+    db::{connect::DbState, models::Notebook},
+    utils::random_id::random_id,
+    StateExtension,
+};
+
+use super::notes::NotesOutgoing;
+
+pub const MAX_NOTEBOOK_NAME: usize = 64;
+
+/// Levels of nesting, top level notebooks being the first.
+pub const MAX_NOTEBOOK_DEPTH: usize = 8;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotebookOutgoing {
+    pub notebook_id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub created_at: String,
+}
+
+impl From<Notebook> for NotebookOutgoing {
+    fn from(notebook: Notebook) -> Self {
+        NotebookOutgoing {
+            notebook_id: notebook.notebook_id,
+            name: notebook.name,
+            parent_id: notebook.parent_id,
+            created_at: notebook.created_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateNotebook {
+    name: String,
+    #[serde(default)]
+    parent_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateNotebook {
+    notebook_id: String,
+    name: String,
+    /// `None` moves the notebook to the top level.
+    #[serde(default)]
+    parent_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeleteNotebook {
+    notebook_id: String,
+    /// Deletes the nested notebooks too and moves every note in them to the
+    /// trash. Otherwise they are handed to the parent of the deleted notebook.
+    #[serde(default)]
+    cascade: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotebookNotes {
+    /// `None` lists the notes outside of any notebook.
+    #[serde(default)]
+    notebook_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MoveNotes {
+    notes_id: Vec<String>,
+    /// `None` takes the notes out of their notebook.
+    #[serde(default)]
+    notebook_id: Option<String>,
+}
+
+fn check_name(name: &str) -> Result<String, StatusCode> {
+    let name = name.trim();
+
+    if name.is_empty() || name.chars().count() > MAX_NOTEBOOK_NAME {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    Ok(name.to_string())
+}
+
+/// Checks the notebook `notebook_id` exists and belongs to `user`.
+pub async fn check_notebook(
+    state: &DbState,
+    user: &str,
+    notebook_id: &str,
+) -> Result<Notebook, StatusCode> {
+    match state.notebooks.find_notebook(user, notebook_id).await {
+        Ok(Some(res)) => Ok(res),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Ids of `notebook_id` and of every notebook nested in it.
+fn subtree(notebooks: &[Notebook], notebook_id: &str) -> Vec<String> {
+    let mut ids = vec![notebook_id.to_string()];
+    let mut next = 0;
+
+    while next < ids.len() {
+        let parent = ids[next].clone();
+
+        ids.extend(
+            notebooks
+                .iter()
+                .filter(|n| n.parent_id.as_deref() == Some(parent.as_str()))
+                .map(|n| n.notebook_id.clone()),
+        );
+        next += 1;
+    }
+
+    ids
+}
+
+/// Checks `notebook_id` (`None` for a new notebook) can be put under
+/// `parent_id`: the parent must exist, not be nested in the notebook itself,
+/// and the result must stay within `MAX_NOTEBOOK_DEPTH`.
+async fn check_parent(
+    state: &DbState,
+    user: &str,
+    notebook_id: Option<&str>,
+    parent_id: Option<&str>,
+) -> Result<(), StatusCode> {
+    let parent_id = match parent_id {
+        Some(res) => res,
+        None => return Ok(()),
+    };
+
+    let notebooks = match state.notebooks.list_notebooks(user).await {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    let parents: HashMap<&str, Option<&str>> = notebooks
+        .iter()
+        .map(|n| (n.notebook_id.as_str(), n.parent_id.as_deref()))
+        .collect();
+
+    if !parents.contains_key(parent_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    // Levels from the top down to the parent.
+    let mut depth = 0;
+    let mut current = Some(parent_id);
+
+    while let Some(id) = current {
+        if Some(id) == notebook_id {
+            return Err(StatusCode::CONFLICT);
+        }
+
+        depth += 1;
+        current = parents.get(id).copied().flatten();
+
+        if depth > MAX_NOTEBOOK_DEPTH {
+            break;
+        }
+    }
+
+    // Levels taken by the notebook and what is nested in it.
+    let height = match notebook_id {
+        Some(id) => height(&notebooks, id),
+        None => 1,
+    };
+
+    if depth + height > MAX_NOTEBOOK_DEPTH {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    Ok(())
+}
+
+fn height(notebooks: &[Notebook], notebook_id: &str) -> usize {
+    let mut level = vec![notebook_id];
+    let mut height = 0;
+
+    while !level.is_empty() && height <= MAX_NOTEBOOK_DEPTH {
+        height += 1;
+        level = notebooks
+            .iter()
+            .filter(|n| n.parent_id.as_deref().is_some_and(|p| level.contains(&p)))
+            .map(|n| n.notebook_id.as_str())
+            .collect();
+    }
+
+    height
+}
+
+/// Every notebook of the caller, flat: `parent_id` gives the nesting.
+#[debug_handler]
+pub async fn get_notebooks(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let notebooks: Vec<NotebookOutgoing> =
+        match state.notebooks.list_notebooks(&claims.userid).await {
+            Ok(res) => res.into_iter().map(NotebookOutgoing::from).collect(),
+            Err(e) => return Err(e.into()),
+        };
+
+    Ok((StatusCode::OK, Json(json!(notebooks))))
+}
+
+#[debug_handler]
+pub async fn create_notebook(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<CreateNotebook>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let name = check_name(&req.name)?;
+
+    check_parent(&state, &claims.userid, None, req.parent_id.as_deref()).await?;
+
+    let notebook = Notebook {
+        notebook_id: random_id(),
+        user: claims.userid,
+        name,
+        parent_id: req.parent_id,
+        created_at: Utc::now(),
+    };
+
+    if let Err(e) = state.notebooks.insert_notebook(&notebook).await {
+        return Err(e.into());
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(NotebookOutgoing::from(notebook))),
+    ))
+}
+
+/// Renames the notebook and sets its parent, moving it with everything in it.
+#[debug_handler]
+pub async fn update_notebook(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<UpdateNotebook>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let name = check_name(&req.name)?;
+
+    let mut notebook = check_notebook(&state, &claims.userid, &req.notebook_id).await?;
+
+    check_parent(
+        &state,
+        &claims.userid,
+        Some(&notebook.notebook_id),
+        req.parent_id.as_deref(),
+    )
+    .await?;
+
+    notebook.name = name;
+    notebook.parent_id = req.parent_id;
+
+    match state.notebooks.update_notebook(&notebook).await {
+        Ok(true) => Ok((
+            StatusCode::OK,
+            Json(json!(doc! {"response": "Notebook updated succesfully"})),
+        )),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The notebook goes last, so a delete that fails halfway can be run again.
+#[debug_handler]
+pub async fn delete_notebook(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<DeleteNotebook>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let user = claims.userid;
+    let notebook = check_notebook(&state, &user, &req.notebook_id).await?;
+
+    if req.cascade {
+        let notebooks = match state.notebooks.list_notebooks(&user).await {
+            Ok(res) => res,
+            Err(e) => return Err(e.into()),
+        };
+
+        let ids = subtree(&notebooks, &notebook.notebook_id);
+
+        let notes = match state
+            .notes
+            .trash_notebook_notes(&user, &ids, Utc::now())
+            .await
+        {
+            Ok(res) => res,
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Err(e) = state.notebooks.delete_notebooks(&user, &ids).await {
+            return Err(e.into());
+        }
+
+        return Ok((
+            StatusCode::OK,
+            Json(json!(doc! {
+                "response": format!(
+                    "{} notebooks deleted and {} notes moved to the trash",
+                    ids.len(),
+                    notes
+                ),
+            })),
+        ));
+    }
+
+    let parent_id = notebook.parent_id.as_deref();
+    let ids = [notebook.notebook_id.clone()];
+
+    let notes = match state
+        .notes
+        .move_notebook_notes(&user, &ids, parent_id)
+        .await
+    {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    let notebooks = match state
+        .notebooks
+        .reparent_notebooks(&user, &notebook.notebook_id, parent_id)
+        .await
+    {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Err(e) = state.notebooks.delete_notebooks(&user, &ids).await {
+        return Err(e.into());
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {
+            "response": format!(
+                "Notebook deleted, {} notebooks and {} notes moved to its parent",
+                notebooks,
+                notes
+            ),
+        })),
+    ))
+}
+
+#[debug_handler]
+pub async fn notebook_notes(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<NotebookNotes>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    if let Some(notebook_id) = &req.notebook_id {
+        check_notebook(&state, &claims.userid, notebook_id).await?;
+    }
+
+    let notes: Vec<NotesOutgoing> = match state
+        .notes
+        .list_notes_in_notebook(&claims.userid, req.notebook_id.as_deref())
+        .await
+    {
+        Ok(res) => res.into_iter().map(NotesOutgoing::from).collect(),
+        Err(e) => return Err(e.into()),
+    };
+
+    if notes.is_empty() {
+        return Err(StatusCode::NO_CONTENT);
+    }
+
+    Ok((StatusCode::OK, Json(json!(notes))))
+}
+
+#[debug_handler]
+pub async fn move_notes(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<MoveNotes>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    if req.notes_id.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    if let Some(notebook_id) = &req.notebook_id {
+        check_notebook(&state, &claims.userid, notebook_id).await?;
+    }
+
+    match state
+        .notes
+        .move_notes(&claims.userid, &req.notes_id, req.notebook_id.as_deref())
+        .await
+    {
+        Ok(0) => Err(StatusCode::NOT_FOUND),
+        Ok(moved) => Ok((
+            StatusCode::OK,
+            Json(json!(doc! {
+                "response": format!("{} notes moved", moved),
+            })),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -1,22 +1,34 @@
-use axum::Json;
+use axum::{
+    extract::Query,
+    http::{HeaderMap, HeaderName},
+    Json,
+};
 use axum_macros::debug_handler;
-use futures::TryStreamExt;
-use hyper::{HeaderMap, StatusCode};
-use mongodb::{bson::doc, options::FindOptions};
+use hyper::StatusCode;
+use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::utils::{get_token::get_token, mongo_health::mongo_query_error, random_id::random_id};
+use crate::utils::{
+    etag::{etag_header, if_match, IfMatch, VersionError},
+    mongo_health::mongo_query_error,
+    random_id::random_id,
+};
 use crate::{
-    auth::jwt::compare_jwt,
-    db::{
-        connect::{database_coll, NOTES},
-        models::Notes,
-    },
+    auth::extractor::AuthUser,
+    db::{connect::DbState, models::Notes},
     StateExtension,
 };
 use chrono::prelude::*;
 
+use super::{
+    notebooks::check_notebook,
+    revisions::{
+        changed_meanwhile, find_live_note, record_revision, update_with_revision, NoteChanges,
+    },
+    tags::{normalize_tag, normalize_tags},
+};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NotesOutgoing {
     pub note_id: String,
@@ -24,6 +36,11 @@ pub struct NotesOutgoing {
     pub priority: u32,
     pub text: String,
     pub date: String,
+    pub tags: Vec<String>,
+    pub notebook_id: Option<String>,
+    /// Set for notes in the trash.
+    pub deleted_at: Option<String>,
+    pub revision: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -31,6 +48,26 @@ pub struct CreateNote {
     title: String,
     priority: u32,
     text: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    notebook_id: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMatch {
+    #[default]
+    Any,
+    All,
+}
+
+/// Query of `get_notes`: `tags` is a comma separated list.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotesFilter {
+    tags: Option<String>,
+    #[serde(default, rename = "match")]
+    tag_match: TagMatch,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,74 +91,61 @@ pub struct UpdateNote {
     title: String,
     priority: u32,
     text: String,
+    /// Keeps the current tags when missing.
+    tags: Option<Vec<String>>,
 }
 
-#[debug_handler]
-pub async fn get_all_notes(state: StateExtension) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let user_coll = database_coll::<Notes>(&state.db, "notes").await;
-
-    let find_options = FindOptions::builder().sort(doc! {}).build();
-
-    let mut cursor = if let Ok(cursor) = user_coll.find(None, find_options).await {
-        cursor
-    } else {
-        panic!("Error")
-    };
-
-    let mut result = Vec::new();
-
-    while let Some(notes) = cursor.try_next().await.unwrap() {
-        result.push(notes)
+impl From<Notes> for NotesOutgoing {
+    fn from(note: Notes) -> Self {
+        // Renames through MongoDB don't keep them ordered.
+        let mut tags = note.tags;
+        tags.sort();
+
+        NotesOutgoing {
+            note_id: note.note_id,
+            title: note.title,
+            priority: note.priority,
+            text: note.text,
+            date: note.date.to_rfc3339(),
+            tags,
+            notebook_id: note.notebook_id,
+            deleted_at: note.deleted_at.map(|date| date.to_rfc3339()),
+            revision: note.revision,
+        }
     }
-
-    Ok((StatusCode::OK, Json(json!(result))))
 }
 
 #[debug_handler]
 pub async fn get_notes(
     state: StateExtension,
-    headers: HeaderMap,
+    AuthUser(claims): AuthUser,
+    Query(filter): Query<NotesFilter>,
 ) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let coll = database_coll::<Notes>(&state.db, NOTES).await;
-
-    let token = match get_token(&headers) {
-        Ok(res) => res,
-        Err(e) => return Err(e),
+    let tags = match filter.tags.as_deref() {
+        Some(tags) => tags
+            .split(',')
+            .map(normalize_tag)
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
     };
 
-    let claims = match compare_jwt(&token).await {
-        Ok(res) => res,
-        Err(_) => return Err(StatusCode::UNAUTHORIZED),
-    };
+    let res = if tags.is_empty() {
+        state.notes.list_notes(&claims.userid).await
+    } else {
+        let all = matches!(filter.tag_match, TagMatch::All);
 
-    let filter = doc! {"user": &claims.claims.userid};
-    let opts = FindOptions::builder().sort(doc! {"date": - 1}).build();
-
-    let notes = match coll.find(filter, opts).await {
-        Ok(mut cursor) => {
-            let mut notes = Vec::new();
-
-            while let Some(note) = cursor.try_next().await.unwrap() {
-                // This thing right down here is a mess, i'll fix some day.
-                let note = NotesOutgoing {
-                    note_id: note.note_id,
-                    title: note.title,
-                    priority: note.priority,
-                    text: note.text,
-                    date: note.date.to_rfc3339(),
-                };
-                notes.push(note)
-            }
+        state
+            .notes
+            .list_notes_with_tags(&claims.userid, &tags, all)
+            .await
+    };
 
-            notes
-        }
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+    let notes: Vec<NotesOutgoing> = match res {
+        Ok(res) => res.into_iter().map(NotesOutgoing::from).collect(),
+        Err(e) => return Err(e.into()),
     };
 
-    if notes.len() < 1 {
+    if notes.is_empty() {
         return Err(StatusCode::NO_CONTENT);
     }
 
@@ -131,52 +155,43 @@ pub async fn get_notes(
 #[debug_handler]
 pub async fn create_note(
     state: StateExtension,
-    headers: HeaderMap,
+    AuthUser(claims): AuthUser,
     Json(req): Json<CreateNote>,
 ) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let coll = database_coll::<Notes>(&state.db, NOTES).await;
-
-    let token = match get_token(&headers) {
-        Ok(res) => res,
-        Err(e) => return Err(e),
-    };
-
     let note_id = random_id();
 
-    let claims = if let Ok(claims) = compare_jwt(&token).await {
-        claims
-    } else {
-        return Err(StatusCode::UNAUTHORIZED);
-    };
+    if let Some(notebook_id) = &req.notebook_id {
+        check_notebook(&state, &claims.userid, notebook_id).await?;
+    }
 
     let data = Notes {
         note_id,
         title: req.title,
         priority: req.priority,
         text: req.text,
-        user: claims.claims.userid,
+        user: claims.userid,
         date: Utc::now(),
+        tags: normalize_tags(&req.tags)?,
+        notebook_id: req.notebook_id,
+        deleted_at: None,
+        revision: 1,
     };
 
-    match coll.find_one(doc! {"note_id": &data.note_id}, None).await {
-        Ok(e) => match e {
-            Some(_) => {
+    match state.notes.find_note(&data.user, &data.note_id).await {
+        Ok(res) => {
+            if res.is_some() {
                 return Err(StatusCode::CONFLICT);
             }
-            None => {}
-        },
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
         }
+        Err(e) => return Err(e.into()),
     };
 
-    match coll.insert_one(data, None).await {
-        Ok(_) => {}
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+    if let Err(e) = state.notes.insert_note(&data).await {
+        return Err(e.into());
+    }
+
+    if let Err(e) = record_revision(&state, &data).await {
+        return Err(e.into());
     }
 
     Ok((
@@ -188,249 +203,182 @@ pub async fn create_note(
 #[debug_handler]
 pub async fn some_note(
     state: StateExtension,
-    headers: HeaderMap,
+    AuthUser(claims): AuthUser,
     Json(req): Json<SomeNote>,
 ) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let coll = database_coll::<Notes>(&state.db, NOTES).await;
-
-    match mongo_query_error(&req.note_phrase) {
-        false => {}
-        true => return Err(StatusCode::BAD_REQUEST),
-    };
-
-    if req.note_phrase.len() < 1 {
+    if mongo_query_error(&req.note_phrase) {
         return Err(StatusCode::BAD_REQUEST);
     }
 
-    let token = match get_token(&headers) {
-        Ok(res) => res,
-        Err(e) => return Err(e),
-    };
-
-    let claims = match compare_jwt(&token).await {
-        Ok(res) => res,
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::UNAUTHORIZED);
-        }
-    };
+    if req.note_phrase.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
 
     let mut passphrase = String::from(".*");
     passphrase.push_str(&req.note_phrase);
     passphrase.push_str(".*");
 
-    let formated = mongodb::bson::Regex {
-        pattern: passphrase,
-        options: String::from("i"),
-    };
-
-    let filters = doc! {"title": formated, "user": &claims.claims.userid};
-    let opts = FindOptions::builder().sort(doc! {"date": -1}).build();
-
-    let note = match coll.find(filters, opts).await {
-        Ok(mut res) => {
-            let mut all_results = Vec::new();
-
-            while let Some(note) = match res.try_next().await {
-                Ok(res) => res,
-                Err(e) => {
-                    println!("Error: {:?}", e);
-                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                }
-            } {
-                let note = NotesOutgoing {
-                    note_id: note.note_id,
-                    title: note.title,
-                    priority: note.priority,
-                    text: note.text,
-                    date: note.date.to_rfc3339(),
-                };
-                all_results.push(note)
-            }
-
-            all_results
-        }
-        Err(e) => {
-            println!("Error: {:?}", e);
-            println!("Me gustan las patatas");
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+    let note: Vec<NotesOutgoing> = match state.notes.search_notes(&claims.userid, &passphrase).await
+    {
+        Ok(res) => res.into_iter().map(NotesOutgoing::from).collect(),
+        Err(e) => return Err(e.into()),
     };
 
-    if note.len() < 1 {
+    if note.is_empty() {
         return Err(StatusCode::NO_CONTENT);
     }
 
     Ok((StatusCode::OK, Json(json!(note))))
 }
 
+/// The note with its revision as `ETag`, to send back in `If-Match` when
+/// updating or deleting it.
 #[debug_handler]
 pub async fn spec_note(
     state: StateExtension,
-    headers: HeaderMap,
+    AuthUser(claims): AuthUser,
     Json(req): Json<SpecificNote>,
-) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let coll = database_coll::<Notes>(&state.db, NOTES).await;
-
-    let token = match get_token(&headers) {
-        Ok(res) => res,
-        Err(e) => return Err(e),
-    };
-
-    let claims = match compare_jwt(&token).await {
-        Ok(res) => res,
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::UNAUTHORIZED);
-        }
-    };
-
-    let filters = doc! {"note_id": &req.note_id, "user": &claims.claims.userid};
+) -> Result<(StatusCode, [(HeaderName, String); 1], Json<Value>), StatusCode> {
+    let note = find_live_note(&state, &claims.userid, &req.note_id).await?;
 
-    let note = match coll.find_one(filters, None).await {
-        Ok(res) => {
-            let note = if let Some(res) = res {
-                NotesOutgoing {
-                    note_id: res.note_id,
-                    title: res.title,
-                    priority: res.priority,
-                    text: res.text,
-                    date: res.date.to_rfc3339(),
-                }
-            } else {
-                return Err(StatusCode::NOT_FOUND);
-            };
-
-            note
-        }
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    };
-
-    Ok((StatusCode::OK, Json(json!(note))))
+    Ok((
+        StatusCode::OK,
+        etag_header(note.revision),
+        Json(json!(NotesOutgoing::from(note))),
+    ))
 }
 
+/// With `If-Match`, the note only goes to the trash while it is at one of
+/// the revisions listed, otherwise 412 tells the current one.
 #[debug_handler]
 pub async fn delete_spec_note(
     state: StateExtension,
+    AuthUser(claims): AuthUser,
     headers: HeaderMap,
     Json(req): Json<SpecificNote>,
-) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let coll = database_coll::<Notes>(&state.db, NOTES).await;
+) -> Result<(StatusCode, Json<Value>), VersionError> {
+    let revision = match if_match(&headers)? {
+        Some(precondition) => {
+            let current = find_live_note(&state, &claims.userid, &req.note_id).await?;
 
-    let token = match get_token(&headers) {
-        Ok(res) => res,
-        Err(e) => return Err(e),
-    };
+            if !precondition.matches(current.revision) {
+                return Err(VersionError::Stale(current.revision));
+            }
 
-    let claims = match compare_jwt(&token).await {
-        Ok(res) => res,
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::UNAUTHORIZED);
+            Some(current.revision)
         }
+        None => None,
     };
 
-    let filters = doc! {"note_id": &req.note_id, "user": &claims.claims.userid};
-
-    match coll.find_one(filters.clone(), None).await {
-        Ok(res) => match res {
-            Some(_) => {}
-            None => return Err(StatusCode::NOT_FOUND),
-        },
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    };
+    match state
+        .notes
+        .trash_note(&claims.userid, &req.note_id, revision, Utc::now())
+        .await
+    {
+        Ok(true) => Ok((
+            StatusCode::OK,
+            Json(json!(doc! {"response": "Note moved to the trash"})),
+        )),
+        Ok(false) => Err(changed_meanwhile(&state, &claims.userid, &req.note_id).await),
+        Err(e) => Err(e.into()),
+    }
+}
 
-    let deleted: bool = match coll.delete_one(filters, None).await {
-        Ok(_) => true,
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+/// Revision of each note of `note_ids` outside the trash, once they all
+/// match `precondition`. Notes that don't exist are left out, 412 lists the
+/// ones that don't match, before anything is deleted.
+async fn check_revisions(
+    state: &DbState,
+    user: &str,
+    note_ids: &[String],
+    precondition: &IfMatch,
+) -> Result<Vec<(String, u64)>, VersionError> {
+    let mut checked = Vec::new();
+    let mut stale = Vec::new();
+
+    for note_id in note_ids {
+        match state.notes.find_note(user, note_id).await {
+            Ok(Some(note)) if note.deleted_at.is_none() => {
+                match precondition.matches(note.revision) {
+                    true => checked.push((note.note_id, note.revision)),
+                    false => stale.push((note.note_id, note.revision)),
+                }
+            }
+            Ok(_) => continue,
+            Err(e) => return Err(e.into()),
         }
-    };
+    }
 
-    match deleted {
-        true => Ok((
-            StatusCode::OK,
-            Json(json!(doc! {"response": "Succesfully deleted"})),
-        )),
-        false => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    if !stale.is_empty() {
+        return Err(VersionError::StaleNotes(stale));
     }
+
+    Ok(checked)
 }
 
+/// With `If-Match`, every note must be at one of the revisions listed,
+/// otherwise 412 tells the current revision of those that aren't.
 #[debug_handler]
 pub async fn delete_notes(
     status: StateExtension,
+    AuthUser(claims): AuthUser,
     headers: HeaderMap,
     Json(req): Json<DeleteNotes>,
-) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let coll = database_coll::<Notes>(&status.db, NOTES).await;
-
-    let token = match get_token(&headers) {
-        Ok(res) => res,
-        Err(e) => return Err(e),
-    };
-
-    let claims = match compare_jwt(&token).await {
-        Ok(res) => res,
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::UNAUTHORIZED);
+) -> Result<(StatusCode, Json<Value>), VersionError> {
+    let mut deleted = Vec::new();
+    let mut not_deleted = Vec::new();
+    let mut stale = Vec::new();
+    let now = Utc::now();
+
+    let notes: Vec<(String, Option<u64>)> = match if_match(&headers)? {
+        Some(precondition) => {
+            check_revisions(&status, &claims.userid, &req.notes_id, &precondition)
+                .await?
+                .into_iter()
+                .map(|(note_id, revision)| (note_id, Some(revision)))
+                .collect()
         }
+        None => req.notes_id.into_iter().map(|note| (note, None)).collect(),
     };
 
-    let mut to_delete: Vec<String> = Vec::new();
-    let mut errored: Vec<String> = Vec::new();
-
-    for note in req.notes_id {
-        let filter = doc! {"note_id": &note, "user": &claims.claims.userid};
-
-        match coll.find_one(filter, None).await {
-            Ok(res) => match res {
-                Some(result) => to_delete.push(result.note_id),
-                _ => {
-                    errored.push(note);
-                    continue;
+    for (note, revision) in notes {
+        match status
+            .notes
+            .trash_note(&claims.userid, &note, revision, now)
+            .await
+        {
+            Ok(true) => deleted.push(note),
+            Ok(false) if revision.is_some() => {
+                if let VersionError::Stale(revision) =
+                    changed_meanwhile(&status, &claims.userid, &note).await
+                {
+                    stale.push((note.clone(), revision));
+                    not_deleted.push(note);
                 }
-            },
+            }
+            Ok(false) => continue,
             Err(e) => {
                 println!("Error: {:?}", e);
-                continue;
+                not_deleted.push(note)
             }
-        };
-    }
-
-    let mut deleted = Vec::new();
-    let mut not_deleted = Vec::new();
-
-    for delete in to_delete {
-        let filter = doc! {"note_id": &delete, "user": &claims.claims.userid};
-
-        match coll.delete_one(filter, None).await {
-            Ok(_) => deleted.push(delete),
-            Err(_) => not_deleted.push(delete),
         }
     }
 
     match deleted.len() {
-        0 => return Err(StatusCode::NOT_FOUND),
+        0 if !stale.is_empty() => Err(VersionError::StaleNotes(stale)),
+        0 => Err(VersionError::Status(StatusCode::NOT_FOUND)),
         _ => match not_deleted.len() {
             0 => Ok((
                 StatusCode::OK,
                 Json(json!(
-                    doc! {"response": "All notes were succesfully deleted"}
+                    doc! {"response": "All notes were moved to the trash"}
                 )),
             )),
 
             _ => Ok((
                 StatusCode::OK,
                 Json(json!(doc! {
-                "response": format!("{:?} notes deleted and {:?} cannot be deleted",
+                "response": format!("{:?} notes moved to the trash and {:?} cannot be deleted",
                 deleted.len(),
                 not_deleted.len())})),
             )),
@@ -438,86 +386,97 @@ pub async fn delete_notes(
     }
 }
 
+/// With `If-Match`, every note must be at one of the revisions listed,
+/// like `delete_notes`.
 #[debug_handler]
 pub async fn delete_all_notes(
     state: StateExtension,
+    AuthUser(claims): AuthUser,
     headers: HeaderMap,
-) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let coll = database_coll::<Notes>(&state.db, NOTES).await;
-
-    let token = match get_token(&headers) {
-        Ok(res) => res,
-        Err(e) => return Err(e),
-    };
+) -> Result<(StatusCode, Json<Value>), VersionError> {
+    if let Some(precondition) = if_match(&headers)? {
+        let note_ids: Vec<String> = match state.notes.list_notes(&claims.userid).await {
+            Ok(res) => res.into_iter().map(|note| note.note_id).collect(),
+            Err(e) => return Err(e.into()),
+        };
 
-    let claims = match compare_jwt(&token).await {
-        Ok(res) => res,
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::UNAUTHORIZED);
+        let notes = check_revisions(&state, &claims.userid, &note_ids, &precondition).await?;
+        let now = Utc::now();
+        let mut changed = 0;
+
+        for (note_id, revision) in &notes {
+            match state
+                .notes
+                .trash_note(&claims.userid, note_id, Some(*revision), now)
+                .await
+            {
+                Ok(true) => continue,
+                Ok(false) => changed += 1,
+                Err(e) => return Err(e.into()),
+            }
         }
-    };
 
-    let filters = doc! {"user": &claims.claims.userid};
+        return Ok((
+            StatusCode::OK,
+            Json(json!(doc! {
+                "response": format!(
+                    "{} notes moved to the trash and {} changed in the meantime",
+                    notes.len() - changed,
+                    changed
+                ),
+            })),
+        ));
+    }
 
-    match coll.delete_many(filters, None).await {
-        Ok(_) => {
-            return Ok((
-                StatusCode::OK,
-                Json(json!(doc! {"response": "ALl notes deleted succesfully"})),
-            ))
-        }
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    };
+    match state
+        .notes
+        .trash_all_notes(&claims.userid, Utc::now())
+        .await
+    {
+        Ok(_) => Ok((
+            StatusCode::OK,
+            Json(json!(doc! {"response": "All notes moved to the trash"})),
+        )),
+        Err(e) => Err(e.into()),
+    }
 }
 
+/// With `If-Match`, the note is only updated while it is at one of the
+/// revisions listed, otherwise 412 tells the current one. The new revision
+/// comes back as `ETag`.
 #[debug_handler]
 pub async fn update_note(
     state: StateExtension,
+    AuthUser(claims): AuthUser,
     headers: HeaderMap,
     Json(req): Json<UpdateNote>,
-) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let coll = database_coll::<Notes>(&state.db, NOTES).await;
+) -> Result<(StatusCode, [(HeaderName, String); 1], Json<Value>), VersionError> {
+    let precondition = if_match(&headers)?;
 
-    let token = match get_token(&headers) {
-        Ok(res) => res,
-        Err(e) => return Err(e),
+    let tags = match &req.tags {
+        Some(tags) => Some(normalize_tags(tags)?),
+        None => None,
     };
 
-    let claims = match compare_jwt(&token).await {
-        Ok(res) => res,
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::UNAUTHORIZED);
-        }
+    let changes = NoteChanges {
+        title: req.title,
+        priority: req.priority,
+        text: req.text,
+        tags,
     };
 
-    let filters = doc! {"note_id": &req.note_id, "user": &claims.claims.userid};
-    let mods = doc! {"$set": {"title": &req.title, "priority": &req.priority,
-    "text": &req.text}};
-
-    match coll.find_one(filters.clone(), None).await {
-        Ok(res) => match res {
-            None => return Err(StatusCode::NOT_FOUND),
-            _ => {}
-        },
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    };
+    let note = update_with_revision(
+        &state,
+        &claims.userid,
+        &req.note_id,
+        changes,
+        precondition.as_ref(),
+    )
+    .await?;
 
-    match coll.update_one(filters, mods, None).await {
-        Ok(_) => Ok((
-            StatusCode::OK,
-            Json(json!(doc! {"response": "Note updated succesfully"})),
-        )),
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    }
+    Ok((
+        StatusCode::OK,
+        etag_header(note.revision),
+        Json(json!(doc! {"response": "Note updated succesfully"})),
+    ))
 }
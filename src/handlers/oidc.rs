@@ -0,0 +1,95 @@
+use axum::{
+    extract::Query,
+    http::{header, HeaderMap, HeaderName},
+    response::Redirect,
+    Json,
+};
+use axum_macros::debug_handler;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    auth::{
+        extractor::ClientInfo,
+        oidc::{
+            authorization_url, browser_state, complete_login, find_or_provision_user, state_cookie,
+        },
+    },
+    StateExtension,
+};
+
+use super::users::finish_log_in;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcCallback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// Sends the browser to the provider's login page, with a cookie binding
+/// the login to it.
+#[debug_handler]
+pub async fn oidc_login(
+    state: StateExtension,
+) -> Result<([(HeaderName, String); 1], Redirect), StatusCode> {
+    let (url, login_state) = match authorization_url(&state).await {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    let cookie = match state_cookie(Some(&login_state)) {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(&url)))
+}
+
+/// Where the provider sends the browser back to. Answers like `log_in`,
+/// once the login is known to be the one the browser started. Two-factor
+/// authentication is left to the provider.
+#[debug_handler]
+pub async fn oidc_callback(
+    state: StateExtension,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Query(req): Query<OidcCallback>,
+) -> Result<(StatusCode, [(HeaderName, String); 1], Json<Value>), StatusCode> {
+    if let Some(error) = req.error {
+        println!("Error: OIDC provider answered {:?}", error);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let (code, login_state) = match (req.code, req.state) {
+        (Some(code), Some(login_state)) => (code, login_state),
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let browser_state = browser_state(&headers);
+
+    let claims = match complete_login(&state, &code, &login_state, browser_state.as_deref()).await {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    let user = match find_or_provision_user(&state, &claims).await {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    if user.disabled {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let cookie = match state_cookie(None) {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    match finish_log_in(&state, &client, user).await {
+        Ok((status, body)) => Ok((status, [(header::SET_COOKIE, cookie)], body)),
+        Err(e) => Err(e),
+    }
+}
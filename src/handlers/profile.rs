@@ -0,0 +1,281 @@
+use axum::{extract::Extension, Json};
+use axum_macros::debug_handler;
+use hyper::StatusCode;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    auth::{
+        extractor::{AuthUser, ClientInfo},
+        jwt::{create_jwt, Claims, ACCESS_TOKEN_TTL},
+        password::{hash_password, verify_password},
+        policy::{
+            check_password, check_username, normalize_username, policy_response, username_key,
+        },
+        revoke::{end_session, expire_access_tokens},
+        throttle::{check_login_allowed, clear_login_failures, record_login_failure},
+        totp::verify_second_factor,
+        verification::send_verification_email,
+    },
+    db::{
+        connect::DbState,
+        models::{TokenPurpose, User},
+    },
+    MailerExtension, StateExtension,
+};
+
+use super::admin::{find_user, save_user};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangeUsername {
+    password: String,
+    username: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangeEmail {
+    password: String,
+    /// `None` removes the email.
+    email: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangePassword {
+    password: String,
+    new_password: String,
+}
+
+/// Loads the caller and checks `password` against theirs. Wrong passwords
+/// count as failed logins, so a stolen token can't be used to guess it.
+pub async fn confirm_password(
+    state: &DbState,
+    client: &ClientInfo,
+    claims: &Claims,
+    password: &str,
+) -> Result<User, StatusCode> {
+    let user = check_password_of(state, client, claims, password).await?;
+
+    if let Err(e) = clear_login_failures(state, &user.username).await {
+        return Err(e.into());
+    }
+
+    Ok(user)
+}
+
+/// `confirm_password` followed by the second factor of the caller, when
+/// they have one. Like at login, earlier failures are only forgotten once
+/// the code is right too, so knowing the password doesn't give unlimited
+/// guesses at the code.
+pub async fn confirm_password_and_code(
+    state: &DbState,
+    client: &ClientInfo,
+    claims: &Claims,
+    password: &str,
+    code: &str,
+) -> Result<User, StatusCode> {
+    let mut user = check_password_of(state, client, claims, password).await?;
+
+    if user.totp_enabled {
+        check_login_allowed(state, &user.username, client).await?;
+
+        if !verify_second_factor(&mut user, code) {
+            if let Err(e) = record_login_failure(state, &user.username, client).await {
+                return Err(e.into());
+            }
+
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    if let Err(e) = clear_login_failures(state, &user.username).await {
+        return Err(e.into());
+    }
+
+    Ok(user)
+}
+
+async fn check_password_of(
+    state: &DbState,
+    client: &ClientInfo,
+    claims: &Claims,
+    password: &str,
+) -> Result<User, StatusCode> {
+    let user = find_user(state, &claims.userid).await?;
+
+    check_login_allowed(state, &user.username, client).await?;
+
+    let valid = match verify_password(password, &user.password).await {
+        Ok(res) => res.valid,
+        Err(e) => {
+            println!("Error: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    if !valid {
+        if let Err(e) = record_login_failure(state, &user.username, client).await {
+            return Err(e.into());
+        }
+
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(user)
+}
+
+/// Makes every access token of `user` issued so far stop validating and
+/// hands the caller a new one with the updated claims, in the same session.
+async fn reissue_token(
+    state: &DbState,
+    claims: &Claims,
+    user: &User,
+    response: &str,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    if let Err(e) = expire_access_tokens(state, &user.user_id).await {
+        return Err(e.into());
+    }
+
+    let token = match create_jwt(user, &claims.sid).await {
+        Ok(res) => res,
+        Err(e) => {
+            println!("Error: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {
+            "response": response,
+            "token": token,
+            "expires_in": *ACCESS_TOKEN_TTL,
+        })),
+    ))
+}
+
+#[debug_handler]
+pub async fn change_username(
+    state: StateExtension,
+    client: ClientInfo,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<ChangeUsername>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let failures = check_username(&req.username);
+
+    if !failures.is_empty() {
+        return Ok(policy_response(&failures));
+    }
+
+    let mut user = confirm_password(&state, &client, &claims, &req.password).await?;
+
+    // Only another account holding the name is a conflict, changing the
+    // case of one's own is fine.
+    match state.users.find_user_by_username(&req.username).await {
+        Ok(Some(other)) if other.user_id != user.user_id => return Err(StatusCode::CONFLICT),
+        Ok(_) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    user.username = normalize_username(&req.username);
+    user.username_key = username_key(&req.username);
+    save_user(&state, &user).await?;
+
+    reissue_token(&state, &claims, &user, "Username changed succesfully").await
+}
+
+/// Sets a new, unverified email and sends the verification mail to it.
+/// Pending password resets are dropped, they went to the old address. Like
+/// at registration emails aren't required to be unique, so this tells
+/// nothing about the accounts of others.
+#[debug_handler]
+pub async fn change_email(
+    state: StateExtension,
+    Extension(mailer): MailerExtension,
+    client: ClientInfo,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<ChangeEmail>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let email = match req.email.as_deref().map(str::trim) {
+        Some("") => return Err(StatusCode::BAD_REQUEST),
+        Some(email) => Some(email.to_string()),
+        None => None,
+    };
+
+    let mut user = confirm_password(&state, &client, &claims, &req.password).await?;
+
+    if email == user.email {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    user.email = email;
+    user.email_verified = false;
+    save_user(&state, &user).await?;
+
+    for purpose in [TokenPurpose::EmailVerification, TokenPurpose::PasswordReset] {
+        if let Err(e) = state
+            .tokens
+            .delete_user_one_time_tokens(&user.user_id, purpose)
+            .await
+        {
+            return Err(e.into());
+        }
+    }
+
+    if let Err(e) = send_verification_email(&state, &mailer, &user).await {
+        return Err(e.into());
+    }
+
+    reissue_token(&state, &claims, &user, "Email changed succesfully").await
+}
+
+/// Sets a new password and logs out every other session.
+#[debug_handler]
+pub async fn change_password(
+    state: StateExtension,
+    client: ClientInfo,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<ChangePassword>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let mut user = confirm_password(&state, &client, &claims, &req.password).await?;
+
+    let failures = check_password(&req.new_password, Some(&user.username));
+
+    if !failures.is_empty() {
+        return Ok(policy_response(&failures));
+    }
+
+    user.password = match hash_password(&req.new_password).await {
+        Ok(res) => res,
+        Err(e) => {
+            println!("Error: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    save_user(&state, &user).await?;
+
+    if let Err(e) = state
+        .tokens
+        .delete_user_one_time_tokens(&user.user_id, TokenPurpose::PasswordReset)
+        .await
+    {
+        return Err(e.into());
+    }
+
+    let sessions = match state.sessions.list_sessions(&user.user_id).await {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    for session in sessions {
+        if session.session_id == claims.sid {
+            continue;
+        }
+
+        if let Err(e) = end_session(&state, &user.user_id, &session.session_id).await {
+            return Err(e.into());
+        }
+    }
+
+    reissue_token(&state, &claims, &user, "Password changed succesfully").await
+}
@@ -0,0 +1,359 @@
+use std::env;
+
+use axum::{
+    http::{HeaderMap, HeaderName},
+    Json,
+};
+use axum_macros::debug_handler;
+use chrono::prelude::*;
+use hyper::StatusCode;
+use mongodb::bson::doc;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use similar::TextDiff;
+
+use crate::{
+    auth::extractor::AuthUser,
+    db::{
+        connect::DbState,
+        models::{Errors, NoteRevision, Notes},
+    },
+    utils::etag::{etag_header, if_match, IfMatch, VersionError},
+    StateExtension,
+};
+
+/// Revisions kept of every note, `NOTE_REVISIONS_KEPT` (default 50). Older
+/// ones are dropped as new ones are saved.
+pub static REVISIONS_KEPT: Lazy<u64> = Lazy::new(|| match env::var("NOTE_REVISIONS_KEPT") {
+    Ok(res) => match res.parse() {
+        Ok(kept) if kept >= 1 => kept,
+        _ => panic!("Error: NOTE_REVISIONS_KEPT must be a positive number"),
+    },
+    Err(_) => 50,
+});
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevisionOutgoing {
+    pub revision: u64,
+    pub title: String,
+    pub priority: u32,
+    pub text: String,
+    pub tags: Vec<String>,
+    pub created_at: String,
+}
+
+impl From<NoteRevision> for RevisionOutgoing {
+    fn from(revision: NoteRevision) -> Self {
+        RevisionOutgoing {
+            revision: revision.revision,
+            title: revision.title,
+            priority: revision.priority,
+            text: revision.text,
+            tags: revision.tags,
+            created_at: revision.created_at.to_rfc3339(),
+        }
+    }
+}
+
+/// A revision in a listing, without its text.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevisionSummary {
+    pub revision: u64,
+    pub title: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteRevisions {
+    note_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SpecificRevision {
+    note_id: String,
+    revision: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiffRevisions {
+    note_id: String,
+    from: u64,
+    to: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Change<T> {
+    pub from: T,
+    pub to: T,
+}
+
+/// What changed from one revision to another. Fields left alone are `None`,
+/// `text` is a unified diff, empty when the text is the same.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevisionDiff {
+    pub from: u64,
+    pub to: u64,
+    pub title: Option<Change<String>>,
+    pub priority: Option<Change<u32>>,
+    pub tags_added: Vec<String>,
+    pub tags_removed: Vec<String>,
+    pub text: String,
+}
+
+/// Saves the content of `note` as its revision `note.revision` and drops
+/// the ones past `REVISIONS_KEPT`.
+pub async fn record_revision(state: &DbState, note: &Notes) -> Result<(), Errors> {
+    let revision = NoteRevision {
+        note_id: note.note_id.clone(),
+        user: note.user.clone(),
+        revision: note.revision,
+        title: note.title.clone(),
+        priority: note.priority,
+        text: note.text.clone(),
+        tags: note.tags.clone(),
+        created_at: Utc::now(),
+    };
+
+    state.revisions.insert_revision(&revision).await?;
+
+    if let Some(below) = (note.revision + 1).checked_sub(*REVISIONS_KEPT) {
+        state
+            .revisions
+            .prune_revisions(&note.user, &note.note_id, below)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// New content of a note. `tags` are kept when `None`.
+pub struct NoteChanges {
+    pub title: String,
+    pub priority: u32,
+    pub text: String,
+    pub tags: Option<Vec<String>>,
+}
+
+/// Updates the note and saves the result as a new revision. Notes written
+/// before revisions were kept get their current content saved first, as
+/// revision 0, so the update doesn't lose it.
+///
+/// The update only goes through while the note is at the revision it was
+/// read at, and that revision matches `precondition` when there is one, so
+/// concurrent writers can't overwrite each other. Without `precondition`,
+/// losing to another writer just means reading the note again.
+pub async fn update_with_revision(
+    state: &DbState,
+    user: &str,
+    note_id: &str,
+    changes: NoteChanges,
+    precondition: Option<&IfMatch>,
+) -> Result<Notes, VersionError> {
+    loop {
+        let current = find_live_note(state, user, note_id).await?;
+
+        if precondition.is_some_and(|p| !p.matches(current.revision)) {
+            return Err(VersionError::Stale(current.revision));
+        }
+
+        if current.revision == 0 {
+            record_revision(state, &current).await?;
+        }
+
+        let update = Notes {
+            title: changes.title.clone(),
+            priority: changes.priority,
+            text: changes.text.clone(),
+            tags: changes.tags.clone().unwrap_or_else(|| current.tags.clone()),
+            ..current
+        };
+
+        let note = match state.notes.update_note(&update).await {
+            Ok(Some(res)) => res,
+            Ok(None) if precondition.is_none() => continue,
+            Ok(None) => return Err(changed_meanwhile(state, user, note_id).await),
+            Err(e) => return Err(e.into()),
+        };
+
+        record_revision(state, &note).await?;
+
+        return Ok(note);
+    }
+}
+
+pub async fn find_live_note(
+    state: &DbState,
+    user: &str,
+    note_id: &str,
+) -> Result<Notes, StatusCode> {
+    match state.notes.find_note(user, note_id).await {
+        Ok(Some(res)) if res.deleted_at.is_none() => Ok(res),
+        Ok(_) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Why a write guarded by the revision of a note matched nothing: the note
+/// moved on to another revision, or it is gone.
+pub async fn changed_meanwhile(state: &DbState, user: &str, note_id: &str) -> VersionError {
+    match find_live_note(state, user, note_id).await {
+        Ok(note) => VersionError::Stale(note.revision),
+        Err(status) => VersionError::Status(status),
+    }
+}
+
+async fn find_revision(
+    state: &DbState,
+    user: &str,
+    note_id: &str,
+    revision: u64,
+) -> Result<NoteRevision, StatusCode> {
+    match state.revisions.find_revision(user, note_id, revision).await {
+        Ok(Some(res)) => Ok(res),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn diff(from: &NoteRevision, to: &NoteRevision) -> RevisionDiff {
+    let title = (from.title != to.title).then(|| Change {
+        from: from.title.clone(),
+        to: to.title.clone(),
+    });
+
+    let priority = (from.priority != to.priority).then_some(Change {
+        from: from.priority,
+        to: to.priority,
+    });
+
+    let text = TextDiff::from_lines(&from.text, &to.text)
+        .unified_diff()
+        .header(
+            &format!("revision {}", from.revision),
+            &format!("revision {}", to.revision),
+        )
+        .to_string();
+
+    RevisionDiff {
+        from: from.revision,
+        to: to.revision,
+        title,
+        priority,
+        tags_added: to
+            .tags
+            .iter()
+            .filter(|tag| !from.tags.contains(tag))
+            .cloned()
+            .collect(),
+        tags_removed: from
+            .tags
+            .iter()
+            .filter(|tag| !to.tags.contains(tag))
+            .cloned()
+            .collect(),
+        text: if from.text == to.text {
+            String::new()
+        } else {
+            text
+        },
+    }
+}
+
+/// Revisions kept of a note, newest first.
+#[debug_handler]
+pub async fn list_revisions(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<NoteRevisions>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let revisions: Vec<RevisionSummary> = match state
+        .revisions
+        .list_revisions(&claims.userid, &req.note_id)
+        .await
+    {
+        Ok(res) => res
+            .into_iter()
+            .map(|revision| RevisionSummary {
+                revision: revision.revision,
+                title: revision.title,
+                created_at: revision.created_at.to_rfc3339(),
+            })
+            .collect(),
+        Err(e) => return Err(e.into()),
+    };
+
+    if revisions.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok((StatusCode::OK, Json(json!(revisions))))
+}
+
+#[debug_handler]
+pub async fn get_revision(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<SpecificRevision>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let revision = find_revision(&state, &claims.userid, &req.note_id, req.revision).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(RevisionOutgoing::from(revision))),
+    ))
+}
+
+#[debug_handler]
+pub async fn diff_revisions(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<DiffRevisions>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let from = find_revision(&state, &claims.userid, &req.note_id, req.from).await?;
+    let to = find_revision(&state, &claims.userid, &req.note_id, req.to).await?;
+
+    Ok((StatusCode::OK, Json(json!(diff(&from, &to)))))
+}
+
+/// Puts the content of an older revision back, as a new revision, so the
+/// one it replaces stays in the history. Takes `If-Match` like
+/// `update_note`.
+#[debug_handler]
+pub async fn restore_revision(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    headers: HeaderMap,
+    Json(req): Json<SpecificRevision>,
+) -> Result<(StatusCode, [(HeaderName, String); 1], Json<Value>), VersionError> {
+    let precondition = if_match(&headers)?;
+    let revision = find_revision(&state, &claims.userid, &req.note_id, req.revision).await?;
+
+    let changes = NoteChanges {
+        title: revision.title,
+        priority: revision.priority,
+        text: revision.text,
+        tags: Some(revision.tags),
+    };
+
+    let note = update_with_revision(
+        &state,
+        &claims.userid,
+        &req.note_id,
+        changes,
+        precondition.as_ref(),
+    )
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        etag_header(note.revision),
+        Json(json!(doc! {
+            "response": format!(
+                "Revision {} restored as revision {}",
+                revision.revision,
+                note.revision
+            ),
+        })),
+    ))
+}
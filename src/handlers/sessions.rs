@@ -0,0 +1,67 @@
+use axum::{extract::Path, Json};
+use axum_macros::debug_handler;
+use hyper::StatusCode;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    auth::{extractor::AuthUser, revoke::end_session},
+    db::models::Session,
+    StateExtension,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionOutgoing {
+    pub session_id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: String,
+    pub last_seen: String,
+    pub current: bool,
+}
+
+impl SessionOutgoing {
+    pub fn new(session: Session, current_sid: &str) -> Self {
+        SessionOutgoing {
+            current: session.session_id == current_sid,
+            session_id: session.session_id,
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: session.created_at.to_rfc3339(),
+            last_seen: session.last_seen.to_rfc3339(),
+        }
+    }
+}
+
+#[debug_handler]
+pub async fn list_sessions(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let sessions: Vec<SessionOutgoing> = match state.sessions.list_sessions(&claims.userid).await {
+        Ok(res) => res
+            .into_iter()
+            .map(|session| SessionOutgoing::new(session, &claims.sid))
+            .collect(),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok((StatusCode::OK, Json(json!(sessions))))
+}
+
+#[debug_handler]
+pub async fn delete_session(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Path(session_id): Path<String>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    match end_session(&state, &claims.userid, &session_id).await {
+        Ok(true) => Ok((
+            StatusCode::OK,
+            Json(json!(doc! {"response": "Session revoked succesfully"})),
+        )),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,176 @@
+use axum::Json;
+use axum_macros::debug_handler;
+use hyper::StatusCode;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    auth::extractor::AuthUser,
+    db::{
+        connect::DbState,
+        models::{Errors, Notes},
+    },
+    StateExtension,
+};
+
+use super::revisions::record_revision;
+
+pub const MAX_TAGS: usize = 20;
+pub const MAX_TAG_LENGTH: usize = 32;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RenameTag {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeleteTag {
+    tag: String,
+}
+
+/// Tags are compared trimmed and lowercased. Commas are refused, they
+/// separate tags in the `tags` filter of `get_notes`.
+pub fn normalize_tag(tag: &str) -> Result<String, StatusCode> {
+    let tag = tag.trim().to_lowercase();
+
+    if tag.is_empty() || tag.chars().count() > MAX_TAG_LENGTH || tag.contains(',') {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    Ok(tag)
+}
+
+/// The tags of a note as stored: normalized, sorted and without duplicates.
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>, StatusCode> {
+    let mut normalized = tags
+        .iter()
+        .map(|tag| normalize_tag(tag))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    normalized.sort();
+    normalized.dedup();
+
+    if normalized.len() > MAX_TAGS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    Ok(normalized)
+}
+
+/// Saves the notes carrying `tag` that were written before revisions were
+/// kept as revision 0, trash included, so a rewrite of the tag doesn't lose
+/// their content. See `update_with_revision`.
+async fn record_legacy_revisions(state: &DbState, user: &str, tag: &str) -> Result<(), Errors> {
+    let tags = [tag.to_string()];
+
+    let mut notes = state.notes.list_notes_with_tags(user, &tags, false).await?;
+    notes.extend(
+        state
+            .notes
+            .list_trash(user)
+            .await?
+            .into_iter()
+            .filter(|note| note.tags.contains(&tags[0])),
+    );
+
+    for note in notes.iter().filter(|note| note.revision == 0) {
+        record_revision(state, note).await?;
+    }
+
+    Ok(())
+}
+
+/// Saves every note changed by a tag rewrite as a new revision.
+async fn record_revisions(state: &DbState, notes: &[Notes]) -> Result<(), Errors> {
+    for note in notes {
+        record_revision(state, note).await?;
+    }
+
+    Ok(())
+}
+
+#[debug_handler]
+pub async fn list_tags(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    match state.notes.list_tags(&claims.userid).await {
+        Ok(res) => Ok((StatusCode::OK, Json(json!(res)))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Renames `from` to `to` on every note, merging them when `to` is in use.
+#[debug_handler]
+pub async fn rename_tag(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<RenameTag>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let from = normalize_tag(&req.from)?;
+    let to = normalize_tag(&req.to)?;
+
+    if from == to {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    if let Err(e) = record_legacy_revisions(&state, &claims.userid, &from).await {
+        return Err(e.into());
+    }
+
+    let notes = match state.notes.rename_tag(&claims.userid, &from, &to).await {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    if notes.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    if let Err(e) = record_revisions(&state, &notes).await {
+        return Err(e.into());
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {
+            "response": format!("Tag renamed on {} notes", notes.len()),
+        })),
+    ))
+}
+
+/// Removes the tag from every note, the notes themselves stay.
+#[debug_handler]
+pub async fn delete_tag(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<DeleteTag>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let tag = normalize_tag(&req.tag)?;
+
+    if let Err(e) = record_legacy_revisions(&state, &claims.userid, &tag).await {
+        return Err(e.into());
+    }
+
+    let notes = match state.notes.remove_tag(&claims.userid, &tag).await {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
+    };
+
+    if notes.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    if let Err(e) = record_revisions(&state, &notes).await {
+        return Err(e.into());
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {
+            "response": format!("Tag removed from {} notes", notes.len()),
+        })),
+    ))
+}
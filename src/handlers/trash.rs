@@ -0,0 +1,101 @@
+use std::env;
+
+use axum::Json;
+use axum_macros::debug_handler;
+use chrono::{prelude::*, Duration};
+use hyper::StatusCode;
+use mongodb::bson::doc;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    auth::extractor::AuthUser,
+    db::{connect::DbState, models::Errors},
+    StateExtension,
+};
+
+use super::notes::NotesOutgoing;
+
+/// Seconds a note stays in the trash before it is deleted for good,
+/// `NOTES_TRASH_RETENTION_SECS` (default 30 days).
+pub static TRASH_RETENTION_SECS: Lazy<i64> =
+    Lazy::new(|| match env::var("NOTES_TRASH_RETENTION_SECS") {
+        Ok(res) => match res.parse() {
+            Ok(secs) if secs >= 0 => secs,
+            _ => panic!("Error: NOTES_TRASH_RETENTION_SECS must be a number of seconds"),
+        },
+        Err(_) => 30 * 24 * 60 * 60,
+    });
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RestoreNotes {
+    notes_id: Vec<String>,
+}
+
+/// Deletes the notes whose time in the trash is over and returns how many.
+pub async fn purge_expired_trash(state: &DbState) -> Result<u64, Errors> {
+    let before = Utc::now() - Duration::try_seconds(*TRASH_RETENTION_SECS).unwrap();
+
+    state.notes.purge_trash(before).await
+}
+
+#[debug_handler]
+pub async fn get_trash(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let notes: Vec<NotesOutgoing> = match state.notes.list_trash(&claims.userid).await {
+        Ok(res) => res.into_iter().map(NotesOutgoing::from).collect(),
+        Err(e) => return Err(e.into()),
+    };
+
+    if notes.is_empty() {
+        return Err(StatusCode::NO_CONTENT);
+    }
+
+    Ok((StatusCode::OK, Json(json!(notes))))
+}
+
+#[debug_handler]
+pub async fn restore_notes(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<RestoreNotes>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    if req.notes_id.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match state
+        .notes
+        .restore_notes(&claims.userid, &req.notes_id)
+        .await
+    {
+        Ok(0) => Err(StatusCode::NOT_FOUND),
+        Ok(restored) => Ok((
+            StatusCode::OK,
+            Json(json!(doc! {
+                "response": format!("{} notes restored", restored),
+            })),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Deletes every note in the trash of the caller for good.
+#[debug_handler]
+pub async fn empty_trash(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    match state.notes.empty_trash(&claims.userid).await {
+        Ok(deleted) => Ok((
+            StatusCode::OK,
+            Json(json!(doc! {
+                "response": format!("{} notes deleted", deleted),
+            })),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,127 @@
+use axum::Json;
+use axum_macros::debug_handler;
+use hyper::StatusCode;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    auth::{
+        extractor::{AuthUser, ClientInfo},
+        totp::{matching_step, new_recovery_codes, new_secret, otpauth_url},
+    },
+    StateExtension,
+};
+
+use super::{
+    admin::{find_user, save_user},
+    profile::confirm_password_and_code,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfirmTwoFactor {
+    code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DisableTwoFactor {
+    password: String,
+    code: String,
+}
+
+/// Starts enrollment with a new secret. Nothing changes for logins until it
+/// is confirmed with a code from the authenticator app.
+#[debug_handler]
+pub async fn setup_two_factor(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let mut user = find_user(&state, &claims.userid).await?;
+
+    if user.totp_enabled {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let secret = new_secret();
+
+    let url = match otpauth_url(&secret, &user.username) {
+        Some(res) => res,
+        None => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    user.totp_secret = Some(secret.clone());
+    save_user(&state, &user).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {"secret": secret, "otpauth_url": url})),
+    ))
+}
+
+/// Turns two-factor authentication on and hands out the recovery codes,
+/// which are only ever shown here.
+#[debug_handler]
+pub async fn confirm_two_factor(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<ConfirmTwoFactor>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let mut user = find_user(&state, &claims.userid).await?;
+
+    if user.totp_enabled {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let secret = match &user.totp_secret {
+        Some(res) => res,
+        None => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let step = match matching_step(secret, &user.username, &req.code) {
+        Some(res) => res,
+        None => return Err(StatusCode::UNAUTHORIZED),
+    };
+
+    let (codes, hashes) = new_recovery_codes();
+
+    user.totp_enabled = true;
+    user.totp_last_step = Some(step);
+    user.recovery_codes = hashes;
+    save_user(&state, &user).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {
+            "response": "Two-factor authentication enabled",
+            "recovery_codes": codes,
+        })),
+    ))
+}
+
+#[debug_handler]
+pub async fn disable_two_factor(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+    client: ClientInfo,
+    Json(req): Json<DisableTwoFactor>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    if !find_user(&state, &claims.userid).await?.totp_enabled {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let mut user =
+        confirm_password_and_code(&state, &client, &claims, &req.password, &req.code).await?;
+
+    user.totp_secret = None;
+    user.totp_enabled = false;
+    user.totp_last_step = None;
+    user.recovery_codes = Vec::new();
+    save_user(&state, &user).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(
+            doc! {"response": "Two-factor authentication disabled"}
+        )),
+    ))
+}
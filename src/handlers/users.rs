@@ -1,26 +1,36 @@
-use axum::Json;
+use axum::{extract::Extension, Json};
 use axum_macros::debug_handler;
-use futures::TryStreamExt;
-use hyper::{HeaderMap, StatusCode};
-use mongodb::{
-    bson::doc,
-    options::{FindOneOptions, FindOptions},
-};
+use hyper::StatusCode;
+use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::db::{connect::USERS, models::UserOptions};
-use crate::utils::{get_token::get_token, random_id::random_id};
+use crate::db::models::UserOptions;
+use crate::utils::random_id::random_id;
 use crate::{
     auth::{
-        bcrypt::{compare, encrypt},
-        jwt::{compare_jwt, create_jwt},
+        extractor::{AuthUser, ClientInfo},
+        one_time::{consume_one_time_token, issue_one_time_token},
+        password::{dummy_verify, hash_password, verify_password},
+        password_reset::{reset_password, send_password_reset},
+        policy::{
+            check_password, check_username, normalize_username, policy_response, username_key,
+        },
+        refresh::rotate_refresh_token,
+        revoke::{revoke_all_sessions, revoke_session},
+        session::start_session,
+        throttle::{check_login_allowed, clear_login_failures, record_login_failure},
+        totp::{verify_second_factor, LOGIN_CHALLENGE_TTL},
+        verification::{resend_verification_email, send_verification_email, verify_email},
     },
     db::models::Errors,
 };
 use crate::{
-    db::{connect::database_coll, models::User},
-    StateExtension,
+    db::{
+        connect::DbState,
+        models::{Role, TokenPurpose, User},
+    },
+    MailerExtension, StateExtension,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -42,85 +52,77 @@ pub struct LogUser {
     password: String,
 }
 
-#[debug_handler]
-pub async fn list_users(state: StateExtension) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let user_coll = database_coll::<User>(&state.db, USERS).await;
-
-    let find_options = FindOptions::builder().sort(doc! {}).build();
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorLogIn {
+    challenge_token: String,
+    code: String,
+}
 
-    let mut cursor = if let Ok(cursor) = user_coll.find(None, find_options).await {
-        cursor
-    } else {
-        panic!("Error")
-    };
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
 
-    let mut result = Vec::new();
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyEmail {
+    token: String,
+}
 
-    while let Some(users) = cursor.try_next().await.unwrap() {
-        result.push(users)
-    }
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RequestPasswordReset {
+    username: String,
+}
 
-    Ok((StatusCode::OK, Json(json!(result))))
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfirmPasswordReset {
+    token: String,
+    password: String,
 }
 
 #[debug_handler]
 pub async fn user_check(
     state: StateExtension,
-    headers: HeaderMap,
+    AuthUser(claims): AuthUser,
 ) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let coll = database_coll::<CheckUser>(&state.db, USERS).await;
-
-    let token = match get_token(&headers) {
-        Ok(res) => res,
-        Err(e) => return Err(e),
-    };
-
-    let claims = match compare_jwt(&token).await {
-        Ok(res) => res,
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::UNAUTHORIZED);
-        }
-    };
-
-    let filters = doc! {"user_id": &claims.claims.userid};
-
-    let opts = FindOneOptions::builder()
-        .projection(doc! { "username": 1, "email": 1 })
-        .build();
-
-    match coll.find_one(filters, opts).await {
+    match state.users.find_user_by_id(&claims.userid).await {
         Ok(res) => match res {
-            Some(user) => Ok((StatusCode::OK, Json(json!(user)))),
+            Some(user) => Ok((
+                StatusCode::OK,
+                Json(json!(CheckUser {
+                    username: user.username,
+                    email: user.email,
+                })),
+            )),
             None => Err(StatusCode::NOT_FOUND),
         },
-        Err(e) => {
-            println!("Error: {:?}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
-        }
+        Err(e) => Err(e.into()),
     }
 }
 
 #[debug_handler]
 pub async fn create_user(
     state: StateExtension,
+    Extension(mailer): MailerExtension,
+    client: ClientInfo,
     Json(req): Json<CreateUser>,
 ) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let coll = database_coll::<User>(&state.db, USERS).await;
-    let filters = doc! {"username": &req.username};
+    let mut failures = check_username(&req.username);
+    failures.extend(check_password(&req.password, Some(&req.username)));
 
-    match coll.find_one(filters, None).await {
-        Ok(cursor) => match cursor {
-            None => {}
-            Some(_) => return Err(StatusCode::CONFLICT),
-        },
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    if !failures.is_empty() {
+        return Ok(policy_response(&failures));
+    }
+
+    match state.users.find_user_by_username(&req.username).await {
+        Ok(res) => {
+            if res.is_some() {
+                return Err(StatusCode::CONFLICT);
+            }
         }
+        Err(e) => return Err(e.into()),
     };
 
-    let encoded_pass = match encrypt(&req.password).await {
+    let encoded_pass = match hash_password(&req.password).await {
         Ok(pass) => pass,
         Err(e) => {
             println!("Error: {:?}", e);
@@ -128,77 +130,66 @@ pub async fn create_user(
         }
     };
 
-    let userid = random_id();
-
-    let email = if let Some(email) = req.email {
-        Some(email)
-    } else {
-        None
-    };
-
     let data = User {
-        user_id: userid.clone(),
-        username: req.username.clone(),
+        user_id: random_id(),
+        username: normalize_username(&req.username),
+        username_key: username_key(&req.username),
         password: encoded_pass,
-        email: email.clone(),
-        ip: None,
+        email: req.email.clone(),
+        ip: client.ip.clone(),
+        role: Role::User,
+        disabled: false,
+        ..Default::default()
     };
 
-    match coll.insert_one(&data, None).await {
-        Ok(_) => {}
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    };
+    if let Err(e) = state.users.insert_user(&data).await {
+        return Err(e.into());
+    }
 
-    let token = match create_jwt(req.username.clone(), userid, email).await {
-        Ok(token) => token,
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    };
+    // The account exists by now, failing here would leave the client unable
+    // to sign up again with the same username. The email can be resent.
+    if let Err(e) = send_verification_email(&state, &mailer, &data).await {
+        println!("Error: {:?}", e);
+    }
+
+    let tokens = start_session(&state, &data, &client).await?;
 
-    let user_options = match UserOptions::create(coll, data.user_id, state) {
+    let user_options = match UserOptions::create(&state, data.user_id).await {
         Ok(msg) => msg,
-        Err(e) => match e {
-            Errors::Mongo(e) => {
-                println!("Error: {:?}", e);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-            Errors::Status(res) => return Err(res),
-        },
+        Err(e) => return Err(e.into()),
     };
 
     Ok((
         StatusCode::OK,
-        Json(json!(
-            doc! {"response": format!("User Created ({:?})", user_options), "token": token}
-        )),
+        Json(json!(doc! {
+            "response": format!("User Created ({:?})", user_options),
+            "token": tokens.access_token,
+            "refresh_token": tokens.refresh_token,
+            "expires_in": tokens.expires_in,
+        })),
     ))
 }
 
 #[debug_handler]
 pub async fn log_in(
     state: StateExtension,
+    client: ClientInfo,
     Json(req): Json<LogUser>,
 ) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let coll = database_coll::<User>(&state.db, USERS).await;
-    let filters = doc! {"username": &req.username};
+    check_login_allowed(&state, &req.username, &client).await?;
 
-    let user_stored = match coll.find_one(filters, None).await {
-        Ok(res) => match res {
-            Some(user) => user,
-            _ => return Err(StatusCode::NOT_FOUND),
-        },
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+    let user_stored = match state.users.find_user_by_username(&req.username).await {
+        Ok(res) => res,
+        Err(e) => return Err(e.into()),
     };
 
-    let authenticated = match compare(&req.password, &user_stored.password).await {
+    // Unknown users and wrong passwords get the same answer in the same time.
+    let verification = match &user_stored {
+        Some(user) => verify_password(&req.password, &user.password).await,
+        None => dummy_verify(&req.password).await,
+    };
+
+    let verification = match verification {
         Ok(res) => res,
         Err(e) => {
             println!("Error: {:?}", e);
@@ -206,75 +197,294 @@ pub async fn log_in(
         }
     };
 
-    let email;
+    let mut user_stored = match user_stored {
+        Some(user) if verification.valid => user,
+        _ => {
+            if let Err(e) = record_login_failure(&state, &req.username, &client).await {
+                return Err(e.into());
+            }
+
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    };
 
-    if authenticated {
-        email = if let Some(e) = user_stored.email {
-            Some(e)
-        } else {
-            None
+    if user_stored.disabled {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Old bcrypt hashes and hashes with outdated parameters are replaced
+    // while the plain password is at hand. A failure here must not fail the
+    // login, the next one tries again.
+    if verification.needs_rehash {
+        match hash_password(&req.password).await {
+            Ok(res) => {
+                user_stored.password = res;
+
+                if let Err(e) = state.users.update_user(&user_stored).await {
+                    println!("Error: {:?}", e);
+                }
+            }
+            Err(e) => println!("Error: {:?}", e),
+        }
+    }
+
+    if user_stored.totp_enabled {
+        let challenge = match issue_one_time_token(
+            &state,
+            &user_stored.user_id,
+            TokenPurpose::LoginChallenge,
+            LOGIN_CHALLENGE_TTL,
+        )
+        .await
+        {
+            Ok(res) => res,
+            Err(e) => return Err(e.into()),
         };
-    } else {
+
+        return Ok((
+            StatusCode::ACCEPTED,
+            Json(json!(doc! {
+                "response": "Two-factor code required",
+                "challenge_token": challenge,
+                "expires_in": LOGIN_CHALLENGE_TTL,
+            })),
+        ));
+    }
+
+    finish_log_in(&state, &client, user_stored).await
+}
+
+/// Second step of `log_in` for accounts with two-factor authentication. A
+/// challenge allows a single attempt, after a wrong code log in again.
+#[debug_handler]
+pub async fn log_in_two_factor(
+    state: StateExtension,
+    client: ClientInfo,
+    Json(req): Json<TwoFactorLogIn>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let user_id =
+        match consume_one_time_token(&state, &req.challenge_token, TokenPurpose::LoginChallenge)
+            .await
+        {
+            Ok(res) => match res {
+                Some(user_id) => user_id,
+                None => return Err(StatusCode::UNAUTHORIZED),
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+    let mut user_stored = match state.users.find_user_by_id(&user_id).await {
+        Ok(res) => match res {
+            Some(user) => user,
+            None => return Err(StatusCode::UNAUTHORIZED),
+        },
+        Err(e) => return Err(e.into()),
+    };
+
+    if user_stored.disabled {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    check_login_allowed(&state, &user_stored.username, &client).await?;
+
+    if !user_stored.totp_enabled || !verify_second_factor(&mut user_stored, &req.code) {
+        if let Err(e) = record_login_failure(&state, &user_stored.username, &client).await {
+            return Err(e.into());
+        }
+
         return Err(StatusCode::UNAUTHORIZED);
     }
 
-    let token = match create_jwt(req.username, user_stored.user_id.clone(), email).await {
-        Ok(token) => token,
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    if let Err(e) = state.users.update_user(&user_stored).await {
+        return Err(e.into());
+    }
+
+    finish_log_in(&state, &client, user_stored).await
+}
+
+/// Starts the session of a user who passed every login check.
+pub async fn finish_log_in(
+    state: &DbState,
+    client: &ClientInfo,
+    mut user_stored: User,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    if let Err(e) = clear_login_failures(state, &user_stored.username).await {
+        return Err(e.into());
+    }
+
+    if client.ip.is_some() && client.ip != user_stored.ip {
+        user_stored.ip = client.ip.clone();
+
+        if let Err(e) = state.users.update_user(&user_stored).await {
+            return Err(e.into());
         }
-    };
+    }
 
-    let user_options = match UserOptions::create(coll, user_stored.user_id, state) {
+    let tokens = start_session(state, &user_stored, client).await?;
+
+    let user_options = match UserOptions::create(state, user_stored.user_id).await {
         Ok(msg) => msg,
         Err(e) => match e {
-            Errors::Mongo(e) => {
-                println!("Error: {:?}", e);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-            Errors::Status(e) => match e {
-                StatusCode::CONFLICT => String::from("UserOptions alredy exists"),
-                _ => return Err(e),
-            },
+            Errors::Status(StatusCode::CONFLICT) => String::from("UserOptions alredy exists"),
+            e => return Err(e.into()),
         },
     };
 
+    let mut res = doc! {
+        "response": format!("Login Successful ({})", user_options),
+        "token": tokens.access_token,
+        "refresh_token": tokens.refresh_token,
+        "expires_in": tokens.expires_in,
+    };
+
+    // Lets the client offer to cancel a pending account deletion.
+    if let Some(due) = user_stored.deletion_scheduled_at {
+        res.insert("deletion_scheduled_at", due.to_rfc3339());
+    }
+
+    Ok((StatusCode::OK, Json(json!(res))))
+}
+
+#[debug_handler]
+pub async fn refresh(
+    state: StateExtension,
+    client: ClientInfo,
+    Json(req): Json<RefreshRequest>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let tokens = rotate_refresh_token(&state, &req.refresh_token, &client).await?;
+
     Ok((
         StatusCode::OK,
-        Json(json!(
-            doc! {"response": format!("Login Successful ({})", user_options), "token": token}
-        )),
+        Json(json!(doc! {
+            "token": tokens.access_token,
+            "refresh_token": tokens.refresh_token,
+            "expires_in": tokens.expires_in,
+        })),
     ))
 }
 
 #[debug_handler]
 pub async fn get_user_options(
     state: StateExtension,
-    headers: HeaderMap,
+    AuthUser(claims): AuthUser,
 ) -> Result<(StatusCode, Json<Value>), StatusCode> {
-    let coll = database_coll::<User>(&state.db, USERS).await;
-
-    let token = match get_token(&headers) {
+    let user_options = match UserOptions::get(&state, claims.userid).await {
         Ok(res) => res,
-        Err(e) => return Err(e),
+        Err(e) => return Err(e.into()),
     };
 
-    let claims = match compare_jwt(&token).await {
+    Ok((StatusCode::OK, Json(json!(user_options))))
+}
+
+#[debug_handler]
+pub async fn logout(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    if let Err(e) = revoke_session(&state, &claims).await {
+        return Err(e.into());
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {"response": "Logged out succesfully"})),
+    ))
+}
+
+#[debug_handler]
+pub async fn logout_all(
+    state: StateExtension,
+    AuthUser(claims): AuthUser,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    if let Err(e) = revoke_all_sessions(&state, &claims.userid).await {
+        return Err(e.into());
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(
+            doc! {"response": "All sessions logged out succesfully"}
+        )),
+    ))
+}
+
+/// Always answers the same, so it can't be used to find out which accounts
+/// exist or have an email.
+#[debug_handler]
+pub async fn request_password_reset(
+    state: StateExtension,
+    Extension(mailer): MailerExtension,
+    Json(req): Json<RequestPasswordReset>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let user = match state.users.find_user_by_username(&req.username).await {
         Ok(res) => res,
-        Err(_) => return Err(StatusCode::UNAUTHORIZED),
+        Err(e) => return Err(e.into()),
     };
 
-    let user_options = match UserOptions::get(coll, claims.claims.userid, state) {
-        Ok(res) => res,
-        Err(e) => match e {
-            Errors::Mongo(e) => {
-                println!("Error: {:?}", e);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-            Errors::Status(e) => return Err(e),
+    if let Some(user) = user {
+        if let Err(e) = send_password_reset(&state, &mailer, &user).await {
+            return Err(e.into());
+        }
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {
+            "response": "If the account has an email, a reset link was sent to it"
+        })),
+    ))
+}
+
+#[debug_handler]
+pub async fn confirm_password_reset(
+    state: StateExtension,
+    Json(req): Json<ConfirmPasswordReset>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let failures = check_password(&req.password, None);
+
+    if !failures.is_empty() {
+        return Ok(policy_response(&failures));
+    }
+
+    reset_password(&state, &req.token, &req.password).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {"response": "Password reset succesfully"})),
+    ))
+}
+
+#[debug_handler]
+pub async fn confirm_email(
+    state: StateExtension,
+    Json(req): Json<VerifyEmail>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    verify_email(&state, &req.token).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {"response": "Email verified succesfully"})),
+    ))
+}
+
+#[debug_handler]
+pub async fn resend_verification(
+    state: StateExtension,
+    Extension(mailer): MailerExtension,
+    AuthUser(claims): AuthUser,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let user = match state.users.find_user_by_id(&claims.userid).await {
+        Ok(res) => match res {
+            Some(user) => user,
+            None => return Err(StatusCode::NOT_FOUND),
         },
+        Err(e) => return Err(e.into()),
     };
 
-    Ok((StatusCode::OK, Json(json!(user_options))))
+    resend_verification_email(&state, &mailer, &user).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!(doc! {"response": "Verification email sent"})),
+    ))
 }
@@ -0,0 +1,3 @@
+pub mod local;
+pub mod mailer;
+pub mod smtp;
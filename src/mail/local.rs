@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::prelude::*;
+
+use crate::utils::random_id::random_id;
+
+use super::mailer::{Email, MailError, Mailer};
+
+/// Prints emails to stdout instead of sending them, for local development.
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        println!(
+            "Email to {:?}\nSubject: {}\n\n{}\n",
+            email.to, email.subject, email.body
+        );
+
+        Ok(())
+    }
+}
+
+/// Writes every email as an `.eml` file into `dir`, for local development.
+pub struct FileMailer {
+    pub dir: PathBuf,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message = email.to_message()?;
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        let name = format!("{}-{}.eml", Utc::now().timestamp_millis(), random_id());
+
+        tokio::fs::write(self.dir.join(name), message.formatted()).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use std::{env, sync::Arc};
+
+use async_trait::async_trait;
+use lettre::{message::header::ContentType, Message};
+use once_cell::sync::Lazy;
+
+use super::{
+    local::{FileMailer, LogMailer},
+    smtp::SmtpMailer,
+};
+
+/// Sender address of every outgoing email, `MAIL_FROM`.
+pub static MAIL_FROM: Lazy<String> = Lazy::new(|| match env::var("MAIL_FROM") {
+    Ok(res) => res,
+    Err(_) => String::from("Note4Keep <no-reply@localhost>"),
+});
+
+/// Public URL of the frontend, `APP_URL`. When set, emails link to it
+/// instead of only carrying the raw token.
+pub static APP_URL: Lazy<Option<String>> = Lazy::new(|| match env::var("APP_URL") {
+    Ok(res) => Some(res.trim_end_matches('/').to_string()),
+    Err(_) => None,
+});
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum MailError {
+    Address(lettre::address::AddressError),
+    Message(lettre::error::Error),
+    Smtp(lettre::transport::smtp::Error),
+    Io(std::io::Error),
+}
+
+impl From<lettre::address::AddressError> for MailError {
+    fn from(e: lettre::address::AddressError) -> Self {
+        MailError::Address(e)
+    }
+}
+
+impl From<lettre::error::Error> for MailError {
+    fn from(e: lettre::error::Error) -> Self {
+        MailError::Message(e)
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for MailError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        MailError::Smtp(e)
+    }
+}
+
+impl From<std::io::Error> for MailError {
+    fn from(e: std::io::Error) -> Self {
+        MailError::Io(e)
+    }
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), MailError>;
+}
+
+impl Email {
+    pub fn to_message(&self) -> Result<Message, MailError> {
+        Ok(Message::builder()
+            .from(MAIL_FROM.parse()?)
+            .to(self.to.parse()?)
+            .subject(&self.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(self.body.clone())?)
+    }
+}
+
+/// Sends `email` in the background. Failures are only logged, so the time a
+/// request takes doesn't tell whether an email went out.
+pub fn send_in_background(mailer: &Arc<dyn Mailer>, email: Email) {
+    let mailer = mailer.clone();
+
+    tokio::spawn(async move {
+        if let Err(e) = mailer.send(&email).await {
+            println!("Error: {:?}", e);
+        }
+    });
+}
+
+/// Mail transport picked with the `MAIL_BACKEND` env var. There is no
+/// default: `log` and `file` keep the reset and verification tokens in
+/// plain sight, they must be asked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MailBackend {
+    Log,
+    File,
+    Smtp,
+}
+
+impl MailBackend {
+    pub fn from_env() -> MailBackend {
+        match env::var("MAIL_BACKEND") {
+            Ok(res) => match res.to_lowercase().as_str() {
+                "log" => MailBackend::Log,
+                "file" => MailBackend::File,
+                "smtp" => MailBackend::Smtp,
+                other => panic!("Error: unknown MAIL_BACKEND {:?}", other),
+            },
+            Err(_) => panic!(
+                "Error: MAIL_BACKEND is not set, pick smtp, or log or file for local development"
+            ),
+        }
+    }
+}
+
+pub fn connect_mailer() -> Arc<dyn Mailer> {
+    match MailBackend::from_env() {
+        MailBackend::Log => Arc::new(LogMailer),
+        MailBackend::File => {
+            let dir = env::var("MAIL_DIR").unwrap_or(String::from("mail"));
+
+            println!("Writing outgoing emails to {}", dir);
+            Arc::new(FileMailer { dir: dir.into() })
+        }
+        MailBackend::Smtp => match SmtpMailer::from_env() {
+            Ok(mailer) => Arc::new(mailer),
+            Err(e) => panic!("Error: {:?}", e),
+        },
+    }
+}
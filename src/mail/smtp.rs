@@ -0,0 +1,61 @@
+use std::env;
+
+use async_trait::async_trait;
+use lettre::{
+    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
+    Tokio1Executor,
+};
+
+use super::mailer::{Email, MailError, Mailer};
+
+/// Sends emails through an SMTP relay configured with `SMTP_HOST`,
+/// `SMTP_PORT`, `SMTP_USERNAME`, `SMTP_PASSWORD` and `SMTP_TLS` (`starttls`
+/// by default, `tls` for implicit TLS or `none` for a local test server).
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    pub fn from_env() -> Result<SmtpMailer, MailError> {
+        let host = match env::var("SMTP_HOST") {
+            Ok(res) => res,
+            Err(_) => panic!("Error: MAIL_BACKEND is smtp but there is no SMTP_HOST"),
+        };
+
+        let mut builder = match env::var("SMTP_TLS")
+            .unwrap_or(String::from("starttls"))
+            .to_lowercase()
+            .as_str()
+        {
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&host)?,
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)?,
+            other => panic!("Error: unknown SMTP_TLS {:?}", other),
+        };
+
+        if let Ok(port) = env::var("SMTP_PORT") {
+            match port.parse() {
+                Ok(port) => builder = builder.port(port),
+                Err(_) => panic!("Error: SMTP_PORT must be a port number"),
+            }
+        }
+
+        if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD"))
+        {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        self.transport.send(email.to_message()?).await?;
+
+        Ok(())
+    }
+}
@@ -1,24 +1,55 @@
-use std::{sync::Arc, time::Duration};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{
     error_handling::HandleErrorLayer,
     extract::Extension,
+    http::header,
+    middleware,
     routing::{delete, get, patch, post},
     Router,
 };
 use dotenv::dotenv;
 use hyper::StatusCode;
 
+use crate::auth::{
+    bootstrap::bootstrap_admin,
+    keys::init_key_ring,
+    password::init_dummy_hash,
+    permissions::{require_permission, Permission},
+};
 use crate::db::connect::connect_db;
 use crate::db::connect::DbState;
 use crate::handlers::{
+    account::{cancel_account_deletion, delete_account},
+    admin::{
+        delete_user, disable_user, enable_user, list_users, reset_password, set_role, view_user,
+    },
+    api_keys::{delete_api_key, list_api_keys, new_api_key},
+    export::export_data,
+    keys::jwks,
+    notebooks::{
+        create_notebook, delete_notebook, get_notebooks, move_notes, notebook_notes,
+        update_notebook,
+    },
     notes::{
         create_note, delete_all_notes, delete_notes, delete_spec_note, get_notes, some_note,
         spec_note, update_note,
     },
-    users::{create_user, get_user_options, list_users, log_in, user_check},
+    oidc::{oidc_callback, oidc_login},
+    profile::{change_email, change_password, change_username},
+    revisions::{diff_revisions, get_revision, list_revisions, restore_revision},
+    sessions::{delete_session, list_sessions},
+    tags::{delete_tag, list_tags, rename_tag},
+    trash::{empty_trash, get_trash, restore_notes},
+    two_factor::{confirm_two_factor, disable_two_factor, setup_two_factor},
+    users::{
+        confirm_email, confirm_password_reset, create_user, get_user_options, log_in,
+        log_in_two_factor, logout, logout_all, refresh, request_password_reset,
+        resend_verification, user_check,
+    },
 };
-use crate::utils::check_integrity::check_integrity;
+use crate::mail::mailer::{connect_mailer, Mailer};
+use crate::utils::{check_integrity::check_integrity, cleanup::spawn_cleanup};
 use tower::{
     timeout::{error, TimeoutLayer},
     BoxError, ServiceBuilder,
@@ -30,9 +61,11 @@ use std::env;
 pub mod auth;
 pub mod db;
 pub mod handlers;
+pub mod mail;
 pub mod utils;
 
 type StateExtension = axum::extract::Extension<Arc<DbState>>;
+type MailerExtension = axum::extract::Extension<Arc<dyn Mailer>>;
 
 pub async fn handle_timeout_error(err: BoxError) -> StatusCode {
     if err.is::<error::Elapsed>() {
@@ -42,42 +75,129 @@ pub async fn handle_timeout_error(err: BoxError) -> StatusCode {
     }
 }
 
+/// Only lets through requests whose token grants `permission`.
+fn with_permission(router: Router, permission: Permission) -> Router {
+    router.route_layer(middleware::from_fn(move |req, next| {
+        require_permission(permission, req, next)
+    }))
+}
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
     check_integrity();
 
-    let db_state = Arc::new(DbState {
-        db: connect_db().await,
-    });
+    init_key_ring();
+
+    init_dummy_hash();
+
+    let db_state = Arc::new(connect_db().await);
+
+    let mailer = connect_mailer();
+
+    bootstrap_admin(&db_state).await;
+
+    spawn_cleanup(db_state.clone());
 
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers([header::ETAG]);
 
     let timeout_middleware = ServiceBuilder::new()
         .layer(HandleErrorLayer::new(handle_timeout_error))
         .layer(TimeoutLayer::new(Duration::from_secs(60)));
 
-    let app = Router::new()
-        .route("/api/users", get(list_users))
-        .route("/api/users/check", post(user_check))
+    let notes_read = Router::new()
         .route("/api/notes", post(get_notes))
-        .route("/api/users/create-user", post(create_user))
-        .route("/api/users/login", post(log_in))
-        .route("/api/notes/create-note", post(create_note))
         .route("/api/notes/some-note", post(some_note))
         .route("/api/notes/spec-note", post(spec_note))
+        .route("/api/notes/tags", get(list_tags))
+        .route("/api/notes/trash", get(get_trash))
+        .route("/api/notes/revisions", post(list_revisions))
+        .route("/api/notes/revisions/get", post(get_revision))
+        .route("/api/notes/revisions/diff", post(diff_revisions))
+        .route("/api/notebooks", get(get_notebooks))
+        .route("/api/notebooks/notes", post(notebook_notes));
+
+    let notes_write = Router::new()
+        .route("/api/notes/create-note", post(create_note))
         .route("/api/notes/delete-spec-note", delete(delete_spec_note))
         .route("/api/notes/delete-notes", delete(delete_notes))
         .route("/api/notes/delete-all-notes", delete(delete_all_notes))
         .route("/api/notes/update-note", patch(update_note))
+        .route("/api/notes/tags/rename", patch(rename_tag))
+        .route("/api/notes/tags/delete", delete(delete_tag))
+        .route("/api/notes/move-notes", patch(move_notes))
+        .route("/api/notes/trash/restore", patch(restore_notes))
+        .route("/api/notes/trash/empty", delete(empty_trash))
+        .route("/api/notes/revisions/restore", patch(restore_revision))
+        .route("/api/notebooks/create-notebook", post(create_notebook))
+        .route("/api/notebooks/update-notebook", patch(update_notebook))
+        .route("/api/notebooks/delete-notebook", delete(delete_notebook));
+
+    let users_read = Router::new()
+        .route("/api/admin/users", get(list_users))
+        .route("/api/admin/users/:user_id", get(view_user));
+
+    let users_manage = Router::new()
+        .route("/api/admin/users/:user_id", delete(delete_user))
+        .route("/api/admin/users/:user_id/disable", patch(disable_user))
+        .route("/api/admin/users/:user_id/enable", patch(enable_user))
+        .route("/api/admin/users/:user_id/role", patch(set_role))
+        .route(
+            "/api/admin/users/:user_id/reset-password",
+            patch(reset_password),
+        );
+
+    let app = Router::new()
+        .route("/.well-known/jwks.json", get(jwks))
+        .route("/api/users/check", post(user_check))
+        .route("/api/users/create-user", post(create_user))
+        .route("/api/users/login", post(log_in))
+        .route("/api/users/login/two-factor", post(log_in_two_factor))
+        .route("/api/users/oidc/login", get(oidc_login))
+        .route("/api/users/oidc/callback", get(oidc_callback))
+        .route("/api/users/refresh", post(refresh))
+        .route("/api/users/logout", post(logout))
+        .route("/api/users/logout-all", post(logout_all))
+        .route(
+            "/api/users/password-reset/request",
+            post(request_password_reset),
+        )
+        .route(
+            "/api/users/password-reset/confirm",
+            post(confirm_password_reset),
+        )
+        .route("/api/users/verify-email", post(confirm_email))
+        .route("/api/users/verify-email/resend", post(resend_verification))
+        .route("/api/users/two-factor/setup", post(setup_two_factor))
+        .route("/api/users/two-factor/confirm", post(confirm_two_factor))
+        .route("/api/users/two-factor/disable", post(disable_two_factor))
+        .route("/api/users/sessions", get(list_sessions))
+        .route("/api/users/sessions/:session_id", delete(delete_session))
+        .route("/api/users/api-keys", get(list_api_keys).post(new_api_key))
+        .route("/api/users/api-keys/:key_id", delete(delete_api_key))
         .route("/api/users/get-user-options", post(get_user_options))
+        .route("/api/users/change-username", patch(change_username))
+        .route("/api/users/change-email", patch(change_email))
+        .route("/api/users/change-password", patch(change_password))
+        .route("/api/users/export", get(export_data))
+        .route("/api/users/delete-account", post(delete_account))
+        .route(
+            "/api/users/delete-account/cancel",
+            post(cancel_account_deletion),
+        )
+        .merge(with_permission(notes_read, Permission::NotesRead))
+        .merge(with_permission(notes_write, Permission::NotesWrite))
+        .merge(with_permission(users_read, Permission::UsersRead))
+        .merge(with_permission(users_manage, Permission::UsersManage))
         .layer(
             ServiceBuilder::new()
                 .layer(Extension(db_state))
+                .layer(Extension(mailer))
                 .layer(timeout_middleware)
                 .layer(cors),
         );
@@ -93,11 +213,16 @@ async fn main() {
     };
 
     bind_to.push_str(ip);
-    bind_to.push_str(":");
+    bind_to.push(':');
     bind_to.push_str(&port);
 
     println!("The server is open on {}:{}", ip, port);
 
     let listener = tokio::net::TcpListener::bind(bind_to).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
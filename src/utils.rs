@@ -1,4 +1,7 @@
 pub mod check_integrity;
-pub mod get_token;
+pub mod cleanup;
+pub mod etag;
+pub mod http_client;
 pub mod mongo_health;
 pub mod random_id;
+pub mod zip;
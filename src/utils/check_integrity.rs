@@ -1,19 +1,39 @@
 use std::collections::HashMap;
 use std::env;
 
-const IMPORTANT_KEYS: [&str; 3] = ["SECRET", "PORT", "MONGODB_URI"];
+use crate::db::connect::Backend;
+use crate::mail::mailer::MailBackend;
+
+const IMPORTANT_KEYS: [&str; 2] = ["SECRET", "PORT"];
+const MONGO_KEYS: [&str; 1] = ["MONGODB_URI"];
+const SMTP_KEYS: [&str; 1] = ["SMTP_HOST"];
+const OIDC_KEYS: [&str; 2] = ["OIDC_CLIENT_ID", "OIDC_REDIRECT_URI"];
 
 pub fn check_integrity() {
     let all_vars = env::vars();
     let mut comprobe = HashMap::new();
 
-    for key in IMPORTANT_KEYS {
-        comprobe.insert(key, false);
+    let mut keys = IMPORTANT_KEYS.to_vec();
+
+    if Backend::from_env() == Backend::Mongo {
+        keys.extend(MONGO_KEYS);
+    }
+
+    if MailBackend::from_env() == MailBackend::Smtp {
+        keys.extend(SMTP_KEYS);
+    }
+
+    if env::var("OIDC_ISSUER").is_ok() {
+        keys.extend(OIDC_KEYS);
+    }
+
+    for key in &keys {
+        comprobe.insert(*key, false);
     }
 
     for (key, _val) in all_vars {
-        for ik in IMPORTANT_KEYS {
-            if key == ik {
+        for ik in &keys {
+            if key == *ik {
                 comprobe.insert(ik, true);
             }
         }
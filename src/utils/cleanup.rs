@@ -0,0 +1,70 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{prelude::*, Duration as ChronoDuration};
+
+use crate::{
+    auth::{deletion::purge_due_accounts, throttle::FAILURE_WINDOW_SECS},
+    db::connect::DbState,
+    handlers::trash::purge_expired_trash,
+};
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodically drops expired server-side auth state so it doesn't pile up.
+pub fn spawn_cleanup(state: Arc<DbState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match state.tokens.delete_expired_refresh_tokens(Utc::now()).await {
+                Ok(0) => {}
+                Ok(deleted) => println!("Pruned {} expired refresh tokens", deleted),
+                Err(e) => println!("Error: {:?}", e),
+            }
+
+            match state.tokens.delete_expired_revocations(Utc::now()).await {
+                Ok(0) => {}
+                Ok(deleted) => println!("Pruned {} expired token revocations", deleted),
+                Err(e) => println!("Error: {:?}", e),
+            }
+
+            match state.sessions.delete_expired_sessions(Utc::now()).await {
+                Ok(0) => {}
+                Ok(deleted) => println!("Pruned {} expired sessions", deleted),
+                Err(e) => println!("Error: {:?}", e),
+            }
+
+            match state
+                .tokens
+                .delete_expired_one_time_tokens(Utc::now())
+                .await
+            {
+                Ok(0) => {}
+                Ok(deleted) => println!("Pruned {} expired one-time tokens", deleted),
+                Err(e) => println!("Error: {:?}", e),
+            }
+
+            let stale = Utc::now() - ChronoDuration::try_seconds(FAILURE_WINDOW_SECS).unwrap();
+
+            match state.attempts.delete_stale_login_attempts(stale).await {
+                Ok(0) => {}
+                Ok(deleted) => println!("Pruned {} stale failed login entries", deleted),
+                Err(e) => println!("Error: {:?}", e),
+            }
+
+            match purge_due_accounts(&state).await {
+                Ok(0) => {}
+                Ok(purged) => println!("Purged {} accounts scheduled for deletion", purged),
+                Err(e) => println!("Error: {:?}", e),
+            }
+
+            match purge_expired_trash(&state).await {
+                Ok(0) => {}
+                Ok(purged) => println!("Purged {} notes from the trash", purged),
+                Err(e) => println!("Error: {:?}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,141 @@
+use std::env;
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::StatusCode;
+use once_cell::sync::Lazy;
+use serde_json::{json, Map, Value};
+
+use crate::db::models::Errors;
+
+/// Set `REQUIRE_IF_MATCH=true` to refuse note updates and deletes sent
+/// without an `If-Match` header, with 428.
+static REQUIRE_IF_MATCH: Lazy<bool> = Lazy::new(|| match env::var("REQUIRE_IF_MATCH") {
+    Ok(res) => matches!(res.to_lowercase().as_str(), "1" | "true" | "yes"),
+    Err(_) => false,
+});
+
+/// The `ETag` of a note is its revision, quoted.
+pub fn etag(revision: u64) -> String {
+    format!("\"{}\"", revision)
+}
+
+/// `ETag` header of a response about the note at `revision`.
+pub fn etag_header(revision: u64) -> [(header::HeaderName, String); 1] {
+    [(header::ETAG, etag(revision))]
+}
+
+/// Parsed `If-Match` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfMatch {
+    Any,
+    /// Revisions listed. Weak and malformed tags are left out, they never
+    /// match.
+    Revisions(Vec<u64>),
+}
+
+impl IfMatch {
+    pub fn matches(&self, revision: u64) -> bool {
+        match self {
+            IfMatch::Any => true,
+            IfMatch::Revisions(revisions) => revisions.contains(&revision),
+        }
+    }
+}
+
+/// Reads the `If-Match` header. `None` when it is missing, unless
+/// `REQUIRE_IF_MATCH` is set.
+pub fn if_match(headers: &HeaderMap) -> Result<Option<IfMatch>, VersionError> {
+    let values: Vec<&HeaderValue> = headers.get_all(header::IF_MATCH).iter().collect();
+
+    if values.is_empty() {
+        return match *REQUIRE_IF_MATCH {
+            true => Err(VersionError::Status(StatusCode::PRECONDITION_REQUIRED)),
+            false => Ok(None),
+        };
+    }
+
+    let mut revisions = Vec::new();
+
+    for value in values {
+        let value = match value.to_str() {
+            Ok(res) => res,
+            Err(_) => return Err(VersionError::Status(StatusCode::BAD_REQUEST)),
+        };
+
+        for tag in value.split(',').map(str::trim) {
+            if tag == "*" {
+                return Ok(Some(IfMatch::Any));
+            }
+
+            let revision = tag
+                .strip_prefix('"')
+                .and_then(|tag| tag.strip_suffix('"'))
+                .and_then(|tag| tag.parse().ok());
+
+            if let Some(revision) = revision {
+                revisions.push(revision);
+            }
+        }
+    }
+
+    Ok(Some(IfMatch::Revisions(revisions)))
+}
+
+/// Error of a write guarded by `If-Match`. `Stale` holds the current
+/// revision of the note, sent back with 412 so the client can catch up,
+/// `StaleNotes` the id and current revision of each note a bulk write
+/// refused.
+#[derive(Debug)]
+pub enum VersionError {
+    Status(StatusCode),
+    Stale(u64),
+    StaleNotes(Vec<(String, u64)>),
+}
+
+impl From<StatusCode> for VersionError {
+    fn from(status: StatusCode) -> Self {
+        VersionError::Status(status)
+    }
+}
+
+impl From<Errors> for VersionError {
+    fn from(e: Errors) -> Self {
+        VersionError::Status(e.into())
+    }
+}
+
+impl IntoResponse for VersionError {
+    fn into_response(self) -> Response {
+        match self {
+            VersionError::Status(status) => status.into_response(),
+            VersionError::Stale(revision) => (
+                StatusCode::PRECONDITION_FAILED,
+                etag_header(revision),
+                Json(json!({
+                    "response": "The note was changed in the meantime",
+                    "revision": revision,
+                })),
+            )
+                .into_response(),
+            VersionError::StaleNotes(notes) => {
+                let revisions: Map<String, Value> = notes
+                    .into_iter()
+                    .map(|(note_id, revision)| (note_id, Value::from(revision)))
+                    .collect();
+
+                (
+                    StatusCode::PRECONDITION_FAILED,
+                    Json(json!({
+                        "response": "Some notes were changed in the meantime",
+                        "revisions": revisions,
+                    })),
+                )
+                    .into_response()
+            }
+        }
+    }
+}
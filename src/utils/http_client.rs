@@ -0,0 +1,197 @@
+use std::{sync::Arc, time::Duration};
+
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{body::Bytes, header, Method, Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+    TlsConnector,
+};
+use url::{form_urlencoded, Url};
+
+/// Time a whole request may take, connecting included.
+const TIMEOUT_SECS: u64 = 10;
+
+/// Bigger answers are refused, nothing this client talks to sends that much.
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+static TLS: Lazy<TlsConnector> = Lazy::new(|| {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+    };
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    TlsConnector::from(Arc::new(config))
+});
+
+#[derive(Debug)]
+pub enum HttpError {
+    Url(String),
+    Io(std::io::Error),
+    Hyper(hyper::Error),
+    Request(hyper::http::Error),
+    Body(String),
+    Status(StatusCode, String),
+    Json(serde_json::Error),
+    Timeout,
+}
+
+impl From<std::io::Error> for HttpError {
+    fn from(e: std::io::Error) -> Self {
+        HttpError::Io(e)
+    }
+}
+
+impl From<hyper::Error> for HttpError {
+    fn from(e: hyper::Error) -> Self {
+        HttpError::Hyper(e)
+    }
+}
+
+impl From<hyper::http::Error> for HttpError {
+    fn from(e: hyper::http::Error) -> Self {
+        HttpError::Request(e)
+    }
+}
+
+/// GETs `url` and parses the JSON answer.
+pub async fn get_json<T: DeserializeOwned>(url: &str) -> Result<T, HttpError> {
+    send(Method::GET, url, None).await
+}
+
+/// POSTs `form` urlencoded to `url` and parses the JSON answer.
+pub async fn post_form<T: DeserializeOwned>(
+    url: &str,
+    form: &[(&str, &str)],
+) -> Result<T, HttpError> {
+    let body = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(form)
+        .finish();
+
+    send(Method::POST, url, Some(body)).await
+}
+
+async fn send<T: DeserializeOwned>(
+    method: Method,
+    url: &str,
+    form: Option<String>,
+) -> Result<T, HttpError> {
+    let request = request(method, url, form);
+
+    let (status, body) =
+        match tokio::time::timeout(Duration::from_secs(TIMEOUT_SECS), request).await {
+            Ok(res) => res?,
+            Err(_) => return Err(HttpError::Timeout),
+        };
+
+    if !status.is_success() {
+        return Err(HttpError::Status(
+            status,
+            String::from_utf8_lossy(&body).into_owned(),
+        ));
+    }
+
+    serde_json::from_slice(&body).map_err(HttpError::Json)
+}
+
+async fn request(
+    method: Method,
+    url: &str,
+    form: Option<String>,
+) -> Result<(StatusCode, Bytes), HttpError> {
+    let url = match Url::parse(url) {
+        Ok(res) => res,
+        Err(e) => return Err(HttpError::Url(format!("{}: {}", url, e))),
+    };
+
+    let host = match url.host_str() {
+        Some(res) => res.to_string(),
+        None => return Err(HttpError::Url(format!("{}: no host", url))),
+    };
+
+    // host_str() keeps the brackets around IPv6 addresses, the Host header
+    // wants them but connecting and TLS don't.
+    let address = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+
+    let port = match url.port_or_known_default() {
+        Some(res) => res,
+        None => return Err(HttpError::Url(format!("{}: no port", url))),
+    };
+
+    let target = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+
+    let authority = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.clone(),
+    };
+
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(target)
+        .header(header::HOST, authority)
+        .header(header::ACCEPT, "application/json")
+        .header(header::USER_AGENT, "Note4Keep");
+
+    if form.is_some() {
+        builder = builder.header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+    }
+
+    let req = builder.body(Full::new(Bytes::from(form.unwrap_or_default())))?;
+
+    let stream = TcpStream::connect((address.as_str(), port)).await?;
+
+    match url.scheme() {
+        "http" => exchange(stream, req).await,
+        "https" => {
+            let server_name = match ServerName::try_from(address) {
+                Ok(res) => res,
+                Err(e) => return Err(HttpError::Url(format!("{}: {}", url, e))),
+            };
+
+            exchange(TLS.connect(server_name, stream).await?, req).await
+        }
+        other => Err(HttpError::Url(format!(
+            "{}: unsupported scheme {}",
+            url, other
+        ))),
+    }
+}
+
+async fn exchange<S>(stream: S, req: Request<Full<Bytes>>) -> Result<(StatusCode, Bytes), HttpError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            println!("Error: {:?}", e);
+        }
+    });
+
+    let res = sender.send_request(req).await?;
+    let status = res.status();
+
+    match Limited::new(res.into_body(), MAX_BODY_BYTES)
+        .collect()
+        .await
+    {
+        Ok(body) => Ok((status, body.to_bytes())),
+        Err(e) => Err(HttpError::Body(e.to_string())),
+    }
+}
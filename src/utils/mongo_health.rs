@@ -1,16 +1,13 @@
 const SPECIAL_CHARS: &str = "*/!-,`[";
 
-pub fn mongo_query_error(input: &String) -> bool {
+pub fn mongo_query_error(input: &str) -> bool {
     let mut to_return: bool = false;
 
     for char in SPECIAL_CHARS.chars() {
-        match input.contains(char) {
-            true => {
-                to_return = true;
-                break;
-            }
-            _ => {}
-        };
+        if input.contains(char) {
+            to_return = true;
+            break;
+        }
     }
 
     to_return
@@ -10,11 +10,7 @@ pub fn random_id() -> String {
 
         if random_num == 0 {
             let rando = rand::thread_rng().gen_range(0..WORDS.len());
-            let char = if let Some(chr) = WORDS.chars().nth(rando) {
-                chr
-            } else {
-                'a'
-            };
+            let char = WORDS.chars().nth(rando).unwrap_or('a');
 
             string.push(char)
         } else if random_num == 1 {
@@ -26,3 +22,11 @@ pub fn random_id() -> String {
 
     string
 }
+
+/// 32 random bytes, hex encoded. Use it for secrets handed to clients, the
+/// ids above are not meant to be unguessable.
+pub fn random_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
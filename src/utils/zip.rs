@@ -0,0 +1,279 @@
+use std::{
+    io::{self, Write},
+    mem,
+};
+
+use chrono::prelude::*;
+use crc32fast::Hasher;
+use flate2::{write::DeflateEncoder, Compression};
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const ZIP64_END: u32 = 0x06064b50;
+const ZIP64_LOCATOR: u32 = 0x07064b50;
+const END: u32 = 0x06054b50;
+
+/// Sizes and CRC follow the data, names are UTF-8.
+const FLAGS: u16 = 0x0808;
+const DEFLATE: u16 = 8;
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+struct Entry {
+    name: String,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed: u64,
+    size: u64,
+    offset: u64,
+}
+
+struct Current {
+    entry: Entry,
+    hasher: Hasher,
+    encoder: DeflateEncoder<Vec<u8>>,
+}
+
+/// Writes a zip archive in one pass, so it can be sent while it is being
+/// built: the output piles up until `take_output` hands it over, only the
+/// directory of the archive stays in memory.
+///
+/// Archives may hold more than 65535 entries or grow past 4 GiB, single
+/// entries can't.
+pub struct ZipWriter {
+    out: Vec<u8>,
+    offset: u64,
+    entries: Vec<Entry>,
+    current: Option<Current>,
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        ZipWriter {
+            out: Vec::new(),
+            offset: 0,
+            entries: Vec::new(),
+            current: None,
+        }
+    }
+
+    /// Bytes of the archive written since the last call.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.offset += self.out.len() as u64;
+
+        mem::take(&mut self.out)
+    }
+
+    /// Length of what `take_output` would hand over.
+    pub fn pending(&self) -> usize {
+        self.out.len()
+    }
+
+    /// Starts the entry `name`, ending the previous one.
+    pub fn start_file(&mut self, name: &str, modified: DateTime<Utc>) -> io::Result<()> {
+        self.finish_file()?;
+
+        let (time, date) = dos_date_time(modified);
+
+        let entry = Entry {
+            name: name.to_string(),
+            time,
+            date,
+            crc: 0,
+            compressed: 0,
+            size: 0,
+            offset: self.position(),
+        };
+
+        self.put_u32(LOCAL_HEADER);
+        self.put_u16(VERSION);
+        self.put_u16(FLAGS);
+        self.put_u16(DEFLATE);
+        self.put_u16(time);
+        self.put_u16(date);
+        // CRC and sizes, in the data descriptor instead.
+        self.put_u32(0);
+        self.put_u32(0);
+        self.put_u32(0);
+        self.put_u16(entry.name.len() as u16);
+        self.put_u16(0);
+        self.out.extend_from_slice(entry.name.as_bytes());
+
+        self.current = Some(Current {
+            entry,
+            hasher: Hasher::new(),
+            encoder: DeflateEncoder::new(Vec::new(), Compression::default()),
+        });
+
+        Ok(())
+    }
+
+    /// Appends `data` to the current entry.
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let current = match self.current.as_mut() {
+            Some(res) => res,
+            None => return Err(io::Error::other("no entry started")),
+        };
+
+        current.hasher.update(data);
+        current.entry.size += data.len() as u64;
+        current.encoder.write_all(data)?;
+
+        let compressed = mem::take(current.encoder.get_mut());
+        current.entry.compressed += compressed.len() as u64;
+        self.out.extend_from_slice(&compressed);
+
+        Ok(())
+    }
+
+    fn finish_file(&mut self) -> io::Result<()> {
+        let Current {
+            mut entry,
+            hasher,
+            encoder,
+        } = match self.current.take() {
+            Some(res) => res,
+            None => return Ok(()),
+        };
+
+        let compressed = encoder.finish()?;
+        entry.compressed += compressed.len() as u64;
+        entry.crc = hasher.finalize();
+        self.out.extend_from_slice(&compressed);
+
+        if entry.size > u32::MAX as u64 || entry.compressed > u32::MAX as u64 {
+            return Err(io::Error::other(format!(
+                "{} is too big for a zip entry",
+                entry.name
+            )));
+        }
+
+        self.put_u32(DATA_DESCRIPTOR);
+        self.put_u32(entry.crc);
+        self.put_u32(entry.compressed as u32);
+        self.put_u32(entry.size as u32);
+
+        self.entries.push(entry);
+
+        Ok(())
+    }
+
+    /// Ends the last entry and writes the directory of the archive.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.finish_file()?;
+
+        let directory_offset = self.position();
+        let entries = mem::take(&mut self.entries);
+
+        for entry in &entries {
+            let zip64 = entry.offset >= u32::MAX as u64;
+
+            self.put_u32(CENTRAL_HEADER);
+            self.put_u16(if zip64 { VERSION_ZIP64 } else { VERSION });
+            self.put_u16(if zip64 { VERSION_ZIP64 } else { VERSION });
+            self.put_u16(FLAGS);
+            self.put_u16(DEFLATE);
+            self.put_u16(entry.time);
+            self.put_u16(entry.date);
+            self.put_u32(entry.crc);
+            self.put_u32(entry.compressed as u32);
+            self.put_u32(entry.size as u32);
+            self.put_u16(entry.name.len() as u16);
+            self.put_u16(if zip64 { 12 } else { 0 });
+            // Comment, disk, internal and external attributes.
+            self.put_u16(0);
+            self.put_u16(0);
+            self.put_u16(0);
+            self.put_u32(0);
+            self.put_u32(if zip64 { u32::MAX } else { entry.offset as u32 });
+            self.out.extend_from_slice(entry.name.as_bytes());
+
+            if zip64 {
+                self.put_u16(0x0001);
+                self.put_u16(8);
+                self.put_u64(entry.offset);
+            }
+        }
+
+        let directory_size = self.position() - directory_offset;
+        let count = entries.len() as u64;
+
+        let zip64 = count >= u16::MAX as u64
+            || directory_size >= u32::MAX as u64
+            || directory_offset >= u32::MAX as u64;
+
+        if zip64 {
+            let end_offset = self.position();
+
+            self.put_u32(ZIP64_END);
+            self.put_u64(44);
+            self.put_u16(VERSION_ZIP64);
+            self.put_u16(VERSION_ZIP64);
+            self.put_u32(0);
+            self.put_u32(0);
+            self.put_u64(count);
+            self.put_u64(count);
+            self.put_u64(directory_size);
+            self.put_u64(directory_offset);
+
+            self.put_u32(ZIP64_LOCATOR);
+            self.put_u32(0);
+            self.put_u64(end_offset);
+            self.put_u32(1);
+        }
+
+        self.put_u32(END);
+        self.put_u16(0);
+        self.put_u16(0);
+        self.put_u16(count.min(u16::MAX as u64) as u16);
+        self.put_u16(count.min(u16::MAX as u64) as u16);
+        self.put_u32(directory_size.min(u32::MAX as u64) as u32);
+        self.put_u32(directory_offset.min(u32::MAX as u64) as u32);
+        self.put_u16(0);
+
+        Ok(())
+    }
+
+    fn position(&self) -> u64 {
+        self.offset + self.out.len() as u64
+    }
+
+    fn put_u16(&mut self, value: u16) {
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(&mut self, value: u32) {
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u64(&mut self, value: u64) {
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+impl Default for ZipWriter {
+    fn default() -> Self {
+        ZipWriter::new()
+    }
+}
+
+/// MS-DOS time and date, which only cover 1980 to 2107.
+fn dos_date_time(date: DateTime<Utc>) -> (u16, u16) {
+    if date.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+
+    if date.year() > 2107 {
+        return (0xbf7d, 0xff9f);
+    }
+
+    let time = (date.hour() << 11) | (date.minute() << 5) | (date.second() / 2);
+    let day = ((date.year() as u32 - 1980) << 9) | (date.month() << 5) | date.day();
+
+    (time as u16, day as u16)
+}
+
+#[cfg(test)]
+mod tests;
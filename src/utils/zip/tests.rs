@@ -0,0 +1,263 @@
+//! Archives written by `ZipWriter`, read back with a reader that checks
+//! every local header and data descriptor against the directory.
+
+use std::io::Read;
+
+use chrono::prelude::*;
+use crc32fast::hash;
+use flate2::read::DeflateDecoder;
+use rand::RngCore;
+
+use super::*;
+
+struct ReadEntry {
+    name: String,
+    time: u16,
+    date: u16,
+    data: Vec<u8>,
+}
+
+fn u16_at(archive: &[u8], position: usize) -> u16 {
+    u16::from_le_bytes(archive[position..position + 2].try_into().unwrap())
+}
+
+fn u32_at(archive: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(archive[position..position + 4].try_into().unwrap())
+}
+
+fn u64_at(archive: &[u8], position: usize) -> u64 {
+    u64::from_le_bytes(archive[position..position + 8].try_into().unwrap())
+}
+
+/// Reads back `archive`, whose first byte is at `base` in the whole
+/// archive, so the tail of a huge one can be checked.
+fn read_zip(archive: &[u8], base: u64) -> Vec<ReadEntry> {
+    let at = |position: u64| (position - base) as usize;
+
+    let end = archive.len() - 22;
+    assert_eq!(u32_at(archive, end), END);
+
+    let mut count = u16_at(archive, end + 10) as u64;
+    let mut directory_size = u32_at(archive, end + 12) as u64;
+    let mut directory_offset = u32_at(archive, end + 16) as u64;
+    assert_eq!(u16_at(archive, end + 8) as u64, count);
+
+    if count == u16::MAX as u64
+        || directory_size == u32::MAX as u64
+        || directory_offset == u32::MAX as u64
+    {
+        let locator = end - 20;
+        assert_eq!(u32_at(archive, locator), ZIP64_LOCATOR);
+
+        let zip64_end = at(u64_at(archive, locator + 8));
+        assert_eq!(zip64_end + 56, locator);
+        assert_eq!(u32_at(archive, zip64_end), ZIP64_END);
+        assert_eq!(
+            u64_at(archive, zip64_end + 24),
+            u64_at(archive, zip64_end + 32)
+        );
+
+        count = u64_at(archive, zip64_end + 32);
+        directory_size = u64_at(archive, zip64_end + 40);
+        directory_offset = u64_at(archive, zip64_end + 48);
+    }
+
+    let mut entries = Vec::new();
+    let mut position = at(directory_offset);
+
+    for _ in 0..count {
+        assert_eq!(u32_at(archive, position), CENTRAL_HEADER);
+        assert_eq!(u16_at(archive, position + 8), FLAGS);
+        assert_eq!(u16_at(archive, position + 10), DEFLATE);
+
+        let time = u16_at(archive, position + 12);
+        let date = u16_at(archive, position + 14);
+        let crc = u32_at(archive, position + 16);
+        let mut compressed = u32_at(archive, position + 20) as u64;
+        let mut size = u32_at(archive, position + 24) as u64;
+        let name_len = u16_at(archive, position + 28) as usize;
+        let extra_len = u16_at(archive, position + 30) as usize;
+        let mut offset = u32_at(archive, position + 42) as u64;
+
+        let name = String::from_utf8(archive[position + 46..][..name_len].to_vec()).unwrap();
+
+        // The zip64 extra field holds, in this order, the values left at
+        // u32::MAX in the header.
+        let mut extra = position + 46 + name_len;
+
+        while extra < position + 46 + name_len + extra_len {
+            let id = u16_at(archive, extra);
+            let len = u16_at(archive, extra + 2) as usize;
+
+            if id == 0x0001 {
+                let mut field = extra + 4;
+
+                for value in [&mut size, &mut compressed, &mut offset] {
+                    if *value == u32::MAX as u64 {
+                        *value = u64_at(archive, field);
+                        field += 8;
+                    }
+                }
+
+                assert_eq!(field, extra + 4 + len);
+            }
+
+            extra += 4 + len;
+        }
+
+        position = extra;
+
+        let local = at(offset);
+        assert_eq!(u32_at(archive, local), LOCAL_HEADER);
+        assert_eq!(u16_at(archive, local + 6), FLAGS);
+        assert_eq!(u16_at(archive, local + 10), time);
+        assert_eq!(u16_at(archive, local + 12), date);
+        assert_eq!(u16_at(archive, local + 26) as usize, name_len);
+        assert_eq!(&archive[local + 30..][..name_len], name.as_bytes());
+
+        let start = local + 30 + name_len + u16_at(archive, local + 28) as usize;
+        let stored = &archive[start..][..compressed as usize];
+
+        let mut data = Vec::new();
+        DeflateDecoder::new(stored).read_to_end(&mut data).unwrap();
+        assert_eq!(data.len() as u64, size);
+        assert_eq!(hash(&data), crc);
+
+        let descriptor = start + compressed as usize;
+        assert_eq!(u32_at(archive, descriptor), DATA_DESCRIPTOR);
+        assert_eq!(u32_at(archive, descriptor + 4), crc);
+        assert_eq!(u32_at(archive, descriptor + 8) as u64, compressed);
+        assert_eq!(u32_at(archive, descriptor + 12) as u64, size);
+
+        entries.push(ReadEntry {
+            name,
+            time,
+            date,
+            data,
+        });
+    }
+
+    assert_eq!(position, at(directory_offset + directory_size));
+
+    entries
+}
+
+/// Writes `files` the way the export does, handing the output over as it
+/// piles up.
+fn write_zip(zip: &mut ZipWriter, files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let modified = Utc.with_ymd_and_hms(2024, 3, 9, 17, 45, 58).unwrap();
+    let mut archive = Vec::new();
+
+    for (name, data) in files {
+        zip.start_file(name, modified).unwrap();
+
+        for chunk in data.chunks(64 * 1024) {
+            zip.write(chunk).unwrap();
+
+            if zip.pending() > 256 * 1024 {
+                archive.extend(zip.take_output());
+            }
+        }
+    }
+
+    zip.finish().unwrap();
+    archive.extend(zip.take_output());
+
+    archive
+}
+
+#[test]
+fn empty_archive() {
+    let archive = write_zip(&mut ZipWriter::new(), &[]);
+
+    assert_eq!(archive.len(), 22);
+    assert!(read_zip(&archive, 0).is_empty());
+}
+
+#[test]
+fn round_trip() {
+    let mut large = vec![0; 5 * 1024 * 1024];
+    rand::thread_rng().fill_bytes(&mut large);
+
+    let files = [
+        ("profile.json", b"{\"username\": \"alice\"}".to_vec()),
+        ("notes/empty.md", Vec::new()),
+        (
+            "notes/café ☕ ノート.md",
+            "# Crème brûlée\n".repeat(1000).into(),
+        ),
+        ("notes/large.bin", large),
+    ];
+
+    let archive = write_zip(&mut ZipWriter::new(), &files);
+    let entries = read_zip(&archive, 0);
+
+    assert_eq!(entries.len(), files.len());
+
+    for (entry, (name, data)) in entries.iter().zip(&files) {
+        assert_eq!(entry.name, *name);
+        assert_eq!(entry.data, *data);
+        assert_eq!(
+            (entry.time, entry.date),
+            dos_date_time(Utc.with_ymd_and_hms(2024, 3, 9, 17, 45, 58).unwrap())
+        );
+    }
+}
+
+#[test]
+fn many_entries_use_zip64() {
+    let names: Vec<String> = (0..70_000).map(|i| format!("notes/{}.md", i)).collect();
+    let files: Vec<(&str, Vec<u8>)> = names
+        .iter()
+        .map(|name| (name.as_str(), name.as_bytes().to_vec()))
+        .collect();
+
+    let archive = write_zip(&mut ZipWriter::new(), &files);
+    let entries = read_zip(&archive, 0);
+
+    assert_eq!(u16_at(&archive, archive.len() - 12), u16::MAX);
+    assert_eq!(entries.len(), files.len());
+    assert_eq!(entries[69_999].name, "notes/69999.md");
+    assert_eq!(entries[69_999].data, b"notes/69999.md");
+}
+
+#[test]
+fn offsets_past_4_gib_use_zip64() {
+    // As if 5 GiB had already been handed over by take_output.
+    let base = 5 << 30;
+
+    let mut zip = ZipWriter::new();
+    zip.offset = base;
+
+    let files = [
+        ("notes/a.md", b"first".to_vec()),
+        ("notes/b.md", b"second".to_vec()),
+    ];
+
+    let archive = write_zip(&mut zip, &files);
+    let entries = read_zip(&archive, base);
+
+    assert_eq!(u32_at(&archive, archive.len() - 6), u32::MAX);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1].name, "notes/b.md");
+    assert_eq!(entries[1].data, b"second");
+}
+
+#[test]
+fn write_needs_an_entry() {
+    let mut zip = ZipWriter::new();
+
+    assert!(zip.write(b"data").is_err());
+}
+
+#[test]
+fn dos_dates_are_clamped() {
+    let date = |y, mo, d, h, mi, s| Utc.with_ymd_and_hms(y, mo, d, h, mi, s).unwrap();
+
+    assert_eq!(dos_date_time(date(1970, 1, 1, 0, 0, 0)), (0, (1 << 5) | 1));
+    assert_eq!(dos_date_time(date(2200, 1, 1, 0, 0, 0)), (0xbf7d, 0xff9f));
+    assert_eq!(
+        dos_date_time(date(2024, 3, 9, 17, 45, 58)),
+        ((17 << 11) | (45 << 5) | 29, (44 << 9) | (3 << 5) | 9)
+    );
+}